@@ -0,0 +1,314 @@
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use anyhow::{Result, anyhow};
+
+use crate::message::{encode_name, is_subdomain, lowercase_name, names_equal};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.network, addr) {
+            (IpAddr::V4(network), IpAddr::V4(addr)) => {
+                prefix_matches(&network.octets(), &addr.octets(), self.prefix)
+            }
+            (IpAddr::V6(network), IpAddr::V6(addr)) => {
+                prefix_matches(&network.octets(), &addr.octets(), self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn prefix_matches(network: &[u8], addr: &[u8], prefix: u8) -> bool {
+    let full = (prefix / 8) as usize;
+    if network[..full] != addr[..full] {
+        return false;
+    }
+    let rest = prefix % 8;
+    if rest == 0 {
+        return true;
+    }
+    let mask = 0xFFu8 << (8 - rest);
+    network[full] & mask == addr[full] & mask
+}
+
+impl FromStr for Cidr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Cidr> {
+        let (network, prefix) = match s.split_once('/') {
+            Some((network, prefix)) => (network, Some(prefix)),
+            None => (s, None),
+        };
+        let network: IpAddr = network
+            .parse()
+            .map_err(|_| anyhow!("Invalid network address in {}", s))?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|prefix| *prefix <= max)
+                .ok_or_else(|| anyhow!("Invalid prefix length in {}", s))?,
+            None => max,
+        };
+        Ok(Cidr { network, prefix })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AclEntry {
+    Network(Cidr),
+    // Requests signed with this TSIG key, wherever they come from.
+    Key(Vec<u8>),
+}
+
+impl FromStr for AclEntry {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<AclEntry> {
+        match s.strip_prefix("key:") {
+            Some(key) => Ok(AclEntry::Key(lowercase_name(&encode_name(key)))),
+            None => Ok(AclEntry::Network(s.parse()?)),
+        }
+    }
+}
+
+/// A list of networks and keys permitted to perform some operation. An
+/// empty list permits nobody.
+#[derive(Debug, Clone, Default)]
+pub struct Acl {
+    entries: Vec<AclEntry>,
+}
+
+impl Acl {
+    pub fn new() -> Acl {
+        Acl { entries: vec![] }
+    }
+
+    pub fn push(&mut self, entry: AclEntry) {
+        self.entries.push(entry);
+    }
+
+    /// `key` is the name of the TSIG key that validly signed the request.
+    pub fn allows(&self, addr: IpAddr, key: Option<&[u8]>) -> bool {
+        self.entries.iter().any(|entry| match entry {
+            AclEntry::Network(network) => network.contains(addr),
+            AclEntry::Key(name) => key.is_some_and(|key| names_equal(key, name)),
+        })
+    }
+}
+
+/// Kinds of request an ACL can restrict.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operation {
+    Query,
+    // Answers from the cache or an upstream resolver rather than local data.
+    Recursion,
+    Transfer,
+    Update,
+}
+
+/// An ACL that applies to names at or below `origin` in place of the
+/// server-wide one.
+#[derive(Debug, Clone)]
+pub struct ZoneAcl {
+    pub origin: Vec<u8>,
+    pub operation: Operation,
+    pub acl: Acl,
+}
+
+/// Who may perform each operation. Queries and recursion are open to
+/// everyone until restricted; transfers and updates need permission.
+#[derive(Debug, Clone, Default)]
+pub struct AccessControl {
+    pub query: Option<Acl>,
+    pub recursion: Option<Acl>,
+    pub transfer: Option<Acl>,
+    pub update: Option<Acl>,
+    pub zones: Vec<ZoneAcl>,
+}
+
+impl AccessControl {
+    fn acl(&self, operation: Operation) -> Option<&Acl> {
+        match operation {
+            Operation::Query => self.query.as_ref(),
+            Operation::Recursion => self.recursion.as_ref(),
+            Operation::Transfer => self.transfer.as_ref(),
+            Operation::Update => self.update.as_ref(),
+        }
+    }
+
+    /// Adds `entry` to the ACL for `operation`, or for `operation` within
+    /// zone `origin` when given.
+    pub fn push(&mut self, operation: Operation, origin: Option<Vec<u8>>, entry: AclEntry) {
+        let acl = match origin {
+            Some(origin) => {
+                let existing = self
+                    .zones
+                    .iter()
+                    .position(|zone| zone.operation == operation && names_equal(&zone.origin, &origin));
+                let index = existing.unwrap_or_else(|| {
+                    self.zones.push(ZoneAcl { origin, operation, acl: Acl::new() });
+                    self.zones.len() - 1
+                });
+                &mut self.zones[index].acl
+            }
+            None => match operation {
+                Operation::Query => self.query.get_or_insert_with(Acl::new),
+                Operation::Recursion => self.recursion.get_or_insert_with(Acl::new),
+                Operation::Transfer => self.transfer.get_or_insert_with(Acl::new),
+                Operation::Update => self.update.get_or_insert_with(Acl::new),
+            },
+        };
+        acl.push(entry);
+    }
+
+    /// Whether `addr`, signing with `key`, may perform `operation` on
+    /// `name`. The ACL of the closest zone enclosing `name` wins over the
+    /// server-wide one.
+    pub fn allows(&self, operation: Operation, name: &[u8], addr: IpAddr, key: Option<&[u8]>) -> bool {
+        let zone_acl = self
+            .zones
+            .iter()
+            .filter(|zone| zone.operation == operation && is_subdomain(name, &zone.origin))
+            .max_by_key(|zone| zone.origin.len())
+            .map(|zone| &zone.acl);
+        match zone_acl.or(self.acl(operation)) {
+            Some(acl) => acl.allows(addr, key),
+            None => matches!(operation, Operation::Query | Operation::Recursion),
+        }
+    }
+
+    /// The ACLs for clients of a view that sets `view`: its own where it
+    /// has them, the server-wide ones otherwise. The same goes for zone
+    /// ACLs, which the view overrides per zone and operation.
+    pub fn inherit(&self, view: &AccessControl) -> AccessControl {
+        let mut zones = view.zones.clone();
+        for zone in &self.zones {
+            let overridden = view.zones
+                .iter()
+                .any(|own| own.operation == zone.operation && names_equal(&own.origin, &zone.origin));
+            if !overridden {
+                zones.push(zone.clone());
+            }
+        }
+        AccessControl {
+            query: view.query.clone().or_else(|| self.query.clone()),
+            recursion: view.recursion.clone().or_else(|| self.recursion.clone()),
+            transfer: view.transfer.clone().or_else(|| self.transfer.clone()),
+            update: view.update.clone().or_else(|| self.update.clone()),
+            zones,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidr(text: &str) -> Cidr {
+        text.parse().unwrap()
+    }
+
+    fn addr(text: &str) -> IpAddr {
+        text.parse().unwrap()
+    }
+
+    fn allows(access: &AccessControl, operation: Operation, name: &str, client: &str, key: Option<&str>) -> bool {
+        let key = key.map(encode_name);
+        access.allows(operation, &encode_name(name), addr(client), key.as_deref())
+    }
+
+    #[test]
+    fn matches_networks() {
+        assert!(cidr("10.0.0.0/8").contains(addr("10.255.1.2")));
+        assert!(!cidr("10.0.0.0/8").contains(addr("11.0.0.1")));
+        assert!(cidr("192.168.1.0/25").contains(addr("192.168.1.127")));
+        assert!(!cidr("192.168.1.0/25").contains(addr("192.168.1.128")));
+        // Host bits in the network are ignored.
+        assert!(cidr("10.1.2.3/8").contains(addr("10.9.9.9")));
+        assert!(cidr("0.0.0.0/0").contains(addr("203.0.113.9")));
+        assert!(cidr("::/0").contains(addr("2001:db8::1")));
+        assert!(cidr("10.0.0.1/32").contains(addr("10.0.0.1")));
+        assert!(!cidr("10.0.0.1/32").contains(addr("10.0.0.2")));
+        assert!(cidr("2001:db8::/33").contains(addr("2001:db8:7fff::1")));
+        assert!(!cidr("2001:db8::/33").contains(addr("2001:db8:8000::1")));
+        assert!(cidr("::1/128").contains(addr("::1")));
+        // Families never match each other, even for mapped addresses.
+        assert!(!cidr("0.0.0.0/0").contains(addr("::ffff:10.0.0.1")));
+        assert!(!cidr("::/0").contains(addr("10.0.0.1")));
+    }
+
+    #[test]
+    fn parses_networks() {
+        assert_eq!(cidr("10.0.0.1").prefix(), 32);
+        assert_eq!(cidr("::1").prefix(), 128);
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("::/129".parse::<Cidr>().is_err());
+        assert!("10.0.0/8".parse::<Cidr>().is_err());
+        assert!("10.0.0.0/x".parse::<Cidr>().is_err());
+        assert_eq!("key:Update-Key.".parse::<AclEntry>().unwrap(), AclEntry::Key(encode_name("update-key")));
+    }
+
+    #[test]
+    fn defaults_open_queries_only() {
+        let access = AccessControl::default();
+        assert!(allows(&access, Operation::Query, "example.com", "203.0.113.1", None));
+        assert!(allows(&access, Operation::Recursion, "example.com", "203.0.113.1", None));
+        assert!(!allows(&access, Operation::Transfer, "example.com", "127.0.0.1", None));
+        assert!(!allows(&access, Operation::Update, "example.com", "127.0.0.1", None));
+    }
+
+    #[test]
+    fn zone_acls_win_over_server_wide_ones() {
+        let mut access = AccessControl::default();
+        access.push(Operation::Query, None, "10.0.0.0/8".parse().unwrap());
+        access.push(Operation::Query, Some(encode_name("public.example")), "0.0.0.0/0".parse().unwrap());
+        access.push(Operation::Query, Some(encode_name("secret.public.example")), "10.1.0.0/16".parse().unwrap());
+        access.push(Operation::Transfer, Some(encode_name("example.com")), "key:xfr".parse().unwrap());
+
+        assert!(!allows(&access, Operation::Query, "other.example", "203.0.113.1", None));
+        assert!(allows(&access, Operation::Query, "other.example", "10.2.0.1", None));
+        assert!(allows(&access, Operation::Query, "WWW.public.example", "203.0.113.1", None));
+        assert!(!allows(&access, Operation::Query, "a.secret.public.example", "10.2.0.1", None));
+        assert!(allows(&access, Operation::Query, "a.secret.public.example", "10.1.0.1", None));
+        // Zone ACLs only restrict their own operation.
+        assert!(allows(&access, Operation::Recursion, "other.example", "203.0.113.1", None));
+        assert!(allows(&access, Operation::Transfer, "example.com", "203.0.113.1", Some("XFR")));
+        assert!(!allows(&access, Operation::Transfer, "example.com", "203.0.113.1", Some("other")));
+        assert!(!allows(&access, Operation::Transfer, "example.com", "203.0.113.1", None));
+        assert!(!allows(&access, Operation::Transfer, "example.org", "203.0.113.1", Some("xfr")));
+    }
+
+    #[test]
+    fn views_inherit_what_they_do_not_set() {
+        let mut server = AccessControl::default();
+        server.push(Operation::Query, None, "10.0.0.0/8".parse().unwrap());
+        server.push(Operation::Transfer, None, "127.0.0.1".parse().unwrap());
+        server.push(Operation::Query, Some(encode_name("private.example")), "10.1.0.0/16".parse().unwrap());
+        server.push(Operation::Query, Some(encode_name("lab.example")), "10.2.0.0/16".parse().unwrap());
+        let mut view = AccessControl::default();
+        view.push(Operation::Query, None, "0.0.0.0/0".parse().unwrap());
+        view.push(Operation::Query, Some(encode_name("lab.example")), "10.3.0.0/16".parse().unwrap());
+
+        let access = server.inherit(&view);
+        assert!(allows(&access, Operation::Query, "example.com", "203.0.113.1", None));
+        assert!(allows(&access, Operation::Transfer, "example.com", "127.0.0.1", None));
+        assert!(!allows(&access, Operation::Transfer, "example.com", "10.0.0.1", None));
+        assert!(!allows(&access, Operation::Query, "www.private.example", "203.0.113.1", None));
+        assert!(allows(&access, Operation::Query, "www.private.example", "10.1.0.1", None));
+        assert!(allows(&access, Operation::Query, "www.lab.example", "10.3.0.1", None));
+        assert!(!allows(&access, Operation::Query, "www.lab.example", "10.2.0.1", None));
+    }
+}
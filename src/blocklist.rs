@@ -0,0 +1,305 @@
+use std::collections::HashSet;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use anyhow::{Result, anyhow};
+
+use crate::message::*;
+use crate::zone::Lookup;
+
+const BLOCKED_TTL: u32 = 60;
+// Names hosts-format lists map to loopback for the local machine itself
+// rather than to block them.
+const LOCAL_NAMES: &[&str] = &["localhost", "localhost.localdomain", "local", "broadcasthost"];
+
+/// How blocked names are answered.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum BlockAction {
+    #[default]
+    NxDomain,
+    // 0.0.0.0 for A queries and :: for AAAA.
+    Null,
+    // Addresses of a sinkhole host; queries for a family without one get
+    // NODATA.
+    Sinkhole(Vec<IpAddr>),
+}
+
+impl FromStr for BlockAction {
+    type Err = anyhow::Error;
+
+    /// Parses `nxdomain`, `null` or a comma separated list of sinkhole
+    /// addresses.
+    fn from_str(s: &str) -> Result<BlockAction> {
+        match s {
+            "nxdomain" => Ok(BlockAction::NxDomain),
+            "null" => Ok(BlockAction::Null),
+            _ => s
+                .split(',')
+                .map(|address| address.trim().parse().map_err(|_| anyhow!("Invalid block action {}", s)))
+                .collect::<Result<_>>()
+                .map(BlockAction::Sinkhole),
+        }
+    }
+}
+
+/// A set of domains, each matching itself and every name below it.
+#[derive(Debug, Default)]
+pub struct DomainSet {
+    domains: HashSet<Vec<u8>>,
+}
+
+impl DomainSet {
+    pub fn insert(&mut self, name: &[u8]) {
+        self.domains.insert(lowercase_name(name));
+    }
+
+    pub fn len(&self) -> usize {
+        self.domains.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.domains.is_empty()
+    }
+
+    /// Whether `name` or one of its ancestors is in the set.
+    pub fn matches(&self, name: &[u8]) -> bool {
+        let mut name = lowercase_name(name);
+        loop {
+            if self.domains.contains(&name) {
+                return true;
+            }
+            match parent_name(&name) {
+                Some(parent) => name = parent,
+                None => return false,
+            }
+        }
+    }
+}
+
+/// Domains to block, read from plain lists of names, hosts-format files
+/// and adblock-style `||domain^` rules, with allowlisted exceptions.
+#[derive(Debug, Default)]
+pub struct Blocklist {
+    blocked: DomainSet,
+    allowed: DomainSet,
+    action: BlockAction,
+}
+
+impl Blocklist {
+    pub fn load(blocklist_files: &[String], allowlist_files: &[String], action: BlockAction) -> Result<Blocklist> {
+        let mut blocklist = Blocklist { action, ..Blocklist::default() };
+        for path in blocklist_files {
+            let text = fs::read_to_string(path)?;
+            blocklist.parse(&text, false);
+        }
+        for path in allowlist_files {
+            let text = fs::read_to_string(path)?;
+            blocklist.parse(&text, true);
+        }
+        Ok(blocklist)
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocked.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocked.is_empty()
+    }
+
+    /// Adds the domains of each line to the blocked set, or to the allowed
+    /// set for allowlists and `@@` exception rules. Lines in no format we
+    /// understand, such as adblock rules for paths or page elements, are
+    /// skipped.
+    fn parse(&mut self, text: &str, allowlist: bool) {
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() || line.starts_with('!') || line.starts_with('[') {
+                continue;
+            }
+            let (line, allowed) = match line.strip_prefix("@@") {
+                Some(rule) => (rule, true),
+                None => (line, allowlist),
+            };
+            let set = if allowed { &mut self.allowed } else { &mut self.blocked };
+
+            if let Some(rule) = line.strip_prefix("||") {
+                // Rules with modifiers only apply in a browser context.
+                if let Some(domain) = rule.strip_suffix('^').filter(|domain| is_domain(domain)) {
+                    set.insert(&encode_name(domain));
+                }
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let first = fields.next().unwrap_or_default();
+            if first.parse::<IpAddr>().is_ok() {
+                for domain in fields.filter(|domain| is_domain(domain) && !LOCAL_NAMES.contains(domain)) {
+                    set.insert(&encode_name(domain));
+                }
+            } else if let Some(domain) = Some(first.trim_start_matches("*.")).filter(|domain| is_domain(domain)) {
+                set.insert(&encode_name(domain));
+            }
+        }
+    }
+
+    pub fn is_blocked(&self, name: &[u8]) -> bool {
+        self.blocked.matches(name) && !self.allowed.matches(name)
+    }
+
+    /// The answer for a blocked name. `None` when `name` is not blocked.
+    pub fn lookup(&self, name: &[u8], qtype: u16) -> Option<Lookup> {
+        if !self.is_blocked(name) {
+            return None;
+        }
+        let addresses = match &self.action {
+            BlockAction::NxDomain => return Some(Lookup::NxDomain),
+            BlockAction::Null => vec![IpAddr::V4(Ipv4Addr::UNSPECIFIED), IpAddr::V6(Ipv6Addr::UNSPECIFIED)],
+            BlockAction::Sinkhole(addresses) => addresses.clone(),
+        };
+        let records: Vec<Answer> = addresses
+            .into_iter()
+            .filter_map(|address| {
+                let (address_type, rdata) = match address {
+                    IpAddr::V4(address) => (rtype::A, RData::A(address)),
+                    IpAddr::V6(address) => (rtype::AAAA, RData::Aaaa(address)),
+                };
+                (qtype == address_type || qtype == rtype::ANY).then(|| blocked_record(name, address_type, &rdata))
+            })
+            .collect();
+        Some(if records.is_empty() { Lookup::NoData } else { Lookup::Records(records) })
+    }
+}
+
+fn blocked_record(name: &[u8], qtype: u16, rdata: &RData) -> Answer {
+    Answer::builder()
+        .name_bytes(name.to_vec())
+        .unwrap()
+        .qtype(qtype)
+        .unwrap()
+        .ttl(BLOCKED_TTL)
+        .unwrap()
+        .rdata(rdata)
+        .unwrap()
+        .build()
+}
+
+/// Whether `domain` is a plain domain name: dot-separated labels of
+/// letters, digits, hyphens and underscores, optionally fully qualified.
+pub(crate) fn is_domain(domain: &str) -> bool {
+    let domain = domain.strip_suffix('.').unwrap_or(domain);
+    !domain.is_empty()
+        && domain.len() <= 253
+        && domain.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && label.bytes().all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_')
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocklist(blocked: &str, allowed: &str, action: BlockAction) -> Blocklist {
+        let mut blocklist = Blocklist { action, ..Blocklist::default() };
+        blocklist.parse(blocked, false);
+        blocklist.parse(allowed, true);
+        blocklist
+    }
+
+    fn blocked(blocklist: &Blocklist, name: &str) -> bool {
+        blocklist.is_blocked(&encode_name(name))
+    }
+
+    #[test]
+    fn parses_all_three_formats() {
+        let text = concat!(
+            "# hosts format\n",
+            "0.0.0.0 ads.example tracker.example # trailing\n",
+            "127.0.0.1 localhost\n",
+            "! adblock format\n",
+            "[Adblock Plus 2.0]\n",
+            "||Metrics.Example^\n",
+            "||script.example^$third-party\n",
+            "||page.example/banner.png\n",
+            "# plain format\n",
+            "plain.example\n",
+            "*.wild.example\n",
+            "not/a domain\n",
+        );
+        let list = blocklist(text, "", BlockAction::NxDomain);
+
+        assert_eq!(list.len(), 5);
+        assert!(blocked(&list, "ads.example"));
+        assert!(blocked(&list, "tracker.example"));
+        assert!(blocked(&list, "metrics.example"));
+        assert!(blocked(&list, "plain.example"));
+        assert!(blocked(&list, "wild.example"));
+        // Every name below a blocked domain is blocked too.
+        assert!(blocked(&list, "cdn.ADS.example"));
+        assert!(!blocked(&list, "example"));
+        assert!(!blocked(&list, "localhost"));
+        assert!(!blocked(&list, "script.example"));
+        assert!(!blocked(&list, "page.example"));
+    }
+
+    #[test]
+    fn allowlists_take_precedence() {
+        let list = blocklist(
+            "ads.example\n0.0.0.0 tracker.example\n||metrics.example^\n@@||ok.metrics.example^\n",
+            "good.ads.example\ntracker.example\n",
+            BlockAction::NxDomain,
+        );
+        assert!(blocked(&list, "ads.example"));
+        assert!(blocked(&list, "bad.ads.example"));
+        assert!(!blocked(&list, "good.ads.example"));
+        assert!(!blocked(&list, "www.good.ads.example"));
+        assert!(!blocked(&list, "tracker.example"));
+        assert!(blocked(&list, "metrics.example"));
+        assert!(!blocked(&list, "ok.metrics.example"));
+    }
+
+    #[test]
+    fn answers_with_the_block_action() {
+        let name = encode_name("ads.example");
+        let list = blocklist("ads.example\n", "", BlockAction::NxDomain);
+        assert!(matches!(list.lookup(&name, rtype::A), Some(Lookup::NxDomain)));
+        assert!(list.lookup(&encode_name("other.example"), rtype::A).is_none());
+
+        let list = blocklist("ads.example\n", "", BlockAction::Null);
+        match list.lookup(&name, rtype::AAAA) {
+            Some(Lookup::Records(records)) => {
+                assert_eq!(records.len(), 1);
+                assert_eq!(records[0].rdata().unwrap(), RData::Aaaa(Ipv6Addr::UNSPECIFIED));
+            }
+            other => panic!("unexpected lookup {:?}", other),
+        }
+
+        let list = blocklist("ads.example\n", "", "10.0.0.1".parse().unwrap());
+        assert!(matches!(list.lookup(&name, rtype::A), Some(Lookup::Records(records)) if records.len() == 1));
+        assert!(matches!(list.lookup(&name, rtype::AAAA), Some(Lookup::NoData)));
+    }
+
+    #[test]
+    fn parses_block_actions() {
+        assert_eq!("null".parse::<BlockAction>().unwrap(), BlockAction::Null);
+        assert_eq!(
+            "10.0.0.1, ::1".parse::<BlockAction>().unwrap(),
+            BlockAction::Sinkhole(vec!["10.0.0.1".parse().unwrap(), "::1".parse().unwrap()])
+        );
+        assert!("sinkhole".parse::<BlockAction>().is_err());
+    }
+
+    #[test]
+    fn recognises_domains() {
+        assert!(is_domain("example.com"));
+        assert!(is_domain("example.com."));
+        assert!(is_domain("_dmarc.x-y.example"));
+        assert!(!is_domain(""));
+        assert!(!is_domain("."));
+        assert!(!is_domain("a..b"));
+        assert!(!is_domain("bad/name"));
+        assert!(!is_domain(&format!("{}.example", "a".repeat(64))));
+    }
+}
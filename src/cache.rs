@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::message::*;
+
+#[derive(Debug)]
+struct CacheEntry {
+    records: Vec<Answer>,
+    expires: Instant,
+    // Whether the RRset was validated as secure with DNSSEC.
+    secure: bool,
+}
+
+/// RRsets learned from upstream, keyed by lowercased owner name and type.
+#[derive(Debug, Default)]
+pub struct Cache {
+    entries: HashMap<(Vec<u8>, u16), CacheEntry>,
+}
+
+impl Cache {
+    pub fn new() -> Cache {
+        Cache {
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Stores records grouped into RRsets, noting whether DNSSEC validation
+    /// found them secure. Each RRset lives as long as its shortest TTL.
+    pub fn insert(&mut self, records: &[Answer], secure: bool) {
+        let mut sets: HashMap<(Vec<u8>, u16), Vec<Answer>> = HashMap::new();
+        for record in records {
+            sets.entry((lowercase_name(&record.name), record.qtype))
+                .or_default()
+                .push(record.clone());
+        }
+
+        let now = Instant::now();
+        for (key, records) in sets {
+            let ttl = records.iter().map(|record| record.ttl).min().unwrap_or(0);
+            if ttl == 0 {
+                continue;
+            }
+            self.entries.insert(key, CacheEntry {
+                records,
+                expires: now + Duration::from_secs(ttl as u64),
+                secure,
+            });
+        }
+    }
+
+    pub fn is_secure(&self, name: &[u8], rtype: u16) -> bool {
+        self.entries
+            .get(&(lowercase_name(name), rtype))
+            .is_some_and(|entry| entry.secure)
+    }
+
+    /// Returns the cached RRset with TTLs reduced by the time spent in cache.
+    pub fn get(&mut self, name: &[u8], rtype: u16) -> Option<Vec<Answer>> {
+        let key = (lowercase_name(name), rtype);
+        let now = Instant::now();
+        let entry = self.entries.get(&key)?;
+        if entry.expires <= now {
+            self.entries.remove(&key);
+            return None;
+        }
+
+        let remaining = (entry.expires - now).as_secs() as u32;
+        Some(
+            entry.records
+                .iter()
+                .cloned()
+                .map(|mut record| {
+                    record.ttl = remaining.max(1);
+                    record
+                })
+                .collect()
+        )
+    }
+}
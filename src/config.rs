@@ -0,0 +1,326 @@
+use std::str::FromStr;
+
+use anyhow::{Result, anyhow};
+
+use crate::acl::{AccessControl, Cidr, Operation};
+use crate::blocklist::BlockAction;
+use crate::message::{encode_name, is_subdomain, names_equal};
+use crate::ratelimit::QueryLimitSettings;
+use crate::reverse::ReverseNetwork;
+use crate::rrl::RrlSettings;
+use crate::tsig::TsigKey;
+
+const DEFAULT_LISTEN: &str = "127.0.0.1:2053";
+
+/// A zone this server serves as a secondary, transferred from `primary`.
+#[derive(Debug, Clone)]
+pub struct SecondaryZone {
+    pub origin: Vec<u8>,
+    pub primary: String,
+    // Key used to sign transfer requests to the primary.
+    pub key: Option<TsigKey>,
+}
+
+/// A secondary to send NOTIFY to when zone `origin` changes.
+#[derive(Debug, Clone)]
+pub struct NotifyTarget {
+    pub origin: Vec<u8>,
+    pub secondary: String,
+}
+
+/// Names at or below `origin` are forwarded to `resolver` rather than the
+/// default resolver.
+#[derive(Debug, Clone)]
+pub struct ForwardZone {
+    pub origin: Vec<u8>,
+    pub resolver: String,
+}
+
+/// Clients in `clients` are answered from this view's zones and forwarding
+/// rules instead of the top-level ones.
+#[derive(Debug, Clone)]
+pub struct ViewConfig {
+    pub name: String,
+    pub clients: Vec<Cidr>,
+    pub zone_files: Vec<String>,
+    pub resolver: Option<String>,
+    pub forward_zones: Vec<ForwardZone>,
+    pub access: AccessControl,
+}
+
+impl FromStr for ViewConfig {
+    type Err = anyhow::Error;
+
+    /// Parses `<name>=<network>[,<network>...]`.
+    fn from_str(s: &str) -> Result<ViewConfig> {
+        let (name, clients) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow!("View must be <name>=<network>[,<network>...]"))?;
+        Ok(ViewConfig {
+            name: name.to_owned(),
+            clients: clients.split(',').map(|client| client.parse()).collect::<Result<_>>()?,
+            zone_files: vec![],
+            resolver: None,
+            forward_zones: vec![],
+            access: AccessControl::default(),
+        })
+    }
+}
+
+/// A private key for signing zone `origin` online.
+#[derive(Debug, Clone)]
+pub struct SigningKeyFile {
+    pub origin: Vec<u8>,
+    pub path: String,
+    // Key signing keys sign only the DNSKEY RRset.
+    pub ksk: bool,
+}
+
+/// Splits a `<zone>=<value>` argument.
+fn zone_argument(flag: &str, value: &str) -> Result<(Vec<u8>, String)> {
+    let (zone, value) = value
+        .split_once('=')
+        .ok_or_else(|| anyhow!("{} expects <zone>=<value>", flag))?;
+    Ok((encode_name(zone), value.to_owned()))
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    // Address for both the UDP socket and the TCP listener.
+    pub listen: String,
+    pub resolver: Option<String>,
+    pub forward_zones: Vec<ForwardZone>,
+    pub zone_files: Vec<String>,
+    // Clients allowed to query, recurse, pull zones and send updates.
+    pub access: AccessControl,
+    pub secondaries: Vec<SecondaryZone>,
+    pub notify: Vec<NotifyTarget>,
+    pub tsig_keys: Vec<TsigKey>,
+    // Files of DS or DNSKEY records to validate forwarded answers from.
+    pub trust_anchor_files: Vec<String>,
+    pub signing_keys: Vec<SigningKeyFile>,
+    // Signed zones that deny existence with NSEC3 rather than NSEC.
+    pub nsec3_zones: Vec<Vec<u8>>,
+    // Networks to synthesize PTR and matching address records for.
+    pub reverse_networks: Vec<ReverseNetwork>,
+    // Files in /etc/hosts format answered ahead of zones and upstream.
+    pub hosts_files: Vec<String>,
+    // YAML or TOML name to address lists that take precedence over them.
+    pub overrides_files: Vec<String>,
+    // Lists of domains to block, and of exceptions to them.
+    pub blocklist_files: Vec<String>,
+    pub allowlist_files: Vec<String>,
+    pub block_action: BlockAction,
+    // Response policy zones, in order of precedence.
+    pub rpz_files: Vec<String>,
+    // Views checked in order; clients matching none get the top-level zones
+    // and forwarding rules.
+    pub views: Vec<ViewConfig>,
+    // Response rate limiting for UDP answers.
+    pub rrl: RrlSettings,
+    // Token bucket limits on the queries each client may send.
+    pub query_limit: QueryLimitSettings,
+}
+
+impl Config {
+    pub fn from_args(args: &[String]) -> Result<Config> {
+        let mut config = Config {
+            listen: DEFAULT_LISTEN.to_owned(),
+            ..Config::default()
+        };
+        let mut args = args.iter().skip(1);
+        let mut secondary_keys = vec![];
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .cloned()
+                    .ok_or_else(|| anyhow!("{} expects a value", arg))
+            };
+            match arg.as_str() {
+                "--listen" => config.listen = value()?,
+                // Zones, forwarding and ACLs given after --view belong to that
+                // view.
+                "--view" => config.views.push(value()?.parse()?),
+                "--resolver" => match config.views.last_mut() {
+                    Some(view) => view.resolver = Some(value()?),
+                    None => config.resolver = Some(value()?),
+                },
+                "--forward" => {
+                    let (origin, resolver) = zone_argument(arg, &value()?)?;
+                    let forward_zones = match config.views.last_mut() {
+                        Some(view) => &mut view.forward_zones,
+                        None => &mut config.forward_zones,
+                    };
+                    forward_zones.push(ForwardZone { origin, resolver });
+                }
+                "--zone" => match config.views.last_mut() {
+                    Some(view) => view.zone_files.push(value()?),
+                    None => config.zone_files.push(value()?),
+                },
+                "--allow-query" | "--allow-recursion" | "--allow-transfer" | "--allow-update" => {
+                    let operation = match arg.as_str() {
+                        "--allow-query" => Operation::Query,
+                        "--allow-recursion" => Operation::Recursion,
+                        "--allow-transfer" => Operation::Transfer,
+                        _ => Operation::Update,
+                    };
+                    // Network and key entries never contain `=`, so one
+                    // marks a <zone>=<entry> ACL for that zone alone.
+                    let value = value()?;
+                    let (origin, entry) = match value.split_once('=') {
+                        Some((zone, entry)) => (Some(encode_name(zone)), entry),
+                        None => (None, value.as_str()),
+                    };
+                    let access = match config.views.last_mut() {
+                        Some(view) => &mut view.access,
+                        None => &mut config.access,
+                    };
+                    access.push(operation, origin, entry.parse()?);
+                }
+                "--secondary" => {
+                    let (origin, primary) = zone_argument(arg, &value()?)?;
+                    config.secondaries.push(SecondaryZone { origin, primary, key: None });
+                }
+                "--secondary-key" => secondary_keys.push(zone_argument(arg, &value()?)?),
+                "--notify" => {
+                    let (origin, secondary) = zone_argument(arg, &value()?)?;
+                    config.notify.push(NotifyTarget { origin, secondary });
+                }
+                "--tsig-key" => config.tsig_keys.push(value()?.parse()?),
+                "--trust-anchor" => config.trust_anchor_files.push(value()?),
+                "--ksk" | "--zsk" => {
+                    let (origin, path) = zone_argument(arg, &value()?)?;
+                    config.signing_keys.push(SigningKeyFile { origin, path, ksk: arg == "--ksk" });
+                }
+                "--reverse-network" => config.reverse_networks.push(value()?.parse()?),
+                "--hosts" => config.hosts_files.push(value()?),
+                "--overrides" => config.overrides_files.push(value()?),
+                "--blocklist" => config.blocklist_files.push(value()?),
+                "--allowlist" => config.allowlist_files.push(value()?),
+                "--block-action" => config.block_action = value()?.parse()?,
+                "--rpz" => config.rpz_files.push(value()?),
+                "--rrl-rate" => config.rrl.rate = value()?.parse()?,
+                "--rrl-window" => {
+                    config.rrl.window = value()?
+                        .parse()
+                        .ok()
+                        .filter(|window| *window > 0)
+                        .ok_or_else(|| anyhow!("--rrl-window expects a number of seconds above zero"))?;
+                }
+                "--rrl-slip" => config.rrl.slip = value()?.parse()?,
+                "--rrl-ipv4-prefix" => config.rrl.ipv4_prefix = value()?.parse()?,
+                "--rrl-ipv6-prefix" => config.rrl.ipv6_prefix = value()?.parse()?,
+                "--query-rate" => config.query_limit.rate = value()?.parse()?,
+                "--query-burst" => {
+                    let burst = value()?
+                        .parse()
+                        .ok()
+                        .filter(|burst| *burst > 0)
+                        .ok_or_else(|| anyhow!("--query-burst expects a number of queries above zero"))?;
+                    config.query_limit.burst = Some(burst);
+                }
+                "--query-ipv4-prefix" => config.query_limit.ipv4_prefix = value()?.parse()?,
+                "--query-ipv6-prefix" => config.query_limit.ipv6_prefix = value()?.parse()?,
+                "--query-limit-action" => config.query_limit.action = value()?.parse()?,
+                "--query-limit-exempt" => config.query_limit.exempt.push(value()?.parse()?),
+                "--nsec3" => config.nsec3_zones.push(encode_name(&value()?)),
+                _ => return Err(anyhow!("Unknown argument {}", arg)),
+            }
+        }
+
+        for (origin, key_name) in secondary_keys {
+            let key = config.tsig_keys
+                .iter()
+                .find(|key| names_equal(&key.name, &encode_name(&key_name)))
+                .cloned()
+                .ok_or_else(|| anyhow!("Unknown TSIG key {}", key_name))?;
+            let secondary = config.secondaries
+                .iter_mut()
+                .find(|secondary| names_equal(&secondary.origin, &origin))
+                .ok_or_else(|| anyhow!("--secondary-key for a zone that is not a secondary"))?;
+            secondary.key = Some(key);
+        }
+
+        Ok(config)
+    }
+
+    /// The settings for clients of `view`: its own zones, forwarding rules
+    /// and any ACLs it sets, and everything else from the top level.
+    pub fn for_view(&self, view: &ViewConfig) -> Config {
+        Config {
+            zone_files: view.zone_files.clone(),
+            resolver: view.resolver.clone(),
+            forward_zones: view.forward_zones.clone(),
+            access: self.access.inherit(&view.access),
+            views: vec![],
+            ..self.clone()
+        }
+    }
+
+    /// Whether names outside local data are sent to an upstream resolver.
+    pub fn forwarding(&self) -> bool {
+        self.resolver.is_some() || !self.forward_zones.is_empty()
+    }
+
+    /// The longest forward zone containing `name`, if any.
+    pub fn forward_zone(&self, name: &[u8]) -> Option<&ForwardZone> {
+        self.forward_zones
+            .iter()
+            .filter(|forward| is_subdomain(name, &forward.origin))
+            .max_by_key(|forward| forward.origin.len())
+    }
+
+    /// The resolver to forward `name` to: that of the longest forward zone
+    /// containing it, or the default resolver.
+    pub fn resolver_for(&self, name: &[u8]) -> Option<&str> {
+        self.forward_zone(name)
+            .map(|forward| forward.resolver.as_str())
+            .or(self.resolver.as_deref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(args: &[&str]) -> Config {
+        let args: Vec<String> = std::iter::once("dns").chain(args.iter().copied()).map(str::to_owned).collect();
+        Config::from_args(&args).unwrap()
+    }
+
+    fn resolver_for<'a>(config: &'a Config, name: &str) -> Option<&'a str> {
+        config.resolver_for(&encode_name(name))
+    }
+
+    #[test]
+    fn forwards_to_the_longest_matching_zone() {
+        let config = config(&[
+            "--resolver", "8.8.8.8:53",
+            "--forward", "corp.example=10.0.0.53:53",
+            "--forward", "lab.corp.example.=10.1.0.53:53",
+            "--forward", "example.net=10.2.0.53:53",
+        ]);
+        assert!(config.forwarding());
+        assert_eq!(resolver_for(&config, "www.corp.example"), Some("10.0.0.53:53"));
+        assert_eq!(resolver_for(&config, "CORP.example"), Some("10.0.0.53:53"));
+        assert_eq!(resolver_for(&config, "host.LAB.corp.example"), Some("10.1.0.53:53"));
+        assert_eq!(resolver_for(&config, "lab.corp.example"), Some("10.1.0.53:53"));
+        assert_eq!(resolver_for(&config, "example.net"), Some("10.2.0.53:53"));
+        // Zones match whole labels only.
+        assert_eq!(resolver_for(&config, "notcorp.example"), Some("8.8.8.8:53"));
+        assert_eq!(resolver_for(&config, "example"), Some("8.8.8.8:53"));
+    }
+
+    #[test]
+    fn forward_zones_need_no_default_resolver() {
+        let config = config(&["--forward", "corp.example=10.0.0.53:53"]);
+        assert!(config.forwarding());
+        assert_eq!(resolver_for(&config, "a.corp.example"), Some("10.0.0.53:53"));
+        assert_eq!(resolver_for(&config, "example.com"), None);
+        assert!(!Config::default().forwarding());
+
+        let args = ["dns".to_owned(), "--forward".to_owned(), "corp.example".to_owned()];
+        assert!(Config::from_args(&args).is_err());
+    }
+}
@@ -0,0 +1,312 @@
+use std::collections::HashMap;
+use std::fs;
+use std::net::IpAddr;
+
+use anyhow::{Result, anyhow};
+
+use crate::blocklist::is_domain;
+use crate::message::*;
+use crate::zone::Lookup;
+
+// Kept short so that clients pick up edits to the files soon after a reload.
+const HOSTS_TTL: u32 = 60;
+
+/// Local name to address overrides, read from `/etc/hosts`-format files and
+/// from simple YAML or TOML lists of `name: address` pairs.
+#[derive(Debug, Default)]
+pub struct HostsTable {
+    // Addresses by lowercased name, in file order.
+    addresses: HashMap<Vec<u8>, Vec<IpAddr>>,
+    // The name PTR queries for each address are answered with.
+    names: HashMap<IpAddr, Vec<u8>>,
+}
+
+impl HostsTable {
+    /// Loads `hosts_files` in order, then `overrides_files`, whose entries
+    /// replace any the hosts files give for the same name.
+    pub fn load(hosts_files: &[String], overrides_files: &[String]) -> Result<HostsTable> {
+        let mut table = HostsTable::default();
+        for path in hosts_files {
+            let text = fs::read_to_string(path)?;
+            table.parse_hosts(&text).map_err(|e| anyhow!("{}: {}", path, e))?;
+        }
+
+        let mut overrides = HostsTable::default();
+        for path in overrides_files {
+            let text = fs::read_to_string(path)?;
+            let entries = if path.ends_with(".toml") {
+                parse_toml_overrides(&text)
+            } else if path.ends_with(".yaml") || path.ends_with(".yml") {
+                parse_yaml_overrides(&text)
+            } else {
+                Err(anyhow!("Overrides must be a .toml, .yaml or .yml file"))
+            };
+            for (number, name, addresses) in entries.map_err(|e| anyhow!("{}: {}", path, e))? {
+                overrides.add(&name, &addresses).map_err(|e| anyhow!("{}: line {}: {}", path, number, e))?;
+            }
+        }
+        table.replace(overrides);
+        Ok(table)
+    }
+
+    /// Parses `<address> <name> [<alias>...]` lines. The first name on a
+    /// line is the one its address maps back to.
+    fn parse_hosts(&mut self, text: &str) -> Result<()> {
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut fields = line.split_whitespace();
+            let Some(address) = fields.next() else {
+                continue;
+            };
+            let address = parse_address(address).map_err(|e| anyhow!("line {}: {}", number + 1, e))?;
+            for name in fields {
+                self.add(name, &[address]).map_err(|e| anyhow!("line {}: {}", number + 1, e))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn add(&mut self, name: &str, addresses: &[IpAddr]) -> Result<()> {
+        if !is_domain(name) {
+            return Err(anyhow!("Invalid name {}", name));
+        }
+        let name = encode_name(name);
+        let known = self.addresses.entry(lowercase_name(&name)).or_default();
+        for &address in addresses {
+            if !known.contains(&address) {
+                known.push(address);
+            }
+            self.names.entry(address).or_insert_with(|| name.clone());
+        }
+        Ok(())
+    }
+
+    /// Replaces the addresses of every name in `overrides`, dropping
+    /// reverse mappings to those names for addresses they no longer have.
+    fn replace(&mut self, overrides: HostsTable) {
+        for (name, addresses) in overrides.addresses {
+            self.names.retain(|address, target| !names_equal(target, &name) || addresses.contains(address));
+            self.addresses.insert(name, addresses);
+        }
+        self.names.extend(overrides.names);
+    }
+
+    /// Answers A, AAAA and PTR queries from the table. `None` when the
+    /// table has no entry for `name`.
+    pub fn lookup(&self, name: &[u8], qtype: u16) -> Option<Lookup> {
+        if let Some(address) = reverse_name_to_ip(name) {
+            let target = self.names.get(&address)?;
+            return Some(match qtype {
+                rtype::PTR | rtype::ANY => {
+                    Lookup::Records(vec![hosts_record(name, rtype::PTR, &RData::Ptr(target.clone()))])
+                }
+                _ => Lookup::NoData,
+            });
+        }
+
+        let records: Vec<Answer> = self
+            .addresses
+            .get(&lowercase_name(name))?
+            .iter()
+            .filter_map(|address| {
+                let (address_type, rdata) = match *address {
+                    IpAddr::V4(address) => (rtype::A, RData::A(address)),
+                    IpAddr::V6(address) => (rtype::AAAA, RData::Aaaa(address)),
+                };
+                (qtype == address_type || qtype == rtype::ANY).then(|| hosts_record(name, address_type, &rdata))
+            })
+            .collect();
+        Some(if records.is_empty() { Lookup::NoData } else { Lookup::Records(records) })
+    }
+}
+
+fn hosts_record(name: &[u8], qtype: u16, rdata: &RData) -> Answer {
+    Answer::builder()
+        .name_bytes(name.to_vec())
+        .unwrap()
+        .qtype(qtype)
+        .unwrap()
+        .ttl(HOSTS_TTL)
+        .unwrap()
+        .rdata(rdata)
+        .unwrap()
+        .build()
+}
+
+/// Parses an address, ignoring the `%<interface>` scope some hosts files
+/// give link-local IPv6 addresses.
+fn parse_address(address: &str) -> Result<IpAddr> {
+    let address = address.split('%').next().unwrap_or_default();
+    address.parse().map_err(|_| anyhow!("Invalid address {}", address))
+}
+
+fn unquote(value: &str) -> &str {
+    let value = value.trim();
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .or_else(|| value.strip_prefix('\'').and_then(|value| value.strip_suffix('\'')))
+        .unwrap_or(value)
+}
+
+/// Parses a single, optionally quoted address or a `[a, b]` list of them.
+fn parse_addresses(value: &str) -> Result<Vec<IpAddr>> {
+    let value = value.trim();
+    match value.strip_prefix('[').and_then(|value| value.strip_suffix(']')) {
+        Some(list) => list
+            .split(',')
+            .filter(|item| !item.trim().is_empty())
+            .map(|item| parse_address(unquote(item)))
+            .collect(),
+        None => Ok(vec![parse_address(unquote(value))?]),
+    }
+}
+
+/// Parses `name = "address"` and `name = ["address", ...]` pairs, at the
+/// top level or under a single table such as `[overrides]`. Each entry
+/// carries the line it is on.
+fn parse_toml_overrides(text: &str) -> Result<Vec<(usize, String, Vec<IpAddr>)>> {
+    let mut entries = vec![];
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() || line.starts_with('[') {
+            continue;
+        }
+        let (name, value) = line
+            .split_once('=')
+            .ok_or_else(|| anyhow!("line {}: expected <name> = <address>", number + 1))?;
+        let addresses = parse_addresses(value).map_err(|e| anyhow!("line {}: {}", number + 1, e))?;
+        entries.push((number + 1, unquote(name).to_owned(), addresses));
+    }
+    Ok(entries)
+}
+
+/// Parses `name: address` and `name: [address, ...]` pairs, or a name
+/// followed by indented `- address` items, optionally nested under a
+/// single top-level key such as `overrides:`. Each entry carries the line
+/// its name is on.
+fn parse_yaml_overrides(text: &str) -> Result<Vec<(usize, String, Vec<IpAddr>)>> {
+    let mut entries: Vec<(usize, String, Vec<IpAddr>)> = vec![];
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() || line == "---" {
+            continue;
+        }
+        let error = |e: anyhow::Error| anyhow!("line {}: {}", number + 1, e);
+        if let Some(item) = line.strip_prefix('-') {
+            let (_, _, addresses) = entries
+                .last_mut()
+                .ok_or_else(|| anyhow!("line {}: list item without a name", number + 1))?;
+            addresses.push(parse_address(unquote(item)).map_err(error)?);
+            continue;
+        }
+        // Names never contain colons, so the first one ends the key even
+        // when the value is an IPv6 address.
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| anyhow!("line {}: expected <name>: <address>", number + 1))?;
+        let addresses = if value.trim().is_empty() { vec![] } else { parse_addresses(value).map_err(error)? };
+        entries.push((number + 1, unquote(name).to_owned(), addresses));
+    }
+    // A key with no addresses of its own only groups the entries below it.
+    entries.retain(|(_, _, addresses)| !addresses.is_empty());
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addresses(table: &HostsTable, name: &str, qtype: u16) -> Vec<RData> {
+        match table.lookup(&encode_name(name), qtype) {
+            Some(Lookup::Records(records)) => records.iter().map(|record| record.rdata().unwrap()).collect(),
+            Some(_) => vec![],
+            None => panic!("{} is not in the table", name),
+        }
+    }
+
+    fn address(text: &str) -> IpAddr {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_hosts_files() {
+        let mut table = HostsTable::default();
+        let text = concat!(
+            "# comment\n127.0.0.1 localhost\n",
+            "10.0.0.5 nas.lan nas # trailing\n\n",
+            "fe80::1%eth0 router.lan\n10.0.0.6 nas.lan\n",
+        );
+        table.parse_hosts(text).unwrap();
+        let expected = [RData::A("10.0.0.5".parse().unwrap()), RData::A("10.0.0.6".parse().unwrap())];
+        assert_eq!(addresses(&table, "NAS.lan", rtype::A), expected);
+        assert_eq!(addresses(&table, "nas", rtype::A).len(), 1);
+        assert_eq!(addresses(&table, "router.lan", rtype::AAAA), [RData::Aaaa("fe80::1".parse().unwrap())]);
+        assert!(addresses(&table, "router.lan", rtype::A).is_empty());
+        assert!(table.lookup(&encode_name("other.lan"), rtype::A).is_none());
+
+        // PTR queries get the first name given for an address.
+        let ptr = addresses(&table, &name_to_string(&reverse_name(address("10.0.0.5"))), rtype::PTR);
+        assert_eq!(ptr, [RData::Ptr(encode_name("nas.lan"))]);
+    }
+
+    #[test]
+    fn reports_bad_hosts_lines() {
+        let error = |text: &str| HostsTable::default().parse_hosts(text).unwrap_err().to_string();
+        assert_eq!(error("10.0.0.1 ok.lan\n10.0.0 bad.lan\n"), "line 2: Invalid address 10.0.0");
+        assert_eq!(error("10.0.0.1 ok.lan\n\n10.0.0.2 bad..lan\n"), "line 3: Invalid name bad..lan");
+        assert_eq!(error("10.0.0.1 under_score.lan bad/name.lan\n"), "line 1: Invalid name bad/name.lan");
+    }
+
+    #[test]
+    fn parses_yaml_overrides() {
+        let text = concat!(
+            "---\noverrides:\n",
+            "  api.dev.local: 10.1.0.5\n",
+            "  \"multi.dev.local\":\n    - 10.1.0.6\n    - fd00::6\n",
+            "  router.lan: [10.9.9.9, '10.9.9.8']\n",
+        );
+        let entries = parse_yaml_overrides(text).unwrap();
+        let expected = [
+            (3, "api.dev.local", vec![address("10.1.0.5")]),
+            (4, "multi.dev.local", vec![address("10.1.0.6"), address("fd00::6")]),
+            (7, "router.lan", vec![address("10.9.9.9"), address("10.9.9.8")]),
+        ];
+        assert_eq!(entries.len(), expected.len());
+        for (entry, (line, name, addresses)) in entries.iter().zip(expected) {
+            assert_eq!((entry.0, entry.1.as_str(), &entry.2), (line, name, &addresses));
+        }
+
+        assert_eq!(parse_yaml_overrides("- 10.0.0.1\n").unwrap_err().to_string(), "line 1: list item without a name");
+        let error = parse_yaml_overrides("a.lan: 10.0.0.1\nb.lan 10.0.0.2\n").unwrap_err();
+        assert_eq!(error.to_string(), "line 2: expected <name>: <address>");
+    }
+
+    #[test]
+    fn parses_toml_overrides() {
+        let entries = parse_toml_overrides(
+            "[overrides]\n\"db.dev.local\" = \"10.2.0.99\" # comment\ntwo.dev.local = [\"10.2.0.2\", \"fd00::2\"]\n",
+        )
+        .unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!((entries[0].0, entries[0].1.as_str()), (2, "db.dev.local"));
+        assert_eq!(entries[0].2, [address("10.2.0.99")]);
+        assert_eq!(entries[1].2, [address("10.2.0.2"), address("fd00::2")]);
+        assert_eq!(parse_toml_overrides("x.lan = \"nope\"\n").unwrap_err().to_string(), "line 1: Invalid address nope");
+    }
+
+    #[test]
+    fn overrides_replace_hosts_entries() {
+        let mut table = HostsTable::default();
+        table.parse_hosts("10.0.0.5 nas.lan\n10.0.0.7 printer.lan\n").unwrap();
+        let mut overrides = HostsTable::default();
+        overrides.add("nas.lan", &[address("10.0.0.50")]).unwrap();
+        table.replace(overrides);
+
+        assert_eq!(addresses(&table, "nas.lan", rtype::A), [RData::A("10.0.0.50".parse().unwrap())]);
+        assert_eq!(addresses(&table, "printer.lan", rtype::A).len(), 1);
+        // The old address no longer maps back to the overridden name.
+        assert!(table.lookup(&reverse_name(address("10.0.0.5")), rtype::PTR).is_none());
+        assert!(table.lookup(&reverse_name(address("10.0.0.50")), rtype::PTR).is_some());
+    }
+}
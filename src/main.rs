@@ -1,36 +1,267 @@
 pub mod response;
 pub mod message;
+pub mod zone;
+pub mod cache;
+pub mod config;
+pub mod acl;
+pub mod transfer;
+pub mod tcp;
+pub mod reload;
+pub mod secondary;
+pub mod notify;
+pub mod update;
+pub mod tsig;
+pub mod validator;
+pub mod signer;
+pub mod reverse;
+pub mod hosts;
+pub mod blocklist;
+pub mod rpz;
+pub mod view;
+pub mod rrl;
+pub mod ratelimit;
 
-use std::net::UdpSocket;
-use std::env;
-use crate::{message::*, response::{build_response, build_response_forward}};
+use std::collections::HashMap;
+use std::net::{TcpListener, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use std::{env, thread};
+use crate::{
+    blocklist::Blocklist,
+    config::Config,
+    hosts::HostsTable,
+    message::*,
+    notify::{handle_notify, notify_secondaries},
+    ratelimit::{LimitAction, QueryRateLimiter},
+    rpz::ResponsePolicy,
+    rrl::{ResponseRateLimiter, RrlAction},
+    response::{answer_request, error_response, format_error, recursion_available, truncate, udp_payload_limit},
+    signer::Signer,
+    tsig::verify_request,
+    validator::Validator,
+    view::View,
+    zone::Zone,
+};
+
+const ZONE_RELOAD_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Reloads the zones and local data of `view` when their files change.
+fn watch_view(view: &View) {
+    {
+        let zones = view.zones.clone();
+        let config = view.config.clone();
+        reload::watch(config.zone_files.clone(), ZONE_RELOAD_INTERVAL, move |path| {
+            match Zone::load(path) {
+                Ok(zone) => {
+                    let mut zones = zones.write().unwrap();
+                    let current = zones
+                        .find_zone(&zone.origin)
+                        .filter(|current| names_equal(&current.origin, &zone.origin));
+                    // Reloading would silently discard updates that clients
+                    // were already told succeeded, and could move the serial
+                    // backwards.
+                    if current.is_some_and(|current| current.is_dynamic()) {
+                        eprintln!(
+                            "Not reloading {}: zone {} has dynamic updates that are not in the file",
+                            path,
+                            name_to_string(&zone.origin)
+                        );
+                        return;
+                    }
+                    println!("Reloaded zone {} from {}", name_to_string(&zone.origin), path);
+                    let old_serial = current.and_then(|zone| zone.serial());
+                    let soa = zone.soa().cloned();
+                    zones.update(zone);
+                    if let Some(soa) = soa.filter(|soa| zone::soa_serial(soa) != old_serial) {
+                        notify_secondaries(&soa, &config);
+                    }
+                }
+                Err(e) => eprintln!("Failed to reload {}: {}", path, e),
+            }
+        });
+    }
+
+    {
+        let zones = view.zones.clone();
+        let config = view.config.clone();
+        let paths = config.hosts_files.iter().chain(&config.overrides_files).cloned().collect();
+        reload::watch(paths, ZONE_RELOAD_INTERVAL, move |path| {
+            match HostsTable::load(&config.hosts_files, &config.overrides_files) {
+                Ok(hosts) => {
+                    println!("Reloaded hosts after {} changed", path);
+                    zones.write().unwrap().set_hosts(hosts);
+                }
+                Err(e) => eprintln!("Failed to reload {}: {}", path, e),
+            }
+        });
+    }
+
+    {
+        let zones = view.zones.clone();
+        let config = view.config.clone();
+        let paths = config.blocklist_files.iter().chain(&config.allowlist_files).cloned().collect();
+        reload::watch(paths, ZONE_RELOAD_INTERVAL, move |path| {
+            match Blocklist::load(&config.blocklist_files, &config.allowlist_files, config.block_action.clone()) {
+                Ok(blocklist) => {
+                    println!("Reloaded {} blocked domains after {} changed", blocklist.len(), path);
+                    zones.write().unwrap().set_blocklist(blocklist);
+                }
+                Err(e) => eprintln!("Failed to reload {}: {}", path, e),
+            }
+        });
+    }
+
+    {
+        let zones = view.zones.clone();
+        let config = view.config.clone();
+        reload::watch(config.rpz_files.clone(), ZONE_RELOAD_INTERVAL, move |path| {
+            match ResponsePolicy::load(&config.rpz_files) {
+                Ok(policy) => {
+                    println!("Reloaded policy zones after {} changed", path);
+                    zones.write().unwrap().set_policy(policy);
+                }
+                Err(e) => eprintln!("Failed to reload {}: {}", path, e),
+            }
+        });
+    }
+}
 
 fn main() {
-    let udp_socket = UdpSocket::bind("127.0.0.1:2053").expect("Failed to bind to address");
-    let mut buf = [0; 512];
-    
     let args: Vec<String> = env::args().collect();
+    let config = Config::from_args(&args).expect("Invalid arguments");
+
+    let validator = if config.trust_anchor_files.is_empty() {
+        None
+    } else {
+        let validator = Validator::load(&config.trust_anchor_files).expect("Failed to load trust anchors");
+        Some(Arc::new(validator))
+    };
+    let signer = if config.signing_keys.is_empty() {
+        None
+    } else {
+        let signer = Signer::load(&config).expect("Failed to load signing keys");
+        Some(Arc::new(Mutex::new(signer)))
+    };
+    let config = Arc::new(config);
+
+    let views = View::load_all(&config).expect("Failed to load zones");
+    for view in &views {
+        watch_view(view);
+    }
+
+    // Views take their secondary zones from the top level, so each one is
+    // transferred into all of them.
+    let mut refresh_triggers = HashMap::new();
+    for secondary in &config.secondaries {
+        let stores = views.iter().map(|view| view.zones.clone()).collect();
+        let trigger = secondary::spawn(secondary.clone(), stores);
+        refresh_triggers.insert(lowercase_name(&secondary.origin), trigger);
+    }
+
+    // Shared with the TCP connections so that a client's queries count
+    // against one bucket whichever transport they arrive over.
+    let limiter = (config.query_limit.rate > 0)
+        .then(|| Arc::new(Mutex::new(QueryRateLimiter::new(config.query_limit.clone()))));
+
+    let tcp_listener = TcpListener::bind(&config.listen).expect("Failed to bind TCP listener");
+    {
+        let views = views.clone();
+        let validator = validator.clone();
+        let signer = signer.clone();
+        let limiter = limiter.clone();
+        let config = config.clone();
+        thread::spawn(move || tcp::serve(tcp_listener, views, validator, signer, limiter, config));
+    }
+
+    let udp_socket = UdpSocket::bind(&config.listen).expect("Failed to bind to address");
+    let mut rrl = (config.rrl.rate > 0).then(|| ResponseRateLimiter::new(config.rrl.clone()));
+    let mut buf = [0; EDNS_PAYLOAD_SIZE as usize];
 
     loop {
         println!("Waiting for data...");
         match udp_socket.recv_from(&mut buf) {
             Ok((size, source)) => {
                 println!("Received {} bytes from {}", size, source);
+                let view = view::select(&views, source.ip());
 
-                let request = Message::decode(&buf);
+                let request = match Message::decode(&buf[..size]) {
+                    Ok(request) => request,
+                    Err(e) => {
+                        eprintln!("Malformed request from {}: {}", source, e);
+                        if let Some(response) = format_error(&buf[..size], source.ip(), &view.config) {
+                            udp_socket
+                                .send_to(&response.encode(), source)
+                                .expect("Failed to send response");
+                        }
+                        continue;
+                    }
+                };
+                let limit = udp_payload_limit(&request);
 
-                let response = if args.len() == 1 {
-                    println!("Directly building response.");
-                    build_response(request)
+                let mut tsig = match verify_request(&buf[..size], &config.tsig_keys) {
+                    Ok(tsig) => tsig,
+                    Err(e) => {
+                        // Never fall back to handling the request as unsigned
+                        // (RFC 8945 section 5.2).
+                        eprintln!("Malformed TSIG from {}: {}", source, e);
+                        let recursion = recursion_available(&request, source.ip(), None, &view.config);
+                        udp_socket
+                            .send_to(&error_response(&request, Rcode::FormErr, recursion).encode(), source)
+                            .expect("Failed to send response");
+                        continue;
+                    }
+                };
+                if let Some(context) = tsig.as_mut().filter(|context| context.error != 0) {
+                    println!("TSIG verification failed for {} with error {}", source, context.error);
+                    let recursion = recursion_available(&request, source.ip(), None, &view.config);
+                    let response = context.sign(error_response(&request, Rcode::NotAuth, recursion).encode());
+                    udp_socket
+                        .send_to(&response, source)
+                        .expect("Failed to send response");
+                    continue;
+                }
+                let key = tsig.as_ref().map(|tsig| tsig.key_name().to_vec());
+                let limited = limiter
+                    .as_ref()
+                    .is_some_and(|limiter| !limiter.lock().unwrap().allow(source.ip(), key.as_deref()));
+                if limited && config.query_limit.action == LimitAction::Drop {
+                    println!("Dropping rate limited query from {}", source);
+                    continue;
+                }
+                let recursion = recursion_available(&request, source.ip(), key.as_deref(), &view.config);
+
+                let response = if limited {
+                    println!("Refusing rate limited query from {}", source);
+                    error_response(&request, Rcode::Refused, recursion)
+                } else if request.header.opcode == Opcode::Notify {
+                    println!("Handling NOTIFY.");
+                    handle_notify(request, source, &config, &refresh_triggers)
                 } else {
-                    println!("Forwarding request to resolver.");
-                    build_response_forward(request, args[2].clone(), &udp_socket)
+                    match answer_request(request, source, key.as_deref(), view, validator.as_deref(), signer.as_deref()) {
+                        Some(response) => response,
+                        None => {
+                            println!("Dropping request from {}", source);
+                            continue;
+                        }
+                    }
                 };
 
+                let response = match rrl.as_mut().map(|rrl| rrl.check(source.ip(), &response)) {
+                    Some(RrlAction::Drop) => {
+                        println!("Rate limited response to {}", source);
+                        continue;
+                    }
+                    Some(RrlAction::Slip) => truncate(response, 0),
+                    _ => truncate(response, limit),
+                };
                 println!("Response built, sending to {}", source);
 
+                let response = match tsig.as_mut() {
+                    Some(tsig) => tsig.sign(response.encode()),
+                    None => response.encode(),
+                };
                 udp_socket
-                    .send_to(&response.encode(), source)
+                    .send_to(&response, source)
                     .expect("Failed to send response");
             }
             Err(e) => {
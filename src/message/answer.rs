@@ -1,6 +1,9 @@
-use anyhow::{Result, Ok};
+use anyhow::{anyhow, Result, Ok};
+
+use crate::message::{lowercase_name, names_equal, read_name, NameCompressor, RData};
 
 #[derive(Debug)]
+#[derive(Clone)]
 pub struct Answer {
     pub name: Vec<u8>,
     pub qtype: u16,
@@ -29,49 +32,101 @@ impl Answer {
         bytes
     }
 
+    pub fn encode_compressed(&self, bytes: &mut Vec<u8>, compressor: &mut NameCompressor) {
+        compressor.write_name(bytes, &self.name);
+        bytes.extend(self.qtype.to_be_bytes());
+        bytes.extend(self.qclass.to_be_bytes());
+        bytes.extend(self.ttl.to_be_bytes());
+        let length_at = bytes.len();
+        bytes.extend([0, 0]);
+        match self.rdata() {
+            Result::Ok(rdata) => rdata.encode_compressed(bytes, compressor),
+            Err(_) => bytes.extend(&self.data),
+        }
+        let length = (bytes.len() - length_at - 2) as u16;
+        bytes[length_at..length_at + 2].copy_from_slice(&length.to_be_bytes());
+    }
+
     pub fn builder() -> AnswerBuilder {
         AnswerBuilder::new()
     }
 
-    pub fn decode(bytes: &[u8], ancount: usize) -> Vec<Answer> {
-        let mut cur = 0;
-        let mut cnt = 0;
-
+    /// Decodes `count` records starting at `offset` in a full message,
+    /// expanding compression pointers in owner names and RDATA. Returns the
+    /// records and the offset just past them.
+    pub fn decode(bytes: &[u8], count: usize, offset: usize) -> Result<(Vec<Answer>, usize)> {
+        let mut cur = offset;
         let mut answers = vec![];
 
-        while cnt < ancount {
-            let mut name: Vec<u8> = vec![];
-            while bytes[cur] != 0 {
-                let length = bytes[cur] as usize;
-                name.extend(
-                    &bytes[cur..=cur+length]
-                );
-                cur += length + 1;
-            }
-            cur += 1;
-            let atype = ((bytes[cur] as u16) << 8) | bytes[cur + 1] as u16;
-            cur += 2;
-            let aclass = ((bytes[cur] as u16) << 8) | bytes[cur + 1] as u16;
-            cur += 2;
-            let ttl = ((bytes[cur] as u32) << 24) | ((bytes[cur + 1] as u32) << 16) | ((bytes[cur + 2] as u32) << 8) | bytes[cur + 3] as u32;
-            cur += 4;
-            let length = ((bytes[cur] as u16) << 8) | bytes[cur + 1] as u16;
-            cur += 2;
-            let data = bytes[cur..cur + length as usize].to_vec();
-            cur += length as usize + 1;
+        for _ in 0..count {
+            let (name, next) = read_name(bytes, cur)?;
+            let fields = bytes
+                .get(next..next + 10)
+                .ok_or_else(|| anyhow!("Record runs past end of message"))?;
+            let atype = u16::from_be_bytes([fields[0], fields[1]]);
+            let aclass = u16::from_be_bytes([fields[2], fields[3]]);
+            let ttl = u32::from_be_bytes([fields[4], fields[5], fields[6], fields[7]]);
+            let length = u16::from_be_bytes([fields[8], fields[9]]) as usize;
+            cur = next + 10;
+            // Empty RDATA is legitimate in UPDATE prerequisites and deletes.
+            let data = if length == 0 {
+                vec![]
+            } else {
+                RData::decode(atype, bytes, cur, length)?.encode()
+            };
+            cur += length;
             answers.push(Answer {
-                name: name,
+                name,
                 qtype: atype,
                 qclass: aclass,
-                ttl: ttl,
-                length: length,
-                data: data,
+                ttl,
+                length: data.len() as u16,
+                data,
             });
-            cnt += 1;
         }
 
-        answers
+        Ok((answers, cur))
     }
+
+    pub fn rdata(&self) -> Result<RData> {
+        RData::decode(self.qtype, &self.data, 0, self.data.len())
+    }
+
+    /// RDATA in canonical form (RFC 4034 section 6.2), falling back to the
+    /// stored bytes for data that does not decode.
+    pub fn canonical_data(&self) -> Vec<u8> {
+        match self.rdata() {
+            Result::Ok(rdata) => rdata.canonical().encode(),
+            Err(_) => self.data.clone(),
+        }
+    }
+
+    /// The record in canonical RR form: lowercased owner, no compression,
+    /// canonical RDATA and the given TTL (the RRSIG original TTL when
+    /// signing or verifying).
+    pub fn canonical_form(&self, ttl: u32) -> Vec<u8> {
+        let data = self.canonical_data();
+        let mut bytes = lowercase_name(&self.name);
+        bytes.push(0);
+        bytes.extend(self.qtype.to_be_bytes());
+        bytes.extend(self.qclass.to_be_bytes());
+        bytes.extend(ttl.to_be_bytes());
+        bytes.extend((data.len() as u16).to_be_bytes());
+        bytes.extend(data);
+        bytes
+    }
+}
+
+/// The records of an RRset in canonical order (RFC 4034 section 6.3),
+/// sorted by canonical RDATA with duplicates dropped.
+pub fn canonical_rrset(records: &[Answer]) -> Vec<Answer> {
+    let mut records: Vec<(Vec<u8>, Answer)> = records
+        .iter()
+        .map(|record| (record.canonical_data(), record.clone()))
+        .collect();
+    records.sort_by(|a, b| a.0.cmp(&b.0));
+    records.dedup_by(|a, b| a.0 == b.0);
+    records.into_iter().map(|(_, record)| record).collect()
 }
 
 pub struct AnswerBuilder {
@@ -83,6 +138,12 @@ pub struct AnswerBuilder {
     data: Option<Vec<u8>>,
 }
 
+impl Default for AnswerBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[allow(dead_code)]
 impl AnswerBuilder {
     pub fn new() -> Self {
@@ -136,17 +197,74 @@ impl AnswerBuilder {
         Ok(self)
     }
 
+    pub fn rdata(mut self, rdata: &RData) -> Result<Self> {
+        let data = rdata.encode();
+        self.length = Some(data.len() as u16);
+        self.data = Some(data);
+        Ok(self)
+    }
+
     pub fn build(mut self) -> Answer {
         if self.name.is_none() {
             self = self.name("codecrafters.io".to_owned()).unwrap();
         }
+        let data = self.data.unwrap_or(vec![8, 8, 8, 8]);
         Answer {
             name: self.name.unwrap(),
             qtype: self.qtype.unwrap_or(1),
             qclass: self.qclass.unwrap_or(1),
             ttl: self.ttl.unwrap_or(60),
-            length: self.length.unwrap_or(4),
-            data: self.data.unwrap_or(vec![8, 8, 8, 8]),
+            length: self.length.unwrap_or(data.len() as u16),
+            data,
+        }
+    }
+}
+/// Groups records into RRsets by owner and type, in order of appearance.
+pub fn rrsets(records: &[Answer]) -> Vec<Vec<Answer>> {
+    let mut sets: Vec<Vec<Answer>> = vec![];
+    for record in records {
+        match sets
+            .iter_mut()
+            .find(|set| set[0].qtype == record.qtype && names_equal(&set[0].name, &record.name))
+        {
+            Some(set) => set.push(record.clone()),
+            None => sets.push(vec![record.clone()]),
         }
     }
-}
\ No newline at end of file
+    sets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{encode_name, name_to_string, rtype};
+
+    fn record(qtype: u16, rdata: RData) -> Answer {
+        Answer::builder()
+            .name_bytes(encode_name("example.com"))
+            .unwrap()
+            .qtype(qtype)
+            .unwrap()
+            .rdata(&rdata)
+            .unwrap()
+            .build()
+    }
+
+    #[test]
+    fn canonical_rrset_sorts_by_canonical_rdata() {
+        let addresses = [[10, 0, 0, 3], [10, 0, 0, 1], [9, 255, 255, 255], [10, 0, 0, 1]]
+            .map(|octets| record(rtype::A, RData::A(octets.into())));
+        let sorted: Vec<RData> = canonical_rrset(&addresses).iter().map(|record| record.rdata().unwrap()).collect();
+        assert_eq!(sorted, [[9, 255, 255, 255], [10, 0, 0, 1], [10, 0, 0, 3]].map(|octets| RData::A(octets.into())));
+
+        // Names compare by their lowercased wire form, so a shorter first
+        // label sorts first and case differences are duplicates.
+        let nameservers = ["ns10.example.com", "NS1.example.com", "b.example.com", "ns1.example.com"]
+            .map(|name| record(rtype::NS, RData::Ns(encode_name(name))));
+        let sorted: Vec<String> = canonical_rrset(&nameservers)
+            .iter()
+            .map(|record| name_to_string(&lowercase_name(record.rdata().unwrap().target().unwrap())))
+            .collect();
+        assert_eq!(sorted, ["b.example.com", "ns1.example.com", "ns10.example.com"]);
+    }
+}
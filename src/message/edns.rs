@@ -0,0 +1,51 @@
+use crate::message::{rtype, Answer, Message};
+
+// Payload size we advertise and accept over UDP.
+pub const EDNS_PAYLOAD_SIZE: u16 = 4096;
+const DNSSEC_OK: u32 = 1 << 15;
+
+/// EDNS(0) parameters carried in an OPT pseudo-record (RFC 6891).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Edns {
+    pub payload_size: u16,
+    // The DO bit (RFC 3225): the sender wants DNSSEC records.
+    pub dnssec_ok: bool,
+}
+
+impl Edns {
+    pub fn new(dnssec_ok: bool) -> Edns {
+        Edns {
+            payload_size: EDNS_PAYLOAD_SIZE,
+            dnssec_ok,
+        }
+    }
+
+    /// Reads the OPT record from the additional section, if there is one.
+    pub fn from_message(message: &Message) -> Option<Edns> {
+        message.additionals
+            .iter()
+            .find(|record| record.qtype == rtype::OPT)
+            .map(|opt| Edns {
+                // Values below 512 are treated as 512 (RFC 6891 section 6.2.3).
+                payload_size: opt.qclass.max(512),
+                dnssec_ok: opt.ttl & DNSSEC_OK != 0,
+            })
+    }
+
+    /// The OPT record, owned by the root, with the payload size in the
+    /// CLASS field and the flags in the TTL.
+    pub fn record(&self) -> Answer {
+        Answer::builder()
+            .name_bytes(vec![])
+            .unwrap()
+            .qtype(rtype::OPT)
+            .unwrap()
+            .qclass(self.payload_size)
+            .unwrap()
+            .ttl(if self.dnssec_ok { DNSSEC_OK } else { 0 })
+            .unwrap()
+            .data(vec![])
+            .unwrap()
+            .build()
+    }
+}
@@ -1,5 +1,119 @@
+use std::fmt;
+
 use anyhow::{Result, anyhow};
 
+/// The kind of request a message carries (RFC 1035 section 4.1.1, RFC 1996,
+/// RFC 2136).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
+    Query,
+    IQuery,
+    Status,
+    Notify,
+    Update,
+    Unknown(u8),
+}
+
+impl From<u8> for Opcode {
+    fn from(value: u8) -> Opcode {
+        match value {
+            0 => Opcode::Query,
+            1 => Opcode::IQuery,
+            2 => Opcode::Status,
+            4 => Opcode::Notify,
+            5 => Opcode::Update,
+            value => Opcode::Unknown(value),
+        }
+    }
+}
+
+impl From<Opcode> for u8 {
+    fn from(opcode: Opcode) -> u8 {
+        match opcode {
+            Opcode::Query => 0,
+            Opcode::IQuery => 1,
+            Opcode::Status => 2,
+            Opcode::Notify => 4,
+            Opcode::Update => 5,
+            Opcode::Unknown(value) => value,
+        }
+    }
+}
+
+/// The outcome of a request (RFC 1035 section 4.1.1, RFC 2136 section 2.2).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rcode {
+    NoError,
+    FormErr,
+    ServFail,
+    NxDomain,
+    NotImp,
+    Refused,
+    YxDomain,
+    YxRrset,
+    NxRrset,
+    NotAuth,
+    NotZone,
+    Unknown(u8),
+}
+
+impl From<u8> for Rcode {
+    fn from(value: u8) -> Rcode {
+        match value {
+            0 => Rcode::NoError,
+            1 => Rcode::FormErr,
+            2 => Rcode::ServFail,
+            3 => Rcode::NxDomain,
+            4 => Rcode::NotImp,
+            5 => Rcode::Refused,
+            6 => Rcode::YxDomain,
+            7 => Rcode::YxRrset,
+            8 => Rcode::NxRrset,
+            9 => Rcode::NotAuth,
+            10 => Rcode::NotZone,
+            value => Rcode::Unknown(value),
+        }
+    }
+}
+
+impl From<Rcode> for u8 {
+    fn from(rcode: Rcode) -> u8 {
+        match rcode {
+            Rcode::NoError => 0,
+            Rcode::FormErr => 1,
+            Rcode::ServFail => 2,
+            Rcode::NxDomain => 3,
+            Rcode::NotImp => 4,
+            Rcode::Refused => 5,
+            Rcode::YxDomain => 6,
+            Rcode::YxRrset => 7,
+            Rcode::NxRrset => 8,
+            Rcode::NotAuth => 9,
+            Rcode::NotZone => 10,
+            Rcode::Unknown(value) => value,
+        }
+    }
+}
+
+impl fmt::Display for Rcode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Rcode::NoError => write!(f, "NOERROR"),
+            Rcode::FormErr => write!(f, "FORMERR"),
+            Rcode::ServFail => write!(f, "SERVFAIL"),
+            Rcode::NxDomain => write!(f, "NXDOMAIN"),
+            Rcode::NotImp => write!(f, "NOTIMP"),
+            Rcode::Refused => write!(f, "REFUSED"),
+            Rcode::YxDomain => write!(f, "YXDOMAIN"),
+            Rcode::YxRrset => write!(f, "YXRRSET"),
+            Rcode::NxRrset => write!(f, "NXRRSET"),
+            Rcode::NotAuth => write!(f, "NOTAUTH"),
+            Rcode::NotZone => write!(f, "NOTZONE"),
+            Rcode::Unknown(value) => write!(f, "{}", value),
+        }
+    }
+}
+
 #[derive(Debug)]
 #[derive(Clone)]
 pub struct Header {
@@ -7,15 +121,18 @@ pub struct Header {
     pub id: u16,
     pub qr: bool,
     // 4 bits
-    pub opcode: u8,
+    pub opcode: Opcode,
     pub aa: bool,
     pub tc: bool,
     pub rd: bool,
     pub ra: bool,
-    // 3 bits
+    // 1 bit, reserved
     pub z: u8,
+    // Authentic data and checking disabled (RFC 4035 section 3.2)
+    pub ad: bool,
+    pub cd: bool,
     // 4 bits
-    pub rcode: u8,
+    pub rcode: Rcode,
     // 16 bits
     pub qdcount: u16,
     // 16 bits
@@ -35,9 +152,9 @@ impl Header {
         let mut bytes = vec![];
         bytes.push(((self.id >> 8) & 0xFF) as u8);
         bytes.push((self.id & 0xFF) as u8);
-        let flags = (self.qr as u8) << 7 | (self.opcode << 3) | (self.aa as u8) << 2 | (self.tc as u8) << 1 | self.rd as u8;
+        let flags = (self.qr as u8) << 7 | (u8::from(self.opcode) << 3) | (self.aa as u8) << 2 | (self.tc as u8) << 1 | self.rd as u8;
         bytes.push(flags);
-        let flags = (self.ra as u8) << 7 | (self.z << 4) | self.rcode;
+        let flags = (self.ra as u8) << 7 | (self.z << 6) | (self.ad as u8) << 5 | (self.cd as u8) << 4 | u8::from(self.rcode);
         bytes.push(flags);
         bytes.push(((self.qdcount >> 8) & 0xFF) as u8);
         bytes.push((self.qdcount & 0xFF) as u8);
@@ -63,7 +180,9 @@ impl Header {
         let rd = flags1 & 1 == 1;
         let flags2 = bytes[3];
         let ra = (flags2 >> 7) & 1 == 1;
-        let z = (flags2 >> 4) & 0b111;
+        let z = (flags2 >> 6) & 1;
+        let ad = (flags2 >> 5) & 1 == 1;
+        let cd = (flags2 >> 4) & 1 == 1;
         let rcode = flags2 & 0b1111;
         let qdcount = ((bytes[4] as u16) << 8) | bytes[5] as u16;
         let ancount = ((bytes[6] as u16) << 8) | bytes[7] as u16;
@@ -75,7 +194,7 @@ impl Header {
                 .unwrap()
                 .qr(qr)
                 .unwrap()
-                .opcode(opcode)
+                .opcode(Opcode::from(opcode))
                 .unwrap()
                 .aa(aa)
                 .unwrap()
@@ -87,7 +206,11 @@ impl Header {
                 .unwrap()
                 .z(z)
                 .unwrap()
-                .rcode(rcode)
+                .ad(ad)
+                .unwrap()
+                .cd(cd)
+                .unwrap()
+                .rcode(Rcode::from(rcode))
                 .unwrap()
                 .qdcount(qdcount)
                 .unwrap()
@@ -104,19 +227,27 @@ impl Header {
 pub struct HeaderBuilder {
     id: Option<u16>,
     qr: Option<bool>,
-    opcode: Option<u8>,
+    opcode: Option<Opcode>,
     aa: Option<bool>,
     tc: Option<bool>,
     rd: Option<bool>,
     ra: Option<bool>,
     z: Option<u8>,
-    rcode: Option<u8>,
+    ad: Option<bool>,
+    cd: Option<bool>,
+    rcode: Option<Rcode>,
     qdcount: Option<u16>,
     ancount: Option<u16>,
     nscount: Option<u16>,
     arcount: Option<u16>
 }
 
+impl Default for HeaderBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[allow(dead_code)]
 impl HeaderBuilder {
 
@@ -130,6 +261,8 @@ impl HeaderBuilder {
             rd: None,
             ra: None,
             z: None,
+            ad: None,
+            cd: None,
             rcode: None,
             qdcount: None,
             ancount: None,
@@ -148,8 +281,8 @@ impl HeaderBuilder {
         Ok(self)
     }
 
-    pub fn opcode(mut self, opcode: u8) -> Result<HeaderBuilder> {
-        if opcode > (1 << 4) {
+    pub fn opcode(mut self, opcode: Opcode) -> Result<HeaderBuilder> {
+        if u8::from(opcode) >= (1 << 4) {
             return Err(anyhow!("OPCODE must be a 4-bit number"));
         }
         self.opcode = Some(opcode);
@@ -177,15 +310,25 @@ impl HeaderBuilder {
     }
 
     pub fn z(mut self, z: u8) -> Result<HeaderBuilder> {
-        if z > (1 << 3) {
-            return Err(anyhow!("Z must be a 3-bit number"));
+        if z > 1 {
+            return Err(anyhow!("Z must be a 1-bit number"));
         }
         self.z = Some(z);
         Ok(self)
     }
 
-    pub fn rcode(mut self, rcode: u8) -> Result<HeaderBuilder> {
-        if rcode > (1 << 4) {
+    pub fn ad(mut self, ad: bool) -> Result<HeaderBuilder> {
+        self.ad = Some(ad);
+        Ok(self)
+    }
+
+    pub fn cd(mut self, cd: bool) -> Result<HeaderBuilder> {
+        self.cd = Some(cd);
+        Ok(self)
+    }
+
+    pub fn rcode(mut self, rcode: Rcode) -> Result<HeaderBuilder> {
+        if u8::from(rcode) >= (1 << 4) {
             return Err(anyhow!("RCODE must be a 4-bit number"));
         }
         self.rcode = Some(rcode);
@@ -216,13 +359,15 @@ impl HeaderBuilder {
         Header {
             id: self.id.unwrap_or(1234),
             qr: self.qr.unwrap_or(true),
-            opcode: self.opcode.unwrap_or(0),
+            opcode: self.opcode.unwrap_or(Opcode::Query),
             aa: self.aa.unwrap_or(false),
             tc: self.tc.unwrap_or(false),
             rd: self.rd.unwrap_or(false),
             ra: self.ra.unwrap_or(false),
             z: self.z.unwrap_or(0),
-            rcode: self.rcode.unwrap_or(0),
+            ad: self.ad.unwrap_or(false),
+            cd: self.cd.unwrap_or(false),
+            rcode: self.rcode.unwrap_or(Rcode::NoError),
             qdcount: self.qdcount.unwrap_or(0),
             ancount: self.ancount.unwrap_or(0),
             nscount: self.nscount.unwrap_or(0),
@@ -1,43 +1,129 @@
-use crate::{message::{Header, Question}, Answer};
+use anyhow::{anyhow, Result};
 
-#[derive(Debug)]
+use crate::{message::{Header, NameCompressor, Question}, Answer};
+
+#[derive(Debug, Clone)]
 pub struct Message {
     pub header: Header,
     pub questions: Vec<Question>,
     pub answers: Vec<Answer>,
+    pub authorities: Vec<Answer>,
+    pub additionals: Vec<Answer>,
 }
 
 impl Message {
     pub fn encode(self) -> Vec<u8> {
         let mut bytes = vec![];
+        let mut compressor = NameCompressor::new();
         bytes.extend(self.header.encode());
         for question in self.questions {
-            bytes.extend(question.encode());
+            question.encode_compressed(&mut bytes, &mut compressor);
         }
         for answer in self.answers {
-            bytes.extend(answer.encode());
+            answer.encode_compressed(&mut bytes, &mut compressor);
+        }
+        for authority in self.authorities {
+            authority.encode_compressed(&mut bytes, &mut compressor);
+        }
+        for additional in self.additionals {
+            additional.encode_compressed(&mut bytes, &mut compressor);
         }
         bytes
     }
 
-    pub fn decode(bytes: &[u8]) -> Self {
-        let header = Header::decode(bytes).unwrap();
-        let mut offset = 12;
-        let (questions, question_len) = Question::decode(
-            &bytes[offset..], 
-            header.qdcount as usize,
-            offset,
-        );
-        offset += question_len;
-        let answers = Answer::decode(
-            &bytes[offset..],
-            header.ancount as usize,
-        );
+    /// Decodes a whole message. Fails on anything malformed, such as
+    /// section counts that promise more records than the message holds or
+    /// RDATA that runs past its end.
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let header = Header::decode(bytes)?;
+        // A question takes at least 5 bytes and a record at least 11, so
+        // counts the message cannot hold are rejected before any section
+        // is decoded.
+        let records = header.ancount as usize + header.nscount as usize + header.arcount as usize;
+        if 12 + header.qdcount as usize * 5 + records * 11 > bytes.len() {
+            return Err(anyhow!("Section counts exceed the message length"));
+        }
+        let (questions, offset) = Question::decode(bytes, header.qdcount as usize, 12)?;
+        let (answers, offset) = Answer::decode(bytes, header.ancount as usize, offset)?;
+        let (authorities, offset) = Answer::decode(bytes, header.nscount as usize, offset)?;
+        let (additionals, _) = Answer::decode(bytes, header.arcount as usize, offset)?;
 
+        Ok(Message {
+            header,
+            questions,
+            answers,
+            authorities,
+            additionals,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{encode_name, rtype, RData};
+
+    fn response() -> Vec<u8> {
+        let answer = Answer::builder()
+            .name_bytes(encode_name("www.example.com"))
+            .unwrap()
+            .rdata(&RData::Mx { preference: 10, exchange: encode_name("mail.example.com") })
+            .unwrap()
+            .qtype(rtype::MX)
+            .unwrap()
+            .build();
         Message {
-            header: header,
-            questions: questions,
-            answers: answers
+            header: Header::builder().id(1).unwrap().qr(true).unwrap().qdcount(1).unwrap().ancount(1).unwrap().build(),
+            questions: vec![Question { name: encode_name("www.example.com"), qtype: rtype::MX, qclass: rtype::CLASS_IN }],
+            answers: vec![answer],
+            authorities: vec![],
+            additionals: vec![],
+        }
+        .encode()
+    }
+
+    // A query header with one question, followed by `question`.
+    fn query(question: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0, 1, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+        bytes.extend(question);
+        bytes
+    }
+
+    #[test]
+    fn decodes_what_it_encodes() {
+        let message = Message::decode(&response()).unwrap();
+        assert_eq!(message.answers.len(), 1);
+        assert_eq!(
+            message.answers[0].rdata().unwrap(),
+            RData::Mx { preference: 10, exchange: encode_name("mail.example.com") }
+        );
+    }
+
+    #[test]
+    fn rejects_every_truncation() {
+        let bytes = response();
+        for length in 0..bytes.len() {
+            assert!(Message::decode(&bytes[..length]).is_err(), "decoded {} of {} bytes", length, bytes.len());
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn rejects_counts_the_message_cannot_hold() {
+        let mut bytes = response();
+        bytes[10..12].copy_from_slice(&[0xFF, 0xFF]);
+        assert!(Message::decode(&bytes).is_err());
+    }
+
+    #[test]
+    fn rejects_pointer_loops() {
+        // A pointer to itself.
+        assert!(Message::decode(&query(&[0xC0, 12, 0, 1, 0, 1])).is_err());
+        // Two pointers to each other.
+        assert!(Message::decode(&query(&[0xC0, 14, 0xC0, 12, 0, 1, 0, 1])).is_err());
+        // A label followed by a pointer back to it, which would grow the
+        // name forever.
+        assert!(Message::decode(&query(&[1, b'a', 0xC0, 12, 0, 1, 0, 1])).is_err());
+        // A pointer past the end of the message.
+        assert!(Message::decode(&query(&[0xC0, 0xFF, 0, 1, 0, 1])).is_err());
+    }
+}
@@ -1,9 +1,17 @@
+#[allow(clippy::module_inception)]
 pub mod message;
 pub mod header;
 pub mod question;
 pub mod answer;
+pub mod name;
+pub mod rdata;
+pub mod rtype;
+pub mod edns;
 
 pub use message::*;
 pub use header::*;
 pub use question::*;
-pub use answer::*;
\ No newline at end of file
+pub use answer::*;
+pub use name::*;
+pub use rdata::*;
+pub use edns::*;
\ No newline at end of file
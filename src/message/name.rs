@@ -0,0 +1,284 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use anyhow::{Result, anyhow};
+
+// Names are kept in wire form without the terminating zero byte, which is
+// what `Question::encode` and `Answer::encode` append.
+
+pub const MAX_NAME_LEN: usize = 255;
+
+pub fn encode_name(name: &str) -> Vec<u8> {
+    let mut bytes = vec![];
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() {
+            continue;
+        }
+        bytes.push(label.len() as u8);
+        bytes.extend(label.as_bytes());
+    }
+    bytes
+}
+
+pub fn name_to_string(name: &[u8]) -> String {
+    if name.is_empty() {
+        return ".".to_owned();
+    }
+    labels(name)
+        .iter()
+        .map(|label| String::from_utf8_lossy(label).into_owned())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+pub fn labels(name: &[u8]) -> Vec<&[u8]> {
+    let mut labels = vec![];
+    let mut cur = 0;
+    while cur < name.len() {
+        let len = name[cur] as usize;
+        labels.push(&name[cur + 1..cur + 1 + len]);
+        cur += len + 1;
+    }
+    labels
+}
+
+pub fn label_count(name: &[u8]) -> usize {
+    labels(name).len()
+}
+
+pub fn lowercase_name(name: &[u8]) -> Vec<u8> {
+    name.to_ascii_lowercase()
+}
+
+pub fn names_equal(a: &[u8], b: &[u8]) -> bool {
+    a.eq_ignore_ascii_case(b)
+}
+
+pub fn parent_name(name: &[u8]) -> Option<Vec<u8>> {
+    if name.is_empty() {
+        return None;
+    }
+    let len = name[0] as usize;
+    Some(name[len + 1..].to_vec())
+}
+
+pub fn is_subdomain(name: &[u8], ancestor: &[u8]) -> bool {
+    let mut cur = name.to_vec();
+    loop {
+        if names_equal(&cur, ancestor) {
+            return true;
+        }
+        match parent_name(&cur) {
+            Some(parent) => cur = parent,
+            None => return false,
+        }
+    }
+}
+
+/// The last `count` labels of `name`.
+pub fn name_suffix(name: &[u8], count: usize) -> Vec<u8> {
+    let mut name = name.to_vec();
+    while label_count(&name) > count {
+        name = parent_name(&name).unwrap();
+    }
+    name
+}
+
+/// The wildcard name `*.<parent>`.
+pub fn wildcard_name(parent: &[u8]) -> Vec<u8> {
+    let mut wildcard = vec![1, b'*'];
+    wildcard.extend(parent);
+    wildcard
+}
+
+pub fn is_wildcard(name: &[u8]) -> bool {
+    name.starts_with(&[1, b'*'])
+}
+
+/// The reverse mapping name of an address: `d.c.b.a.in-addr.arpa` for
+/// IPv4 (RFC 1035 section 3.5), one label per nibble under `ip6.arpa` for
+/// IPv6 (RFC 3596 section 2.5).
+pub fn reverse_name(addr: IpAddr) -> Vec<u8> {
+    let mut labels: Vec<String> = match addr {
+        IpAddr::V4(addr) => addr.octets().iter().rev().map(|octet| octet.to_string()).collect(),
+        IpAddr::V6(addr) => addr
+            .octets()
+            .iter()
+            .rev()
+            .flat_map(|byte| [byte & 0x0F, byte >> 4])
+            .map(|nibble| format!("{:x}", nibble))
+            .collect(),
+    };
+    labels.push(if addr.is_ipv4() { "in-addr.arpa" } else { "ip6.arpa" }.to_owned());
+    encode_name(&labels.join("."))
+}
+
+/// The address a reverse mapping name stands for. `None` for names outside
+/// `in-addr.arpa` and `ip6.arpa` and for names of partial addresses.
+pub fn reverse_name_to_ip(name: &[u8]) -> Option<IpAddr> {
+    let labels: Vec<String> = labels(name)
+        .iter()
+        .map(|label| String::from_utf8_lossy(label).to_ascii_lowercase())
+        .collect();
+    match labels.iter().map(|label| label.as_str()).collect::<Vec<_>>().as_slice() {
+        [d, c, b, a, "in-addr", "arpa"] => {
+            let mut octets = [0; 4];
+            for (octet, label) in octets.iter_mut().zip([a, b, c, d]) {
+                // Leading zeros would give one address several names.
+                if label.len() > 1 && label.starts_with('0') {
+                    return None;
+                }
+                *octet = label.parse().ok()?;
+            }
+            Some(IpAddr::V4(Ipv4Addr::from(octets)))
+        }
+        [nibbles @ .., "ip6", "arpa"] if nibbles.len() == 32 => {
+            let mut octets = [0; 16];
+            for (i, label) in nibbles.iter().rev().enumerate() {
+                if label.len() != 1 {
+                    return None;
+                }
+                let nibble = u8::from_str_radix(label, 16).ok()?;
+                octets[i / 2] |= if i % 2 == 0 { nibble << 4 } else { nibble };
+            }
+            Some(IpAddr::V6(Ipv6Addr::from(octets)))
+        }
+        _ => None,
+    }
+}
+
+/// Canonical DNS name order (RFC 4034 section 6.1): names are compared
+/// label by label from the root, each label as lowercased bytes, and a name
+/// sorts before any of its subdomains.
+pub fn canonical_cmp(a: &[u8], b: &[u8]) -> Ordering {
+    let a_labels = labels(a);
+    let b_labels = labels(b);
+    for (a_label, b_label) in a_labels.iter().rev().zip(b_labels.iter().rev()) {
+        let order = a_label.to_ascii_lowercase().cmp(&b_label.to_ascii_lowercase());
+        if order != Ordering::Equal {
+            return order;
+        }
+    }
+    a_labels.len().cmp(&b_labels.len())
+}
+
+/// Reads a possibly compressed name starting at `offset` in a full message.
+/// Returns the name and the offset just past it in the original stream.
+pub fn read_name(message: &[u8], offset: usize) -> Result<(Vec<u8>, usize)> {
+    let mut name = vec![];
+    let mut cur = offset;
+    let mut end = None;
+    let mut jumps = 0;
+
+    loop {
+        let len = *message
+            .get(cur)
+            .ok_or_else(|| anyhow!("Name runs past end of message"))? as usize;
+        match len >> 6 {
+            0b00 => {
+                if len == 0 {
+                    cur += 1;
+                    break;
+                }
+                let label = message
+                    .get(cur..=cur + len)
+                    .ok_or_else(|| anyhow!("Label runs past end of message"))?;
+                name.extend(label);
+                if name.len() + 1 > MAX_NAME_LEN {
+                    return Err(anyhow!("Name exceeds {} bytes", MAX_NAME_LEN));
+                }
+                cur += len + 1;
+            }
+            0b11 => {
+                let low = *message
+                    .get(cur + 1)
+                    .ok_or_else(|| anyhow!("Pointer runs past end of message"))?;
+                if end.is_none() {
+                    end = Some(cur + 2);
+                }
+                jumps += 1;
+                if jumps > 64 {
+                    return Err(anyhow!("Too many compression pointers"));
+                }
+                cur = ((len & 0x3f) << 8) | low as usize;
+            }
+            _ => return Err(anyhow!("Not a label or pointer")),
+        }
+    }
+
+    Ok((name, end.unwrap_or(cur)))
+}
+
+/// Remembers where names were written into a message so that later
+/// occurrences can be replaced by pointers (RFC 1035 section 4.1.4).
+#[derive(Debug, Default)]
+pub struct NameCompressor {
+    offsets: HashMap<Vec<u8>, usize>,
+}
+
+impl NameCompressor {
+    pub fn new() -> NameCompressor {
+        NameCompressor {
+            offsets: HashMap::new(),
+        }
+    }
+
+    /// Appends `name` to `bytes`, which must hold the message from its first
+    /// byte so that recorded offsets are absolute.
+    pub fn write_name(&mut self, bytes: &mut Vec<u8>, name: &[u8]) {
+        let mut cur = 0;
+        while cur < name.len() {
+            let suffix = lowercase_name(&name[cur..]);
+            if let Some(&offset) = self.offsets.get(&suffix) {
+                bytes.extend((0xC000 | offset as u16).to_be_bytes());
+                return;
+            }
+            // Pointers only have 14 bits of offset.
+            if bytes.len() < 0x4000 {
+                self.offsets.insert(suffix, bytes.len());
+            }
+            let len = name[cur] as usize;
+            bytes.extend(&name[cur..=cur + len]);
+            cur += len + 1;
+        }
+        bytes.push(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reverse_names_round_trip() {
+        for addr in ["192.0.2.1", "0.0.0.0", "255.255.255.255", "2001:db8::1", "::", "fe80::1:2:3:4"] {
+            let addr: IpAddr = addr.parse().unwrap();
+            assert_eq!(reverse_name_to_ip(&reverse_name(addr)), Some(addr));
+        }
+        assert_eq!(name_to_string(&reverse_name("192.0.2.1".parse().unwrap())), "1.2.0.192.in-addr.arpa");
+        let v6 = name_to_string(&reverse_name("2001:db8::1".parse().unwrap()));
+        assert_eq!(v6, format!("1.{}8.b.d.0.1.0.0.2.ip6.arpa", "0.".repeat(23)));
+    }
+
+    #[test]
+    fn reverse_name_to_ip_rejects_other_names() {
+        for name in [
+            "2.0.192.in-addr.arpa",
+            "01.2.0.192.in-addr.arpa",
+            "256.2.0.192.in-addr.arpa",
+            "x.2.0.192.in-addr.arpa",
+            "1.2.0.192.in-addr.example",
+            "1.0.ip6.arpa",
+            "example.com",
+        ] {
+            assert_eq!(reverse_name_to_ip(&encode_name(name)), None, "{}", name);
+        }
+        let long_nibble = format!("10.{}ip6.arpa", "0.".repeat(31));
+        assert_eq!(reverse_name_to_ip(&encode_name(&long_nibble)), None);
+        assert_eq!(
+            reverse_name_to_ip(&encode_name("1.2.0.192.IN-ADDR.ARPA")),
+            Some("192.0.2.1".parse().unwrap())
+        );
+    }
+}
@@ -1,6 +1,8 @@
-use std::collections::HashMap;
+use std::net::IpAddr;
 
-use anyhow::{Result, Ok};
+use anyhow::{anyhow, Result, Ok};
+
+use crate::message::{read_name, reverse_name, reverse_name_to_ip, rtype, NameCompressor};
 
 #[derive(Debug)]
 #[derive(Clone)]
@@ -23,166 +25,44 @@ impl Question {
         bytes
     }
 
+    pub fn encode_compressed(&self, bytes: &mut Vec<u8>, compressor: &mut NameCompressor) {
+        compressor.write_name(bytes, &self.name);
+        bytes.extend(self.qtype.to_be_bytes());
+        bytes.extend(self.qclass.to_be_bytes());
+    }
+
     pub fn builder() -> QuestionBuilder {
         QuestionBuilder::new()
     }
 
-    pub fn decode(bytes: &[u8], qncount: usize, header_offset: usize) -> (Vec<Question>, usize) {
-        #[derive(Debug)]
-        enum LabelOrPointer {
-            Label(Vec<u8>),
-            Pointer(usize),
-            QType(Vec<u8>),
-            QClass(Vec<u8>),
-            Stop,
-        }
-
-        let mut tokens: Vec<LabelOrPointer> = vec![];
-        let mut cur = 0;
-        let mut cnt = 0;
-        let mut label_index = HashMap::new();
-
-        while cnt < qncount {
-            loop {
-                if bytes[cur] == 0 {
-                    tokens.push(LabelOrPointer::Stop);
-                    cur += 1;
-                    tokens.push(
-                        LabelOrPointer::QType(
-                            bytes[cur .. cur + 2].to_vec()
-                        )
-                    );
-                    tokens.push(
-                        LabelOrPointer::QClass(
-                            bytes[cur + 2 .. cur + 4].to_vec()
-                        )
-                    );
-                    cur += 4;
-                    break;
-                }
-                let head = (bytes[cur] >> 6) & 0b11;
-                if head == 0b00 {
-                    label_index.insert(cur + header_offset, tokens.len());
-                    let len = bytes[cur] as usize;
-                    tokens.push(
-                        LabelOrPointer::Label(
-                            bytes[cur..=cur+len].to_vec()
-                        )
-                    );
-                    cur += len + 1;
-                }
-                else if head == 0b11 {
-                    let pointer = (
-                        (
-                            (bytes[cur] & 0x3f) as u16
-                        ) << 8
-                    ) 
-                    | bytes[cur+1] as u16;
-                    tokens.push(
-                        LabelOrPointer::Pointer(pointer as usize)
-                    );
-                    cur += 2;
-                    tokens.push(
-                        LabelOrPointer::QType(
-                            bytes[cur .. cur + 2].to_vec()
-                        )
-                    );
-                    tokens.push(
-                        LabelOrPointer::QClass(
-                            bytes[cur + 2 .. cur + 4].to_vec()
-                        )
-                    );
-                    cur += 4;
-                    break;
-                }
-                else {
-                    panic!("Not a label or pointer");
-                }
-            }
-            cnt += 1;
-        }
-
-        // println!("{:#?}", tokens);
-        // println!("{:#?}", label_index);
-
-        let mut next = 0;
-
+    /// Decodes `qdcount` questions starting at `offset` in a full message,
+    /// expanding compression pointers. Returns the questions and the offset
+    /// just past them.
+    pub fn decode(bytes: &[u8], qdcount: usize, offset: usize) -> Result<(Vec<Question>, usize)> {
+        let mut cur = offset;
         let mut questions = vec![];
-        for _ in 0..qncount {
-            let mut name: Vec<u8> = vec![];
-            let mut q_class: Option<Vec<u8>> = None;
-            let mut q_type: Option<Vec<u8>> = None;
-
-            let mut jumped = false;
-            let mut cursor = next;
-            loop {
-                match tokens[cursor] {
-                    LabelOrPointer::Label(ref label) => {
-                        name.extend(label);
-                        cursor += 1;
-                        if !jumped {
-                            next = cursor;
-                        }
-                    }
-                    LabelOrPointer::Pointer(pointer) => {
-                        if !jumped {
-                            next = cursor + 1;
-                            q_type = Some(
-                                if let LabelOrPointer::QType(ref q_type) = tokens[next] {
-                                    q_type.clone()
-                                } else {
-                                    panic!("Expect a QType after Pointer, got {:?}", tokens[next]);
-                                }
-                            );
-
-                            q_class = Some(
-                                if let LabelOrPointer::QClass(ref q_class) = tokens[next + 1] {
-                                    q_class.clone()
-                                } else {
-                                    panic!("Expect a QClass after Pointer, got {:?}", tokens[next + 1]);
-                                }
-                            );
-                            next += 2;
-                            jumped = true;
-                        }
-                        cursor = *label_index.get(&pointer).unwrap();
-                    }
-                    LabelOrPointer::Stop => {
-                        next += 1;
-                        if !jumped {
-                            q_type = Some(
-                                if let LabelOrPointer::QType(ref q_type) = tokens[next] {
-                                    q_type.clone()
-                                } else {
-                                    panic!("Expect a QType after Stop, got {:?}", tokens[next]);
-                                }
-                            );
-                            q_class = Some(
-                                if let LabelOrPointer::QClass(ref q_class) = tokens[next + 1] {
-                                    q_class.clone()
-                                } else {
-                                    panic!("Expect a QClass after Stop, got {:?}", tokens[next + 1]);
-                                }
-                            );
-                            next += 2;
-                        }
-                        break;
-                    }
-                    _ => {
-                        panic!("Not a label or pointer");
-                    }
-                }
-            }
+        for _ in 0..qdcount {
+            let (name, next) = read_name(bytes, cur)?;
+            let fields = bytes
+                .get(next..next + 4)
+                .ok_or_else(|| anyhow!("Question runs past end of message"))?;
             questions.push(Question {
                 name,
-                qtype: u16::from_be_bytes(q_type.unwrap().try_into().unwrap()),
-                qclass: u16::from_be_bytes(q_class.unwrap().try_into().unwrap()),
+                qtype: u16::from_be_bytes([fields[0], fields[1]]),
+                qclass: u16::from_be_bytes([fields[2], fields[3]]),
             });
+            cur = next + 4;
         }
+        Ok((questions, cur))
+    }
 
-        (questions, cur)
+    /// The address a PTR question asks about, if its name is a reverse
+    /// mapping name.
+    pub fn reverse_address(&self) -> Option<IpAddr> {
+        reverse_name_to_ip(&self.name)
     }
 
+    #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> usize {
         self.name.len() + 6
     }
@@ -194,6 +74,12 @@ pub struct QuestionBuilder {
     qclass: Option<u16>,
 }
 
+impl Default for QuestionBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[allow(dead_code)]
 impl QuestionBuilder {
     pub fn new() -> Self {
@@ -219,6 +105,13 @@ impl QuestionBuilder {
         Ok(self)
     }
 
+    /// Asks for the PTR record of `addr`.
+    pub fn reverse(mut self, addr: IpAddr) -> Self {
+        self.name = Some(reverse_name(addr));
+        self.qtype = Some(rtype::PTR);
+        self
+    }
+
     pub fn qtype(mut self, qtype: u16) -> Self {
         self.qtype = Some(qtype);
         self
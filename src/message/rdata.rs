@@ -0,0 +1,801 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use anyhow::{Result, anyhow};
+use base64::Engine;
+
+use crate::message::{encode_name, lowercase_name, read_name, rtype, NameCompressor};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Ns(Vec<u8>),
+    Cname(Vec<u8>),
+    Ptr(Vec<u8>),
+    Mx {
+        preference: u16,
+        exchange: Vec<u8>,
+    },
+    Txt(Vec<Vec<u8>>),
+    Soa {
+        mname: Vec<u8>,
+        rname: Vec<u8>,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        minimum: u32,
+    },
+    Srv {
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: Vec<u8>,
+    },
+    Dname(Vec<u8>),
+    Tsig {
+        algorithm: Vec<u8>,
+        // 48-bit seconds since the epoch.
+        time_signed: u64,
+        fudge: u16,
+        mac: Vec<u8>,
+        original_id: u16,
+        error: u16,
+        other: Vec<u8>,
+    },
+    Ds {
+        key_tag: u16,
+        algorithm: u8,
+        digest_type: u8,
+        digest: Vec<u8>,
+    },
+    Rrsig {
+        type_covered: u16,
+        algorithm: u8,
+        labels: u8,
+        original_ttl: u32,
+        // Seconds since the epoch, compared in serial number arithmetic.
+        expiration: u32,
+        inception: u32,
+        key_tag: u16,
+        signer: Vec<u8>,
+        signature: Vec<u8>,
+    },
+    Nsec {
+        next: Vec<u8>,
+        types: Vec<u16>,
+    },
+    Dnskey {
+        flags: u16,
+        protocol: u8,
+        algorithm: u8,
+        public_key: Vec<u8>,
+    },
+    Nsec3 {
+        hash_algorithm: u8,
+        flags: u8,
+        iterations: u16,
+        salt: Vec<u8>,
+        // The raw hash of the next owner, not its base32hex label.
+        next_hashed: Vec<u8>,
+        types: Vec<u16>,
+    },
+    Nsec3Param {
+        hash_algorithm: u8,
+        flags: u8,
+        iterations: u16,
+        salt: Vec<u8>,
+    },
+    Unknown(Vec<u8>),
+}
+
+fn read_u16(bytes: &[u8], cur: usize) -> Result<u16> {
+    let slice = bytes
+        .get(cur..cur + 2)
+        .ok_or_else(|| anyhow!("RDATA too short"))?;
+    Ok(u16::from_be_bytes(slice.try_into().unwrap()))
+}
+
+fn read_u32(bytes: &[u8], cur: usize) -> Result<u32> {
+    let slice = bytes
+        .get(cur..cur + 4)
+        .ok_or_else(|| anyhow!("RDATA too short"))?;
+    Ok(u32::from_be_bytes(slice.try_into().unwrap()))
+}
+
+fn read_u8(bytes: &[u8], cur: usize) -> Result<u8> {
+    bytes.get(cur).copied().ok_or_else(|| anyhow!("RDATA too short"))
+}
+
+fn read_bytes(bytes: &[u8], cur: usize, len: usize) -> Result<Vec<u8>> {
+    bytes
+        .get(cur..cur + len)
+        .map(|slice| slice.to_vec())
+        .ok_or_else(|| anyhow!("RDATA too short"))
+}
+
+fn push_name(bytes: &mut Vec<u8>, name: &[u8]) {
+    bytes.extend(name);
+    bytes.push(0);
+}
+
+/// Decodes the window blocks of an NSEC/NSEC3 type bitmap
+/// (RFC 4034 section 4.1.2).
+fn decode_type_bitmap(data: &[u8]) -> Result<Vec<u16>> {
+    let mut types = vec![];
+    let mut cur = 0;
+    while cur < data.len() {
+        let window = read_u8(data, cur)? as u16;
+        let len = read_u8(data, cur + 1)? as usize;
+        if len == 0 || len > 32 {
+            return Err(anyhow!("Invalid type bitmap length {}", len));
+        }
+        let bitmap = read_bytes(data, cur + 2, len)?;
+        for (index, byte) in bitmap.iter().enumerate() {
+            for bit in 0..8 {
+                if byte & (0x80 >> bit) != 0 {
+                    types.push((window << 8) | (index * 8 + bit) as u16);
+                }
+            }
+        }
+        cur += 2 + len;
+    }
+    Ok(types)
+}
+
+fn encode_type_bitmap(bytes: &mut Vec<u8>, types: &[u16]) {
+    let mut types = types.to_vec();
+    types.sort_unstable();
+    types.dedup();
+    let mut cur = 0;
+    while cur < types.len() {
+        let window = types[cur] >> 8;
+        let mut bitmap = [0u8; 32];
+        let mut len = 0;
+        while cur < types.len() && types[cur] >> 8 == window {
+            let low = (types[cur] & 0xFF) as usize;
+            bitmap[low / 8] |= 0x80 >> (low % 8);
+            len = low / 8 + 1;
+            cur += 1;
+        }
+        bytes.push(window as u8);
+        bytes.push(len as u8);
+        bytes.extend(&bitmap[..len]);
+    }
+}
+
+const BASE32HEX: &[u8; 32] = b"0123456789ABCDEFGHIJKLMNOPQRSTUV";
+
+/// Decodes base32 with the extended hex alphabet, as used for NSEC3 hashes
+/// (RFC 5155 section 3.3).
+pub fn base32hex_decode(text: &str) -> Result<Vec<u8>> {
+    let mut bytes = vec![];
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in text.trim_end_matches('=').bytes() {
+        let value = BASE32HEX
+            .iter()
+            .position(|digit| *digit == c.to_ascii_uppercase())
+            .ok_or_else(|| anyhow!("Invalid base32hex digit {:?}", c as char))?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    Ok(bytes)
+}
+
+/// Encodes bytes as unpadded base32hex, the form of NSEC3 owner labels.
+pub fn base32hex_encode(bytes: &[u8]) -> String {
+    let mut text = String::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for byte in bytes {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            text.push(BASE32HEX[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        text.push(BASE32HEX[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    text
+}
+
+fn hex_decode(text: &str) -> Result<Vec<u8>> {
+    text.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .filter(|digits| digits.len() == 2)
+                .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                .ok_or_else(|| anyhow!("Invalid hex string {}", text))
+        })
+        .collect()
+}
+
+/// Parses an RRSIG timestamp, either `YYYYMMDDHHmmSS` in UTC or plain
+/// seconds since the epoch (RFC 4034 section 3.2).
+fn parse_timestamp(text: &str) -> Result<u32> {
+    if text.len() != 14 {
+        return text.parse().map_err(|_| anyhow!("Invalid timestamp {}", text));
+    }
+    let part = |range: std::ops::Range<usize>| -> Result<i64> {
+        text.get(range)
+            .and_then(|digits| digits.parse().ok())
+            .ok_or_else(|| anyhow!("Invalid timestamp {}", text))
+    };
+    let (year, month, day) = (part(0..4)?, part(4..6)?, part(6..8)?);
+    // Days since the epoch for a civil date, after Howard Hinnant's
+    // days_from_civil.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    let seconds = days * 86400 + part(8..10)? * 3600 + part(10..12)? * 60 + part(12..14)?;
+    // Timestamps wrap modulo 2^32.
+    Ok(seconds as u32)
+}
+
+/// Resolves a name token from a zone file against the current origin.
+pub fn absolute_name(token: &str, origin: &[u8]) -> Vec<u8> {
+    if token == "@" {
+        return origin.to_vec();
+    }
+    let mut name = encode_name(token);
+    if !token.ends_with('.') {
+        name.extend(origin);
+    }
+    name
+}
+
+impl RData {
+    /// Decodes RDATA found at `offset` in a full message, expanding any
+    /// compression pointers so the result can be re-encoded on its own.
+    pub fn decode(rtype: u16, message: &[u8], offset: usize, length: usize) -> Result<RData> {
+        let end = offset + length;
+        let data = message
+            .get(offset..end)
+            .ok_or_else(|| anyhow!("RDATA runs past end of message"))?;
+        // Each type also yields where its fields ended, which has to be
+        // RDLENGTH: embedded names are read from the whole message and
+        // could otherwise run on into the next record.
+        let (rdata, parsed_end) = match rtype {
+            rtype::A => {
+                let octets: [u8; 4] = data.try_into().map_err(|_| anyhow!("A record must be 4 bytes"))?;
+                (RData::A(Ipv4Addr::from(octets)), end)
+            }
+            rtype::AAAA => {
+                let octets: [u8; 16] = data.try_into().map_err(|_| anyhow!("AAAA record must be 16 bytes"))?;
+                (RData::Aaaa(Ipv6Addr::from(octets)), end)
+            }
+            rtype::NS => {
+                let (name, cur) = read_name(message, offset)?;
+                (RData::Ns(name), cur)
+            }
+            rtype::CNAME => {
+                let (name, cur) = read_name(message, offset)?;
+                (RData::Cname(name), cur)
+            }
+            rtype::PTR => {
+                let (name, cur) = read_name(message, offset)?;
+                (RData::Ptr(name), cur)
+            }
+            rtype::MX => {
+                let preference = read_u16(data, 0)?;
+                let (exchange, cur) = read_name(message, offset + 2)?;
+                (RData::Mx { preference, exchange }, cur)
+            }
+            rtype::TXT => {
+                let mut strings = vec![];
+                let mut cur = 0;
+                while cur < data.len() {
+                    let len = data[cur] as usize;
+                    let string = data
+                        .get(cur + 1..cur + 1 + len)
+                        .ok_or_else(|| anyhow!("TXT string runs past RDATA"))?;
+                    strings.push(string.to_vec());
+                    cur += len + 1;
+                }
+                (RData::Txt(strings), end)
+            }
+            rtype::SOA => {
+                let (mname, cur) = read_name(message, offset)?;
+                let (rname, cur) = read_name(message, cur)?;
+                let soa = RData::Soa {
+                    mname,
+                    rname,
+                    serial: read_u32(message, cur)?,
+                    refresh: read_u32(message, cur + 4)?,
+                    retry: read_u32(message, cur + 8)?,
+                    expire: read_u32(message, cur + 12)?,
+                    minimum: read_u32(message, cur + 16)?,
+                };
+                (soa, cur + 20)
+            }
+            rtype::SRV => {
+                let (target, cur) = read_name(message, offset + 6)?;
+                let srv = RData::Srv {
+                    priority: read_u16(data, 0)?,
+                    weight: read_u16(data, 2)?,
+                    port: read_u16(data, 4)?,
+                    target,
+                };
+                (srv, cur)
+            }
+            rtype::DNAME => {
+                let (name, cur) = read_name(message, offset)?;
+                (RData::Dname(name), cur)
+            }
+            rtype::TSIG => {
+                let (algorithm, cur) = read_name(message, offset)?;
+                let time = message
+                    .get(cur..cur + 6)
+                    .ok_or_else(|| anyhow!("RDATA too short"))?;
+                let time_signed = time.iter().fold(0u64, |acc, byte| (acc << 8) | *byte as u64);
+                let fudge = read_u16(message, cur + 6)?;
+                let mac_len = read_u16(message, cur + 8)? as usize;
+                let mac_end = cur + 10 + mac_len;
+                let mac = message
+                    .get(cur + 10..mac_end)
+                    .ok_or_else(|| anyhow!("RDATA too short"))?
+                    .to_vec();
+                let other_len = read_u16(message, mac_end + 4)? as usize;
+                let tsig = RData::Tsig {
+                    algorithm,
+                    time_signed,
+                    fudge,
+                    mac,
+                    original_id: read_u16(message, mac_end)?,
+                    error: read_u16(message, mac_end + 2)?,
+                    other: message
+                        .get(mac_end + 6..mac_end + 6 + other_len)
+                        .ok_or_else(|| anyhow!("RDATA too short"))?
+                        .to_vec(),
+                };
+                (tsig, mac_end + 6 + other_len)
+            }
+            rtype::DS => {
+                let ds = RData::Ds {
+                    key_tag: read_u16(data, 0)?,
+                    algorithm: read_u8(data, 2)?,
+                    digest_type: read_u8(data, 3)?,
+                    digest: read_bytes(data, 4, data.len().saturating_sub(4))?,
+                };
+                (ds, end)
+            }
+            rtype::RRSIG => {
+                let (signer, cur) = read_name(message, offset + 18)?;
+                if cur > end {
+                    return Err(anyhow!("RRSIG signer runs past RDATA"));
+                }
+                let rrsig = RData::Rrsig {
+                    type_covered: read_u16(data, 0)?,
+                    algorithm: read_u8(data, 2)?,
+                    labels: read_u8(data, 3)?,
+                    original_ttl: read_u32(data, 4)?,
+                    expiration: read_u32(data, 8)?,
+                    inception: read_u32(data, 12)?,
+                    key_tag: read_u16(data, 16)?,
+                    signer,
+                    signature: read_bytes(message, cur, end - cur)?,
+                };
+                (rrsig, end)
+            }
+            rtype::NSEC => {
+                let (next, cur) = read_name(message, offset)?;
+                let types = message
+                    .get(cur..end)
+                    .ok_or_else(|| anyhow!("NSEC next name runs past RDATA"))?;
+                (RData::Nsec { next, types: decode_type_bitmap(types)? }, end)
+            }
+            rtype::DNSKEY => {
+                let dnskey = RData::Dnskey {
+                    flags: read_u16(data, 0)?,
+                    protocol: read_u8(data, 2)?,
+                    algorithm: read_u8(data, 3)?,
+                    public_key: read_bytes(data, 4, data.len().saturating_sub(4))?,
+                };
+                (dnskey, end)
+            }
+            rtype::NSEC3 => {
+                let salt_len = read_u8(data, 4)? as usize;
+                let hash_at = 5 + salt_len;
+                let hash_len = read_u8(data, hash_at)? as usize;
+                let types_at = hash_at + 1 + hash_len;
+                let nsec3 = RData::Nsec3 {
+                    hash_algorithm: read_u8(data, 0)?,
+                    flags: read_u8(data, 1)?,
+                    iterations: read_u16(data, 2)?,
+                    salt: read_bytes(data, 5, salt_len)?,
+                    next_hashed: read_bytes(data, hash_at + 1, hash_len)?,
+                    types: decode_type_bitmap(data.get(types_at..).unwrap_or_default())?,
+                };
+                (nsec3, end)
+            }
+            rtype::NSEC3PARAM => {
+                let salt_len = read_u8(data, 4)? as usize;
+                let nsec3param = RData::Nsec3Param {
+                    hash_algorithm: read_u8(data, 0)?,
+                    flags: read_u8(data, 1)?,
+                    iterations: read_u16(data, 2)?,
+                    salt: read_bytes(data, 5, salt_len)?,
+                };
+                (nsec3param, offset + 5 + salt_len)
+            }
+            _ => (RData::Unknown(data.to_vec()), end),
+        };
+        if parsed_end != end {
+            return Err(anyhow!("RDATA of type {} does not match its length {}", rtype, length));
+        }
+        Ok(rdata)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        match self {
+            RData::A(addr) => bytes.extend(addr.octets()),
+            RData::Aaaa(addr) => bytes.extend(addr.octets()),
+            RData::Ns(name) | RData::Cname(name) | RData::Ptr(name) | RData::Dname(name) => {
+                push_name(&mut bytes, name)
+            }
+            RData::Mx { preference, exchange } => {
+                bytes.extend(preference.to_be_bytes());
+                push_name(&mut bytes, exchange);
+            }
+            RData::Txt(strings) => {
+                for string in strings {
+                    bytes.push(string.len() as u8);
+                    bytes.extend(string);
+                }
+            }
+            RData::Soa { mname, rname, serial, refresh, retry, expire, minimum } => {
+                push_name(&mut bytes, mname);
+                push_name(&mut bytes, rname);
+                for value in [serial, refresh, retry, expire, minimum] {
+                    bytes.extend(value.to_be_bytes());
+                }
+            }
+            RData::Srv { priority, weight, port, target } => {
+                bytes.extend(priority.to_be_bytes());
+                bytes.extend(weight.to_be_bytes());
+                bytes.extend(port.to_be_bytes());
+                push_name(&mut bytes, target);
+            }
+            RData::Tsig { algorithm, time_signed, fudge, mac, original_id, error, other } => {
+                push_name(&mut bytes, algorithm);
+                bytes.extend(&time_signed.to_be_bytes()[2..]);
+                bytes.extend(fudge.to_be_bytes());
+                bytes.extend((mac.len() as u16).to_be_bytes());
+                bytes.extend(mac);
+                bytes.extend(original_id.to_be_bytes());
+                bytes.extend(error.to_be_bytes());
+                bytes.extend((other.len() as u16).to_be_bytes());
+                bytes.extend(other);
+            }
+            RData::Ds { key_tag, algorithm, digest_type, digest } => {
+                bytes.extend(key_tag.to_be_bytes());
+                bytes.push(*algorithm);
+                bytes.push(*digest_type);
+                bytes.extend(digest);
+            }
+            RData::Rrsig {
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                signer,
+                signature,
+            } => {
+                bytes.extend(type_covered.to_be_bytes());
+                bytes.push(*algorithm);
+                bytes.push(*labels);
+                bytes.extend(original_ttl.to_be_bytes());
+                bytes.extend(expiration.to_be_bytes());
+                bytes.extend(inception.to_be_bytes());
+                bytes.extend(key_tag.to_be_bytes());
+                push_name(&mut bytes, signer);
+                bytes.extend(signature);
+            }
+            RData::Nsec { next, types } => {
+                push_name(&mut bytes, next);
+                encode_type_bitmap(&mut bytes, types);
+            }
+            RData::Dnskey { flags, protocol, algorithm, public_key } => {
+                bytes.extend(flags.to_be_bytes());
+                bytes.push(*protocol);
+                bytes.push(*algorithm);
+                bytes.extend(public_key);
+            }
+            RData::Nsec3 { hash_algorithm, flags, iterations, salt, next_hashed, types } => {
+                bytes.push(*hash_algorithm);
+                bytes.push(*flags);
+                bytes.extend(iterations.to_be_bytes());
+                bytes.push(salt.len() as u8);
+                bytes.extend(salt);
+                bytes.push(next_hashed.len() as u8);
+                bytes.extend(next_hashed);
+                encode_type_bitmap(&mut bytes, types);
+            }
+            RData::Nsec3Param { hash_algorithm, flags, iterations, salt } => {
+                bytes.push(*hash_algorithm);
+                bytes.push(*flags);
+                bytes.extend(iterations.to_be_bytes());
+                bytes.push(salt.len() as u8);
+                bytes.extend(salt);
+            }
+            RData::Unknown(data) => bytes.extend(data),
+        }
+        bytes
+    }
+
+    /// The RDATA in canonical form (RFC 4034 section 6.2), with the embedded
+    /// names of the listed types lowercased. The NSEC next name keeps its
+    /// case (RFC 6840 section 5.1).
+    pub fn canonical(&self) -> RData {
+        let mut rdata = self.clone();
+        match &mut rdata {
+            RData::Ns(name) | RData::Cname(name) | RData::Ptr(name) | RData::Dname(name) => {
+                *name = lowercase_name(name)
+            }
+            RData::Mx { exchange, .. } => *exchange = lowercase_name(exchange),
+            RData::Soa { mname, rname, .. } => {
+                *mname = lowercase_name(mname);
+                *rname = lowercase_name(rname);
+            }
+            RData::Srv { target, .. } => *target = lowercase_name(target),
+            RData::Rrsig { signer, .. } => *signer = lowercase_name(signer),
+            _ => {}
+        }
+        rdata
+    }
+
+    /// The key tag of a DNSKEY (RFC 4034 appendix B), which DS and RRSIG
+    /// records use to refer to it.
+    pub fn key_tag(&self) -> Option<u16> {
+        if !matches!(self, RData::Dnskey { .. }) {
+            return None;
+        }
+        let mut sum: u32 = 0;
+        for (index, byte) in self.encode().iter().enumerate() {
+            sum += if index % 2 == 0 { (*byte as u32) << 8 } else { *byte as u32 };
+        }
+        sum += (sum >> 16) & 0xFFFF;
+        Some((sum & 0xFFFF) as u16)
+    }
+
+    /// Appends the RDATA to a message being built, compressing the embedded
+    /// names of the types RFC 3597 section 4 allows to be compressed.
+    pub fn encode_compressed(&self, bytes: &mut Vec<u8>, compressor: &mut NameCompressor) {
+        match self {
+            RData::Ns(name) | RData::Cname(name) | RData::Ptr(name) => compressor.write_name(bytes, name),
+            RData::Mx { preference, exchange } => {
+                bytes.extend(preference.to_be_bytes());
+                compressor.write_name(bytes, exchange);
+            }
+            RData::Soa { mname, rname, serial, refresh, retry, expire, minimum } => {
+                compressor.write_name(bytes, mname);
+                compressor.write_name(bytes, rname);
+                for value in [serial, refresh, retry, expire, minimum] {
+                    bytes.extend(value.to_be_bytes());
+                }
+            }
+            _ => bytes.extend(self.encode()),
+        }
+    }
+
+    /// Parses the presentation form of RDATA as found in a zone file.
+    pub fn parse(rtype: u16, fields: &[String], origin: &[u8]) -> Result<RData> {
+        let field = |index: usize| -> Result<&str> {
+            fields
+                .get(index)
+                .map(|field| field.as_str())
+                .ok_or_else(|| anyhow!("Missing RDATA field {} for type {}", index, rtype))
+        };
+        let number = |index: usize| -> Result<u32> {
+            field(index)?
+                .parse()
+                .map_err(|_| anyhow!("Invalid number in RDATA for type {}", rtype))
+        };
+        // Base64 and hex fields may be split across several tokens.
+        let rest = |index: usize| -> Result<String> {
+            field(index)?;
+            Ok(fields[index..].concat())
+        };
+        let base64_decode = |text: &str| -> Result<Vec<u8>> {
+            base64::engine::general_purpose::STANDARD
+                .decode(text)
+                .map_err(|e| anyhow!("Invalid base64 in RDATA for type {}: {}", rtype, e))
+        };
+        let type_name = |name: &str| -> Result<u16> {
+            rtype::from_str(name).ok_or_else(|| anyhow!("Unknown record type {} in RDATA", name))
+        };
+        let type_list = |index: usize| -> Result<Vec<u16>> {
+            fields.iter().skip(index).map(|name| type_name(name)).collect()
+        };
+        let salt = |index: usize| -> Result<Vec<u8>> {
+            match field(index)? {
+                "-" => Ok(vec![]),
+                salt => hex_decode(salt),
+            }
+        };
+        let rdata = match rtype {
+            rtype::A => RData::A(field(0)?.parse()?),
+            rtype::AAAA => RData::Aaaa(field(0)?.parse()?),
+            rtype::NS => RData::Ns(absolute_name(field(0)?, origin)),
+            rtype::CNAME => RData::Cname(absolute_name(field(0)?, origin)),
+            rtype::PTR => RData::Ptr(absolute_name(field(0)?, origin)),
+            rtype::MX => RData::Mx {
+                preference: number(0)? as u16,
+                exchange: absolute_name(field(1)?, origin),
+            },
+            rtype::TXT => RData::Txt(fields.iter().map(|field| field.as_bytes().to_vec()).collect()),
+            rtype::SOA => RData::Soa {
+                mname: absolute_name(field(0)?, origin),
+                rname: absolute_name(field(1)?, origin),
+                serial: number(2)?,
+                refresh: number(3)?,
+                retry: number(4)?,
+                expire: number(5)?,
+                minimum: number(6)?,
+            },
+            rtype::SRV => RData::Srv {
+                priority: number(0)? as u16,
+                weight: number(1)? as u16,
+                port: number(2)? as u16,
+                target: absolute_name(field(3)?, origin),
+            },
+            rtype::DNAME => RData::Dname(absolute_name(field(0)?, origin)),
+            rtype::DS => RData::Ds {
+                key_tag: number(0)? as u16,
+                algorithm: number(1)? as u8,
+                digest_type: number(2)? as u8,
+                digest: hex_decode(&rest(3)?)?,
+            },
+            rtype::RRSIG => RData::Rrsig {
+                type_covered: type_name(field(0)?)?,
+                algorithm: number(1)? as u8,
+                labels: number(2)? as u8,
+                original_ttl: number(3)?,
+                expiration: parse_timestamp(field(4)?)?,
+                inception: parse_timestamp(field(5)?)?,
+                key_tag: number(6)? as u16,
+                signer: absolute_name(field(7)?, origin),
+                signature: base64_decode(&rest(8)?)?,
+            },
+            rtype::NSEC => RData::Nsec {
+                next: absolute_name(field(0)?, origin),
+                types: type_list(1)?,
+            },
+            rtype::DNSKEY => RData::Dnskey {
+                flags: number(0)? as u16,
+                protocol: number(1)? as u8,
+                algorithm: number(2)? as u8,
+                public_key: base64_decode(&rest(3)?)?,
+            },
+            rtype::NSEC3 => RData::Nsec3 {
+                hash_algorithm: number(0)? as u8,
+                flags: number(1)? as u8,
+                iterations: number(2)? as u16,
+                salt: salt(3)?,
+                next_hashed: base32hex_decode(field(4)?)?,
+                types: type_list(5)?,
+            },
+            rtype::NSEC3PARAM => RData::Nsec3Param {
+                hash_algorithm: number(0)? as u8,
+                flags: number(1)? as u8,
+                iterations: number(2)? as u16,
+                salt: salt(3)?,
+            },
+            _ => return Err(anyhow!("Unsupported record type {} in zone data", rtype)),
+        };
+        Ok(rdata)
+    }
+
+    /// The name this record points at, for types that redirect or refer.
+    pub fn target(&self) -> Option<&[u8]> {
+        match self {
+            RData::Ns(name) | RData::Cname(name) | RData::Ptr(name) => Some(name),
+            RData::Mx { exchange, .. } => Some(exchange),
+            RData::Srv { target, .. } => Some(target),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(rtype: u16, text: &str) -> RData {
+        let fields: Vec<String> = text.split_whitespace().map(|field| field.to_owned()).collect();
+        RData::parse(rtype, &fields, &encode_name("example.com")).unwrap()
+    }
+
+    fn samples() -> Vec<(u16, RData)> {
+        let mut samples: Vec<(u16, RData)> = [
+            (rtype::A, "192.0.2.1"),
+            (rtype::AAAA, "2001:db8::1"),
+            (rtype::NS, "ns1"),
+            (rtype::CNAME, "www.example.org."),
+            (rtype::PTR, "host"),
+            (rtype::MX, "10 mail"),
+            (rtype::TXT, "hello world"),
+            (rtype::SOA, "ns admin 2024010101 3600 600 86400 300"),
+            (rtype::SRV, "0 5 5060 sip"),
+            (rtype::DNAME, "example.net."),
+            (rtype::DS, "60485 5 1 2BB183AF5F22588179A53B0A 98631FAD1A292118"),
+            (rtype::RRSIG, "A 13 3 300 20300101000000 20240101000000 12345 example.com. AAECAwQF BgcICQ=="),
+            (rtype::NSEC, "Next.example.com. A NS SOA RRSIG NSEC"),
+            (rtype::DNSKEY, "257 3 13 AAECAwQFBgcICQoLDA0ODw=="),
+            (rtype::NSEC3, "1 0 10 AABBCCDD 2T7B4G4VSA5SMI47K61MV5BV1A22BOJR A RRSIG"),
+            (rtype::NSEC3PARAM, "1 0 10 -"),
+        ]
+        .iter()
+        .map(|(rtype, text)| (*rtype, parse(*rtype, text)))
+        .collect();
+        samples.push((
+            rtype::TSIG,
+            RData::Tsig {
+                algorithm: encode_name("hmac-sha256"),
+                time_signed: 1_700_000_000,
+                fudge: 300,
+                mac: vec![7; 32],
+                original_id: 0x1234,
+                error: 0,
+                other: vec![1, 2],
+            },
+        ));
+        samples
+    }
+
+    #[test]
+    fn round_trips_every_type() {
+        for (rtype, rdata) in samples() {
+            let bytes = rdata.encode();
+            assert_eq!(RData::decode(rtype, &bytes, 0, bytes.len()).unwrap(), rdata, "type {}", rtype);
+
+            // RDATA must end exactly at its length, in either direction,
+            // unless its last field runs to the end whatever that is.
+            let open_ended = matches!(
+                rtype,
+                rtype::TXT | rtype::DS | rtype::RRSIG | rtype::NSEC | rtype::DNSKEY | rtype::NSEC3
+            );
+            if open_ended {
+                continue;
+            }
+            let mut longer = bytes.clone();
+            longer.push(0);
+            assert!(RData::decode(rtype, &longer, 0, longer.len()).is_err(), "type {} with a byte extra", rtype);
+            assert!(RData::decode(rtype, &bytes, 0, bytes.len() - 1).is_err(), "type {} a byte short", rtype);
+        }
+    }
+
+    #[test]
+    fn decodes_compressed_names_from_the_message() {
+        let mut message = encode_name("example.com");
+        message.push(0);
+        let offset = message.len();
+        message.extend([0, 10, 0xC0, 0]);
+        let rdata = RData::decode(rtype::MX, &message, offset, 4).unwrap();
+        assert_eq!(rdata, RData::Mx { preference: 10, exchange: encode_name("example.com") });
+    }
+
+    #[test]
+    fn canonical_form_lowercases_names_except_nsec() {
+        let rdata = parse(rtype::MX, "10 Mail.Example.COM.").canonical();
+        assert_eq!(rdata, RData::Mx { preference: 10, exchange: encode_name("mail.example.com") });
+        let nsec = parse(rtype::NSEC, "Next.example.com. A");
+        assert_eq!(nsec.canonical(), nsec);
+    }
+}
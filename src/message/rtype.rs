@@ -0,0 +1,59 @@
+// Resource record TYPE and CLASS values (RFC 1035 3.2.2 and later registries).
+
+pub const A: u16 = 1;
+pub const NS: u16 = 2;
+pub const CNAME: u16 = 5;
+pub const SOA: u16 = 6;
+pub const PTR: u16 = 12;
+pub const MX: u16 = 15;
+pub const TXT: u16 = 16;
+pub const AAAA: u16 = 28;
+pub const SRV: u16 = 33;
+pub const DNAME: u16 = 39;
+pub const OPT: u16 = 41;
+pub const DS: u16 = 43;
+pub const RRSIG: u16 = 46;
+pub const NSEC: u16 = 47;
+pub const DNSKEY: u16 = 48;
+pub const NSEC3: u16 = 50;
+pub const NSEC3PARAM: u16 = 51;
+pub const TSIG: u16 = 250;
+pub const IXFR: u16 = 251;
+pub const AXFR: u16 = 252;
+pub const ANY: u16 = 255;
+
+pub const CLASS_IN: u16 = 1;
+pub const CLASS_CH: u16 = 3;
+pub const CLASS_HS: u16 = 4;
+pub const CLASS_NONE: u16 = 254;
+pub const CLASS_ANY: u16 = 255;
+
+/// QTYPEs and meta-types that can never appear as data in a zone.
+pub fn is_meta(rtype: u16) -> bool {
+    rtype == OPT || (128..=255).contains(&rtype)
+}
+
+pub fn from_str(name: &str) -> Option<u16> {
+    match name.to_ascii_uppercase().as_str() {
+        "A" => Some(A),
+        "NS" => Some(NS),
+        "CNAME" => Some(CNAME),
+        "SOA" => Some(SOA),
+        "PTR" => Some(PTR),
+        "MX" => Some(MX),
+        "TXT" => Some(TXT),
+        "AAAA" => Some(AAAA),
+        "SRV" => Some(SRV),
+        "DNAME" => Some(DNAME),
+        "DS" => Some(DS),
+        "RRSIG" => Some(RRSIG),
+        "NSEC" => Some(NSEC),
+        "DNSKEY" => Some(DNSKEY),
+        "NSEC3" => Some(NSEC3),
+        "NSEC3PARAM" => Some(NSEC3PARAM),
+        "IXFR" => Some(IXFR),
+        "AXFR" => Some(AXFR),
+        "ANY" => Some(ANY),
+        other => other.strip_prefix("TYPE").and_then(|n| n.parse().ok()),
+    }
+}
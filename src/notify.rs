@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::message::*;
+
+const NOTIFY_ATTEMPTS: usize = 5;
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(2);
+
+fn notify_message(id: u16, soa: &Answer) -> Message {
+    Message {
+        header: Header::builder()
+            .id(id)
+            .unwrap()
+            .qr(false)
+            .unwrap()
+            .opcode(Opcode::Notify)
+            .unwrap()
+            .aa(true)
+            .unwrap()
+            .qdcount(1)
+            .unwrap()
+            .ancount(1)
+            .unwrap()
+            .build(),
+        questions: vec![
+            Question::builder()
+                .name_bytes(soa.name.clone())
+                .unwrap()
+                .qtype(rtype::SOA)
+                .build(),
+        ],
+        answers: vec![soa.clone()],
+        authorities: vec![],
+        additionals: vec![],
+    }
+}
+
+fn send_notify(secondary: &str, soa: &Answer) {
+    let socket = match UdpSocket::bind("0.0.0.0:0") {
+        Ok(socket) => socket,
+        Err(e) => {
+            eprintln!("Cannot open socket for NOTIFY: {}", e);
+            return;
+        }
+    };
+    socket.set_read_timeout(Some(NOTIFY_TIMEOUT)).ok();
+    let id: u16 = rand::random();
+
+    // NOTIFY is retransmitted until the secondary acknowledges it
+    // (RFC 1996 section 3.6).
+    for _ in 0..NOTIFY_ATTEMPTS {
+        if socket.send_to(&notify_message(id, soa).encode(), secondary).is_err() {
+            continue;
+        }
+        let mut buf = [0; 512];
+        // Replies from anywhere but the secondary are ignored, so that a
+        // forged acknowledgement cannot stop the retransmissions.
+        while let Ok((size, source)) = socket.recv_from(&mut buf) {
+            let expected = secondary.to_socket_addrs().is_ok_and(|mut addrs| addrs.any(|addr| addr == source));
+            let Ok(response) = Message::decode(&buf[..size]) else {
+                continue;
+            };
+            if expected && response.header.id == id && response.header.opcode == Opcode::Notify {
+                println!("Secondary {} acknowledged NOTIFY for {}", secondary, name_to_string(&soa.name));
+                return;
+            }
+        }
+    }
+    eprintln!("Secondary {} did not acknowledge NOTIFY for {}", secondary, name_to_string(&soa.name));
+}
+
+/// Tells the configured secondaries of a zone that it has changed.
+pub fn notify_secondaries(soa: &Answer, config: &Config) {
+    for target in config.notify.iter().filter(|target| names_equal(&target.origin, &soa.name)) {
+        let secondary = target.secondary.clone();
+        let soa = soa.clone();
+        thread::spawn(move || send_notify(&secondary, &soa));
+    }
+}
+
+fn resolves_to(address: &str, source: SocketAddr) -> bool {
+    address
+        .to_socket_addrs()
+        .map(|mut addrs| addrs.any(|addr| addr.ip() == source.ip()))
+        .unwrap_or(false)
+}
+
+/// Answers a NOTIFY from a primary and wakes the refresh loop of the zone
+/// it names.
+pub fn handle_notify(
+    request: Message,
+    source: SocketAddr,
+    config: &Config,
+    refresh_triggers: &HashMap<Vec<u8>, Sender<()>>,
+) -> Message {
+    let mut rcode = Rcode::NoError;
+    match request.questions.first() {
+        None => rcode = Rcode::FormErr,
+        Some(question) => {
+            let secondary = config.secondaries
+                .iter()
+                .find(|secondary| names_equal(&secondary.origin, &question.name));
+            match secondary {
+                None => rcode = Rcode::NotAuth,
+                Some(secondary) if !resolves_to(&secondary.primary, source) => {
+                    println!("Ignoring NOTIFY for {} from {}", name_to_string(&question.name), source);
+                    rcode = Rcode::Refused;
+                }
+                Some(secondary) => {
+                    println!("NOTIFY for {} from {}", name_to_string(&question.name), source);
+                    if let Some(trigger) = refresh_triggers.get(&lowercase_name(&secondary.origin)) {
+                        trigger.send(()).ok();
+                    }
+                }
+            }
+        }
+    }
+
+    Message {
+        header: Header::builder()
+            .id(request.header.id)
+            .unwrap()
+            .opcode(Opcode::Notify)
+            .unwrap()
+            .aa(rcode == Rcode::NoError)
+            .unwrap()
+            .rcode(rcode)
+            .unwrap()
+            .qdcount(request.questions.len() as u16)
+            .unwrap()
+            .build(),
+        questions: request.questions,
+        answers: vec![],
+        authorities: vec![],
+        additionals: vec![],
+    }
+}
@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use anyhow::{Result, anyhow};
+
+use crate::acl::Acl;
+use crate::rrl::client_network;
+
+// How often buckets that have refilled are forgotten.
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// What happens to queries over a client's limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum LimitAction {
+    #[default]
+    Drop,
+    Refuse,
+}
+
+impl FromStr for LimitAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<LimitAction> {
+        match s {
+            "drop" => Ok(LimitAction::Drop),
+            "refuse" => Ok(LimitAction::Refuse),
+            _ => Err(anyhow!("Rate limit action must be drop or refuse")),
+        }
+    }
+}
+
+/// Per-client query rate limiting settings. A `rate` of zero turns it off.
+#[derive(Debug, Clone)]
+pub struct QueryLimitSettings {
+    // Queries per second each client network may send.
+    pub rate: u32,
+    // Queries a client may send at once after being idle; the rate when
+    // unset.
+    pub burst: Option<u32>,
+    pub ipv4_prefix: u8,
+    pub ipv6_prefix: u8,
+    pub action: LimitAction,
+    // Trusted clients that are never limited.
+    pub exempt: Acl,
+}
+
+impl Default for QueryLimitSettings {
+    fn default() -> QueryLimitSettings {
+        QueryLimitSettings {
+            rate: 0,
+            burst: None,
+            ipv4_prefix: 32,
+            ipv6_prefix: 128,
+            action: LimitAction::Drop,
+            exempt: Acl::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// A token bucket per client network, refilled at the configured rate.
+#[derive(Debug)]
+pub struct QueryRateLimiter {
+    settings: QueryLimitSettings,
+    buckets: HashMap<IpAddr, Bucket>,
+    last_cleanup: Instant,
+}
+
+impl QueryRateLimiter {
+    pub fn new(settings: QueryLimitSettings) -> QueryRateLimiter {
+        QueryRateLimiter {
+            settings,
+            buckets: HashMap::new(),
+            last_cleanup: Instant::now(),
+        }
+    }
+
+    /// Takes a token for a query from `client`, signed with `key` if any.
+    /// False when the client has none left.
+    pub fn allow(&mut self, client: IpAddr, key: Option<&[u8]>) -> bool {
+        if self.settings.exempt.allows(client, key) {
+            return true;
+        }
+        let now = Instant::now();
+        let rate = f64::from(self.settings.rate);
+        let burst = f64::from(self.settings.burst.unwrap_or(self.settings.rate));
+        if now.duration_since(self.last_cleanup) >= CLEANUP_INTERVAL {
+            // A bucket idle long enough to have refilled is the same as a
+            // new one.
+            self.buckets
+                .retain(|_, bucket| bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate < burst);
+            self.last_cleanup = now;
+        }
+
+        let network = client_network(client, self.settings.ipv4_prefix, self.settings.ipv6_prefix);
+        let bucket = self.buckets.entry(network).or_insert(Bucket { tokens: burst, updated: now });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(burst);
+        bucket.updated = now;
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::encode_name;
+
+    fn limiter(rate: u32, burst: Option<u32>) -> QueryRateLimiter {
+        QueryRateLimiter::new(QueryLimitSettings { rate, burst, ..QueryLimitSettings::default() })
+    }
+
+    fn allowed(limiter: &mut QueryRateLimiter, client: &str, count: usize) -> usize {
+        (0..count).filter(|_| limiter.allow(client.parse().unwrap(), None)).count()
+    }
+
+    // Moves every bucket's last update `elapsed` into the past, as if no
+    // queries had come in since.
+    fn wait(limiter: &mut QueryRateLimiter, elapsed: Duration) {
+        for bucket in limiter.buckets.values_mut() {
+            bucket.updated -= elapsed;
+        }
+    }
+
+    #[test]
+    fn refills_at_the_rate() {
+        let mut limiter = limiter(2, Some(5));
+        assert_eq!(allowed(&mut limiter, "192.0.2.1", 10), 5);
+        wait(&mut limiter, Duration::from_millis(1500));
+        assert_eq!(allowed(&mut limiter, "192.0.2.1", 10), 3);
+        // Never more than the burst, however long the client was idle.
+        wait(&mut limiter, Duration::from_secs(60));
+        assert_eq!(allowed(&mut limiter, "192.0.2.1", 10), 5);
+    }
+
+    #[test]
+    fn bursts_default_to_the_rate() {
+        let mut limiter = limiter(3, None);
+        assert_eq!(allowed(&mut limiter, "192.0.2.1", 10), 3);
+        assert_eq!(allowed(&mut limiter, "192.0.2.2", 10), 3);
+    }
+
+    #[test]
+    fn groups_clients_by_network() {
+        let mut limiter = QueryRateLimiter::new(QueryLimitSettings {
+            rate: 2,
+            ipv4_prefix: 24,
+            ipv6_prefix: 64,
+            ..QueryLimitSettings::default()
+        });
+        assert_eq!(allowed(&mut limiter, "192.0.2.1", 1), 1);
+        assert_eq!(allowed(&mut limiter, "192.0.2.99", 5), 1);
+        assert_eq!(allowed(&mut limiter, "192.0.3.1", 5), 2);
+        assert_eq!(allowed(&mut limiter, "2001:db8::1", 1), 1);
+        assert_eq!(allowed(&mut limiter, "2001:db8::2", 5), 1);
+        assert_eq!(allowed(&mut limiter, "2001:db8:0:1::1", 5), 2);
+    }
+
+    #[test]
+    fn exempts_trusted_clients_and_keys() {
+        let mut settings = QueryLimitSettings { rate: 1, ..QueryLimitSettings::default() };
+        settings.exempt.push("10.0.0.0/8".parse().unwrap());
+        settings.exempt.push("key:monitor".parse().unwrap());
+        let mut limiter = QueryRateLimiter::new(settings);
+
+        assert_eq!(allowed(&mut limiter, "10.1.2.3", 10), 10);
+        assert_eq!(allowed(&mut limiter, "192.0.2.1", 10), 1);
+        let client = "192.0.2.1".parse().unwrap();
+        assert!(limiter.allow(client, Some(&encode_name("Monitor"))));
+        assert!(!limiter.allow(client, Some(&encode_name("other"))));
+        // Exempt queries take no tokens.
+        assert_eq!(limiter.buckets.len(), 1);
+    }
+
+    #[test]
+    fn parses_actions() {
+        assert_eq!("drop".parse::<LimitAction>().unwrap(), LimitAction::Drop);
+        assert_eq!("refuse".parse::<LimitAction>().unwrap(), LimitAction::Refuse);
+        assert!("servfail".parse::<LimitAction>().is_err());
+    }
+}
@@ -0,0 +1,28 @@
+use std::fs;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+fn modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Polls `paths` every `interval` and calls `on_change` with each file whose
+/// modification time moved since the previous poll.
+pub fn watch<F>(paths: Vec<String>, interval: Duration, mut on_change: F) -> JoinHandle<()>
+where
+    F: FnMut(&str) + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut seen: Vec<Option<SystemTime>> = paths.iter().map(|path| modified(path)).collect();
+        loop {
+            thread::sleep(interval);
+            for (path, last) in paths.iter().zip(seen.iter_mut()) {
+                let current = modified(path);
+                if current != *last {
+                    *last = current;
+                    on_change(path);
+                }
+            }
+        }
+    })
+}
@@ -1,123 +1,1071 @@
-use std::net::UdpSocket;
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::{Mutex, RwLock};
+use std::time::Duration;
 
+use anyhow::{Result, anyhow};
+
+use crate::acl::Operation;
+use crate::cache::Cache;
+use crate::config::Config;
 use crate::message::*;
+use crate::reverse::synthesize;
+use crate::rpz::PolicyAction;
+use crate::signer::Signer;
+use crate::tcp::{read_message, write_message};
+use crate::update::handle_update;
+use crate::validator::{Security, Validator};
+use crate::view::View;
+use crate::zone::{same_record, Lookup, ZoneStore};
 
-pub fn build_response(request: Message) -> Message {
-    let mut answers = vec![];
-    let mut questions = vec![];
+// Upper bound on CNAME links followed for a single question.
+const MAX_CHAIN_LENGTH: usize = 16;
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(5);
 
-    for question in request.questions {
+#[derive(Debug)]
+pub enum ChainEnd {
+    Answered,
+    NoData,
+    NxDomain,
+    NameTooLong,
+    Loop,
+    // The name is delegated away from a local zone.
+    Referral(Vec<u8>),
+    // Neither a local zone nor the cache can answer for this name.
+    Unresolved(Vec<u8>),
+}
 
-        let response_question = Question::builder()
-            .name_bytes(question.name.clone())
-            .unwrap()
-            .build();
-        let response_answer = Answer::builder()
-            .name_bytes(question.name.clone())
-            .unwrap()
-            .qtype(question.qtype)
-            .unwrap()
-            .qclass(question.qclass)
-            .unwrap()
-            .ttl(60)
-            .unwrap()
-            .length(4)
-            .unwrap()
-            .data(vec![8, 8, 8, 8])
-            .unwrap()
-            .build();
-        questions.push(response_question);
-        answers.push(response_answer);
+#[derive(Debug)]
+pub struct Chain {
+    pub answers: Vec<Answer>,
+    pub authorities: Vec<Answer>,
+    pub additionals: Vec<Answer>,
+    // Whether the question name itself was answered from zone data.
+    pub authoritative: bool,
+    // Whether every cached RRset used was validated as secure.
+    pub secure: bool,
+    pub end: ChainEnd,
+}
+
+impl ChainEnd {
+    fn rcode(&self) -> Rcode {
+        match self {
+            ChainEnd::NxDomain => Rcode::NxDomain,
+            ChainEnd::NameTooLong => Rcode::YxDomain,
+            ChainEnd::Loop => Rcode::ServFail,
+            _ => Rcode::NoError,
+        }
     }
+}
 
-    Message {
-        header: Header::builder()
-            .qdcount(questions.len() as u16)
-            .unwrap()
-            .ancount(answers.len() as u16)
-            .unwrap()
+impl Chain {
+    fn finish(mut self, end: ChainEnd) -> Chain {
+        self.end = end;
+        self
+    }
+
+    /// Ends the chain with records from the hosts table, the blocklist or
+    /// synthesized for a reverse network, which are answered
+    /// authoritatively but unsigned.
+    fn finish_local(mut self, lookup: Lookup, first_link: bool) -> Chain {
+        self.secure = false;
+        if first_link {
+            self.authoritative = true;
+        }
+        match lookup {
+            Lookup::Records(records) => {
+                self.answers.extend(records);
+                self.finish(ChainEnd::Answered)
+            }
+            Lookup::NxDomain => self.finish(ChainEnd::NxDomain),
+            _ => self.finish(ChainEnd::NoData),
+        }
+    }
+
+    /// Replaces the answer for `name` with a policy action, keeping the
+    /// CNAMEs that led from `qname` to it. Policy data is answered under
+    /// `name`, so wildcard triggers answer for the name that matched them.
+    fn rewrite(mut self, qname: &[u8], name: &[u8], action: PolicyAction, qtype: u16) -> Chain {
+        let kept = if names_equal(name, qname) {
+            0
+        } else {
+            self.answers
+                .iter()
+                .position(|record| {
+                    record.qtype == rtype::CNAME
+                        && record.rdata().ok().and_then(|rdata| rdata.target().map(|target| names_equal(target, name)))
+                            == Some(true)
+                })
+                .map_or(0, |index| index + 1)
+        };
+        self.answers.truncate(kept);
+        self.authorities.clear();
+        self.additionals.clear();
+        self.authoritative = false;
+        self.secure = false;
+        match action {
+            PolicyAction::NxDomain => self.finish(ChainEnd::NxDomain),
+            PolicyAction::LocalData(records) => {
+                let records: Vec<Answer> = records
+                    .into_iter()
+                    .filter(|record| qtype == rtype::ANY || record.qtype == qtype || record.qtype == rtype::CNAME)
+                    .map(|mut record| {
+                        record.name = name.to_vec();
+                        record
+                    })
+                    .collect();
+                if records.is_empty() {
+                    self.finish(ChainEnd::NoData)
+                } else {
+                    self.answers.extend(records);
+                    self.finish(ChainEnd::Answered)
+                }
+            }
+            PolicyAction::NoData => self.finish(ChainEnd::NoData),
+            // Left to the caller, which answers unchanged or not at all.
+            PolicyAction::Passthru | PolicyAction::Drop => self,
+        }
+    }
+
+    /// Continues a chain a policy answered with a CNAME through local
+    /// zones, the hosts table and the cache. A target known nowhere
+    /// locally is left for the client to resolve.
+    fn follow_policy_cname(mut self, qtype: u16, zones: &ZoneStore, cache: &mut Cache, config: &Config) -> Chain {
+        let target = match self.answers.last() {
+            Some(record) if record.qtype == rtype::CNAME && qtype != rtype::CNAME && qtype != rtype::ANY => {
+                record.rdata().ok().and_then(|rdata| rdata.target().map(|target| target.to_vec()))
+            }
+            _ => None,
+        };
+        let Some(target) = target else {
+            return self;
+        };
+        let rest = follow_chain(&target, qtype, zones, cache, config);
+        self.answers.extend(rest.answers);
+        match rest.end {
+            ChainEnd::Unresolved(_) | ChainEnd::Referral(_) => self,
+            end => {
+                self.authorities = rest.authorities;
+                self.additionals.extend(rest.additionals);
+                self.finish(end)
+            }
+        }
+    }
+
+    fn fill_additionals(&mut self, zones: &ZoneStore, cache: &mut Cache) {
+        let referring: Vec<Answer> = self.answers
+            .iter()
+            .chain(self.authorities.iter())
+            .cloned()
+            .collect();
+        let extra = additional_records(&referring, &self.additionals, zones, cache);
+        self.additionals.extend(extra);
+    }
+}
+
+/// Answers `qname` from local zones and the cache, following CNAMEs (and
+/// CNAMEs synthesized from DNAMEs) until the requested type is found, the
+/// name is proven not to exist, or the chain leaves what we know locally.
+/// Names in the hosts table are answered from it ahead of everything else,
+/// then blocked names with the block action. Names in configured reverse
+/// networks that no zone has data for are answered with synthesized
+/// records.
+pub fn follow_chain(qname: &[u8], qtype: u16, zones: &ZoneStore, cache: &mut Cache, config: &Config) -> Chain {
+    let mut chain = Chain {
+        answers: vec![],
+        authorities: vec![],
+        additionals: vec![],
+        authoritative: false,
+        secure: true,
+        end: ChainEnd::Answered,
+    };
+    let mut visited: Vec<Vec<u8>> = vec![];
+    let mut name = qname.to_vec();
+
+    loop {
+        if visited.len() >= MAX_CHAIN_LENGTH || visited.iter().any(|seen| names_equal(seen, &name)) {
+            println!("CNAME loop detected at {}", name_to_string(&name));
+            return chain.finish(ChainEnd::Loop);
+        }
+        visited.push(name.clone());
+        let first_link = visited.len() == 1;
+
+        let local = zones.hosts().lookup(&name, qtype).or_else(|| zones.blocklist().lookup(&name, qtype));
+        let cname = if let Some(lookup) = local {
+            return chain.finish_local(lookup, first_link);
+        } else if let Some(zone) = zones.find_zone(&name) {
+            chain.secure = false;
+            let lookup = match zone.lookup(&name, qtype) {
+                Lookup::NxDomain => synthesize(&name, qtype, &config.reverse_networks).unwrap_or(Lookup::NxDomain),
+                lookup => lookup,
+            };
+            if first_link {
+                chain.authoritative = !matches!(lookup, Lookup::Referral { .. });
+            }
+            match lookup {
+                Lookup::Records(records) => {
+                    chain.answers.extend(records);
+                    return chain.finish(ChainEnd::Answered);
+                }
+                Lookup::Cname(cname) => cname,
+                Lookup::Dname(dname, cname) => {
+                    chain.answers.push(dname);
+                    cname
+                }
+                Lookup::NameTooLong(dname) => {
+                    chain.answers.push(dname);
+                    return chain.finish(ChainEnd::NameTooLong);
+                }
+                Lookup::Referral { ns, glue } => {
+                    chain.authorities.extend(ns);
+                    chain.additionals.extend(glue);
+                    return chain.finish(ChainEnd::Referral(name));
+                }
+                // Negative answers carry the SOA for negative caching
+                // (RFC 2308 section 3).
+                Lookup::NoData => {
+                    chain.authorities.extend(zone.soa().cloned());
+                    return chain.finish(ChainEnd::NoData);
+                }
+                Lookup::NxDomain => {
+                    chain.authorities.extend(zone.soa().cloned());
+                    return chain.finish(ChainEnd::NxDomain);
+                }
+            }
+        } else if let Some(lookup) = synthesize(&name, qtype, &config.reverse_networks) {
+            return chain.finish_local(lookup, first_link);
+        } else if let Some(records) = cache.get(&name, qtype) {
+            chain.secure &= cache.is_secure(&name, qtype);
+            chain.answers.extend(records);
+            return chain.finish(ChainEnd::Answered);
+        } else if let Some(cname) = cache.get(&name, rtype::CNAME).filter(|_| qtype != rtype::CNAME) {
+            chain.secure &= cache.is_secure(&name, rtype::CNAME);
+            cname[0].clone()
+        } else {
+            return chain.finish(ChainEnd::Unresolved(name));
+        };
+
+        let target = cname.rdata().ok().and_then(|rdata| rdata.target().map(|target| target.to_vec()));
+        chain.answers.push(cname);
+        match target {
+            Some(target) => name = target,
+            None => return chain.finish(ChainEnd::Answered),
+        }
+    }
+}
+
+/// Finds the first CNAME in a forwarded answer whose target is blocked, so
+/// that trackers cloaked behind names in allowed domains are blocked too.
+/// Returns its index and the block answer for its target.
+fn cloaked_cname(answers: &[Answer], qtype: u16, zones: &ZoneStore) -> Option<(usize, Lookup)> {
+    answers.iter().enumerate().find_map(|(index, record)| {
+        if record.qtype != rtype::CNAME {
+            return None;
+        }
+        let rdata = record.rdata().ok()?;
+        let lookup = zones.blocklist().lookup(rdata.target()?, qtype)?;
+        Some((index, lookup))
+    })
+}
+
+/// The records of a forwarded answer that resolve `qname`: the CNAME and
+/// DNAME chain starting there and the RRset it ends in. Anything else in
+/// the answer section was not asked for and is not trusted.
+fn answer_chain(answers: &[Answer], qname: &[u8], qtype: u16) -> Vec<Answer> {
+    let mut chain: Vec<Answer> = vec![];
+    let mut visited: Vec<Vec<u8>> = vec![];
+    let mut name = qname.to_vec();
+
+    while visited.len() < MAX_CHAIN_LENGTH && !visited.iter().any(|seen| names_equal(seen, &name)) {
+        visited.push(name.clone());
+        let dnames = answers.iter().filter(|record| {
+            record.qtype == rtype::DNAME && is_subdomain(&name, &record.name) && !names_equal(&name, &record.name)
+        });
+        for dname in dnames {
+            if !chain.iter().any(|link| same_record(link, dname)) {
+                chain.push(dname.clone());
+            }
+        }
+
+        let at_name: Vec<&Answer> = answers.iter().filter(|record| names_equal(&record.name, &name)).collect();
+        let rrset: Vec<Answer> = at_name
+            .iter()
+            .filter(|record| qtype == rtype::ANY || record.qtype == qtype)
+            .map(|record| (*record).clone())
+            .collect();
+        if !rrset.is_empty() {
+            chain.extend(rrset);
+            break;
+        }
+        let Some(cname) = at_name.into_iter().find(|record| record.qtype == rtype::CNAME) else {
+            break;
+        };
+        chain.push(cname.clone());
+        match cname.rdata().ok().and_then(|rdata| rdata.target().map(|target| target.to_vec())) {
+            Some(target) => name = target,
+            None => break,
+        }
+    }
+
+    chain
+}
+
+/// Address records for the targets of MX, NS and SRV records, taken from
+/// zone data or the cache, to spare the client a follow-up query.
+pub fn additional_records(records: &[Answer], existing: &[Answer], zones: &ZoneStore, cache: &mut Cache) -> Vec<Answer> {
+    let mut additionals: Vec<Answer> = vec![];
+
+    for record in records {
+        if !matches!(record.qtype, rtype::MX | rtype::NS | rtype::SRV) {
+            continue;
+        }
+        let target = match record.rdata() {
+            Ok(rdata) => match rdata.target() {
+                Some(target) if !target.is_empty() => target.to_vec(),
+                _ => continue,
+            },
+            Err(_) => continue,
+        };
+
+        for address_type in [rtype::A, rtype::AAAA] {
+            let addresses = match zones.find_zone(&target) {
+                Some(zone) => zone.records_at(&target)
+                    .iter()
+                    .filter(|address| address.qtype == address_type)
+                    .cloned()
+                    .collect(),
+                None => cache.get(&target, address_type).unwrap_or_default(),
+            };
+            for address in addresses {
+                let duplicate = existing.iter().chain(additionals.iter()).any(|other| {
+                    other.qtype == address.qtype
+                        && other.data == address.data
+                        && names_equal(&other.name, &address.name)
+                });
+                if !duplicate {
+                    additionals.push(address);
+                }
+            }
+        }
+    }
+
+    additionals
+}
+
+/// A response under construction. The header is derived from the request
+/// and from how resolution went (RFC 1035 section 4.1.1, RFC 4035 section
+/// 3.2) rather than set field by field.
+pub struct Response {
+    header: Header,
+    questions: Vec<Question>,
+    answers: Vec<Answer>,
+    authorities: Vec<Answer>,
+    additionals: Vec<Answer>,
+    edns: Option<Edns>,
+    // Whether the client sent AD to signal that it understands it.
+    wants_ad: bool,
+}
+
+impl Response {
+    /// Starts a response to `request`. `recursion_available` is whether this
+    /// server answers for names outside its own zones.
+    pub fn new(request: &Message, recursion_available: bool) -> Response {
+        let header = Header::builder()
             .id(request.header.id)
             .unwrap()
+            .qr(true)
+            .unwrap()
             .opcode(request.header.opcode)
             .unwrap()
             .rd(request.header.rd)
             .unwrap()
-            .rcode(if request.header.opcode == 0 { 0 } else { 4 })
+            .ra(recursion_available)
             .unwrap()
-            .build(),
-        questions: questions,
-        answers: answers,
+            .cd(request.header.cd)
+            .unwrap()
+            .build();
+        Response {
+            header,
+            questions: vec![],
+            answers: vec![],
+            authorities: vec![],
+            additionals: vec![],
+            edns: Edns::from_message(request),
+            wants_ad: request.header.ad,
+        }
+    }
+
+    pub fn dnssec_ok(&self) -> bool {
+        self.edns.is_some_and(|edns| edns.dnssec_ok)
+    }
+
+    /// Sets the RCODE unless an earlier question already failed.
+    pub fn set_rcode(&mut self, rcode: Rcode) {
+        if self.header.rcode == Rcode::NoError {
+            self.header.rcode = rcode;
+        }
+    }
+
+    /// Sets AD for an answer validated as secure, but only for clients that
+    /// signal they understand it (RFC 6840 section 5.8).
+    pub fn set_authenticated(&mut self, secure: bool) {
+        self.header.ad = secure && (self.dnssec_ok() || self.wants_ad);
+    }
+
+    /// Adds the outcome of resolving `question`. The first question decides
+    /// whether the answer is authoritative.
+    pub fn add_chain(&mut self, question: Question, chain: Chain) {
+        if self.questions.is_empty() {
+            self.header.aa = chain.authoritative;
+        }
+        self.questions.push(question);
+        self.answers.extend(chain.answers);
+        self.authorities.extend(chain.authorities);
+        self.additionals.extend(chain.additionals);
+    }
+
+    /// An empty response to `request` carrying only `rcode`.
+    pub fn error(request: &Message, rcode: Rcode, recursion_available: bool) -> Message {
+        let mut response = Response::new(request, recursion_available);
+        response.questions = request.questions.clone();
+        response.header.rcode = rcode;
+        response.build()
+    }
+
+    pub fn build(mut self) -> Message {
+        if let Some(edns) = self.edns {
+            self.additionals.push(Edns::new(edns.dnssec_ok).record());
+        }
+        self.header.qdcount = self.questions.len() as u16;
+        self.header.ancount = self.answers.len() as u16;
+        self.header.nscount = self.authorities.len() as u16;
+        self.header.arcount = self.additionals.len() as u16;
+        Message {
+            header: self.header,
+            questions: self.questions,
+            answers: self.answers,
+            authorities: self.authorities,
+            additionals: self.additionals,
+        }
+    }
+}
+
+/// An empty response to `request` carrying only an error RCODE.
+/// `recursion_available` is whether the client may recurse, as reported by
+/// [`recursion_available`].
+pub fn error_response(request: &Message, rcode: Rcode, recursion_available: bool) -> Message {
+    Response::error(request, rcode, recursion_available)
+}
+
+/// The FORMERR answer to a message that does not decode, echoing its ID
+/// and opcode. `None` when not even the header decodes, or when the
+/// message is itself a response, which is never answered. `client` is
+/// where it came from, checked against `config` for the RA bit.
+pub fn format_error(bytes: &[u8], client: IpAddr, config: &Config) -> Option<Message> {
+    let header = Header::decode(bytes).ok().filter(|header| !header.qr)?;
+    let request = Message {
+        header,
+        questions: vec![],
+        answers: vec![],
+        authorities: vec![],
+        additionals: vec![],
+    };
+    let recursion = recursion_available(&request, client, None, config);
+    Some(error_response(&request, Rcode::FormErr, recursion))
+}
+
+/// The largest UDP response `request` allows: 512 bytes, or the payload
+/// size it advertises with EDNS, up to our own (RFC 6891 section 6.2.5).
+pub fn udp_payload_limit(request: &Message) -> usize {
+    match Edns::from_message(request) {
+        Some(edns) => edns.payload_size.clamp(512, EDNS_PAYLOAD_SIZE) as usize,
+        None => 512,
+    }
+}
+
+/// Cuts a response that does not fit in `limit` bytes down to its question
+/// and OPT record and sets TC, so the client retries over TCP (RFC 2181
+/// section 9).
+pub fn truncate(mut response: Message, limit: usize) -> Message {
+    if response.clone().encode().len() <= limit {
+        return response;
+    }
+    response.header.tc = true;
+    response.answers.clear();
+    response.authorities.clear();
+    response.additionals.retain(|record| record.qtype == rtype::OPT);
+    response.header.ancount = 0;
+    response.header.nscount = 0;
+    response.header.arcount = response.additionals.len() as u16;
+    response
+}
+
+/// The RCODE for a question this server does not look up, if any.
+fn question_rcode(question: &Question) -> Option<Rcode> {
+    match question.qclass {
+        rtype::CLASS_IN | rtype::CLASS_ANY => {}
+        // Classes that exist but that we hold no data for.
+        rtype::CLASS_CH | rtype::CLASS_HS => return Some(Rcode::Refused),
+        _ => return Some(Rcode::NotImp),
+    }
+    match question.qtype {
+        rtype::ANY => None,
+        // OPT and TSIG only ever appear in the additional section.
+        rtype::OPT | rtype::TSIG => Some(Rcode::FormErr),
+        // Zone transfers are served over TCP only, and the remaining
+        // meta-types are not implemented.
+        qtype if rtype::is_meta(qtype) => Some(Rcode::NotImp),
+        _ => None,
+    }
+}
+
+/// Whether `client`, signing with `key`, may perform `operation` on every
+/// name `request` asks about.
+pub fn request_allowed(request: &Message, operation: Operation, client: IpAddr, key: Option<&[u8]>, config: &Config) -> bool {
+    request.questions
+        .iter()
+        .all(|question| config.access.allows(operation, &question.name, client, key))
+}
+
+/// Whether `client`, signing with `key`, gets answers from upstream for
+/// `request`: the view forwards somewhere and lets the client recurse for
+/// every name asked about. A request without questions is checked against
+/// the root.
+pub fn recursion_available(request: &Message, client: IpAddr, key: Option<&[u8]>, config: &Config) -> bool {
+    if !config.forwarding() {
+        return false;
+    }
+    if request.questions.is_empty() {
+        return config.access.allows(Operation::Recursion, &[], client, key);
+    }
+    request_allowed(request, Operation::Recursion, client, key, config)
+}
+
+/// The RCODE for a request that cannot be answered by looking up its
+/// questions: an opcode other than QUERY, or a question we do not serve.
+fn request_rcode(request: &Message) -> Option<Rcode> {
+    if request.header.opcode != Opcode::Query {
+        return Some(Rcode::NotImp);
     }
+    request.questions.iter().find_map(question_rcode)
 }
 
-pub fn build_response_forward(request: Message, resolver: String, socket: &UdpSocket) -> Message {
-    let mut answers = vec![];
-    let mut questions = vec![];
+pub fn build_response(
+    request: Message,
+    zones: &ZoneStore,
+    cache: &mut Cache,
+    mut signer: Option<&mut Signer>,
+    config: &Config,
+) -> Message {
+    if let Some(rcode) = request_rcode(&request) {
+        return Response::error(&request, rcode, false);
+    }
+    let mut response = Response::new(&request, false);
+    let dnssec_ok = response.dnssec_ok();
 
     for question in request.questions {
-        
-        let relay: Message = Message {
-            header: request.header.clone(),
-            questions: vec![question.clone()],
-            answers: vec![],
+        let mut chain = follow_chain(&question.name, question.qtype, zones, cache, config);
+        if let Some(signer) = signer.as_deref_mut().filter(|_| dnssec_ok) {
+            signer.sign_chain(&mut chain, &question.name, question.qtype, zones);
+        }
+        response.set_rcode(chain.end.rcode());
+        // Without a resolver to ask, names outside our zones are refused.
+        if chain.answers.is_empty() && matches!(chain.end, ChainEnd::Unresolved(_)) {
+            response.set_rcode(Rcode::Refused);
+        }
+        chain.fill_additionals(zones, cache);
+        response.add_chain(question, chain);
+    }
+
+    response.build()
+}
+
+/// Sends `question` to `resolver` over UDP, retrying over TCP when the
+/// answer is truncated. With `dnssec_ok` the query carries the DO bit so
+/// the resolver includes signatures and denial records.
+pub fn query_upstream(header: &Header, question: Question, resolver: &str, dnssec_ok: bool) -> Result<Message> {
+    let mut header = header.clone();
+    header.id = rand::random();
+    header.qr = false;
+    header.qdcount = 1;
+    header.ancount = 0;
+    header.nscount = 0;
+    header.arcount = 1;
+    let relay: Message = Message {
+        header,
+        questions: vec![question],
+        answers: vec![],
+        authorities: vec![],
+        additionals: vec![Edns::new(dnssec_ok).record()],
+    };
+    let id = relay.header.id;
+
+    println!("Relaying request to resolver: {}", resolver);
+    let relay_bytes = relay.encode();
+
+    // A socket of our own, so that replies cannot be confused with client
+    // queries arriving on the listening socket.
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.set_read_timeout(Some(UPSTREAM_TIMEOUT))?;
+    socket.send_to(&relay_bytes, resolver)?;
+
+    let mut relayed_buffer = vec![0; EDNS_PAYLOAD_SIZE as usize];
+    let response = loop {
+        let (size, source) = socket.recv_from(&mut relayed_buffer)?;
+        // Anyone can send us garbage, so it is ignored like any other
+        // reply that is not the one we wait for.
+        let Ok(response) = Message::decode(&relayed_buffer[..size]) else {
+            continue;
         };
+        let expected = resolver.to_socket_addrs()?.any(|addr| addr == source);
+        if expected && response.header.id == id {
+            break response;
+        }
+    };
 
-        println!("Relaying request to resolver: {}", resolver);
-        let relay_bytes = relay.encode();
+    let response = if response.header.tc {
+        println!("Truncated response from resolver, retrying over TCP.");
+        let addr = resolver
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| anyhow!("Cannot resolve {}", resolver))?;
+        let mut stream = TcpStream::connect_timeout(&addr, UPSTREAM_TIMEOUT)?;
+        stream.set_read_timeout(Some(UPSTREAM_TIMEOUT))?;
+        write_message(&mut stream, &relay_bytes)?;
+        let bytes = read_message(&mut stream)?.ok_or_else(|| anyhow!("Resolver closed the connection"))?;
+        Message::decode(&bytes)?
+    } else {
+        response
+    };
 
-        socket
-            .send_to(&relay_bytes, &resolver)
-            .expect("Failed to send request to resolver");
+    println!("Got response from resolver.");
+    Ok(response)
+}
 
-        let mut relayed_buffer: [u8; 512] = [0; 512];
-        socket.set_read_timeout(Some(std::time::Duration::from_secs(5)))
-            .expect("Failed to set read timeout");
+/// Whether a record only matters to DNSSEC-aware clients (RFC 4035
+/// section 3.2.1).
+fn is_dnssec_record(record: &Answer, qtype: u16) -> bool {
+    matches!(record.qtype, rtype::RRSIG | rtype::NSEC | rtype::NSEC3) && record.qtype != qtype
+}
 
-        let size= socket
-            .recv(&mut relayed_buffer)
-            .expect("Failed to receive response from resolver");
+/// Answers from local data where possible and forwards the rest to the
+/// resolver configured for each name, applying response policy zones to
+/// the result. `None` when a policy says to drop the request. The view's
+/// zones and cache are only locked around local work, never while waiting
+/// on a resolver. `client` is where the request came from, for policies
+/// triggered by it.
+pub fn build_response_forward(
+    request: Message,
+    client: IpAddr,
+    zones: &RwLock<ZoneStore>,
+    cache: &Mutex<Cache>,
+    validator: Option<&Validator>,
+    signer: Option<&Mutex<Signer>>,
+    config: &Config,
+) -> Option<Message> {
+    if let Some(rcode) = request_rcode(&request) {
+        return Some(Response::error(&request, rcode, true));
+    }
+    let mut response = Response::new(&request, true);
+    let dnssec_ok = response.dnssec_ok();
+    let mut secure = validator.is_some();
 
-        let response = Message::decode(&relayed_buffer[..size]);
+    for question in request.questions.iter().cloned() {
+        let (mut chain, query_hit) = {
+            let zones = zones.read().unwrap();
+            let mut chain = follow_chain(&question.name, question.qtype, &zones, &mut cache.lock().unwrap(), config);
+            if let Some(signer) = signer.filter(|_| dnssec_ok) {
+                signer.lock().unwrap().sign_chain(&mut chain, &question.name, question.qtype, &zones);
+            }
+            let query_hit = zones.policy().check_query(client, &question.name, &chain.answers);
+            (chain, query_hit)
+        };
 
-        println!("Got response from resolver.");
+        // Only the final target of the chain needs to go upstream; the
+        // resolver answers with the rest of the chain from there. Names we
+        // delegate away are resolved upstream rather than referred. Nothing
+        // is forwarded for a query a policy already rewrites.
+        let mut rcode = chain.end.rcode();
+        let upstream_target = match &chain.end {
+            _ if query_hit.as_ref().is_some_and(|hit| !matches!(hit.action, PolicyAction::Passthru)) => None,
+            ChainEnd::Unresolved(target) | ChainEnd::Referral(target) => {
+                config.resolver_for(target).map(|resolver| (target.clone(), resolver))
+            }
+            _ => None,
+        };
+        if let Some((target, resolver)) = upstream_target {
+            let relay_question = Question {
+                name: target,
+                qtype: question.qtype,
+                qclass: question.qclass,
+            };
+            let upstream = match query_upstream(&request.header, relay_question.clone(), resolver, validator.is_some()) {
+                Ok(upstream) => upstream,
+                Err(e) => {
+                    eprintln!("Failed to query resolver {}: {}", resolver, e);
+                    return Some(Response::error(&request, Rcode::ServFail, true));
+                }
+            };
+            let security = match validator {
+                Some(validator) => validator.validate(&relay_question, &upstream, resolver),
+                None => Security::Insecure,
+            };
+            match &security {
+                Security::Secure => {}
+                Security::Insecure => chain.secure = false,
+                Security::Bogus(reason) => {
+                    println!("Bogus answer for {}: {}", name_to_string(&relay_question.name), reason);
+                    // With CD set the client validates for itself (RFC 4035
+                    // section 3.2.2).
+                    if !request.header.cd {
+                        return Some(Response::error(&request, Rcode::ServFail, true));
+                    }
+                    chain.secure = false;
+                }
+            }
+            if !matches!(security, Security::Bogus(_)) {
+                // The cache answers every client of the view, so it only
+                // takes what the question proves, and from a per-domain
+                // forwarder only names in its domain.
+                let domain = config.forward_zone(&relay_question.name).map(|forward| forward.origin.as_slice());
+                let cacheable: Vec<Answer> = answer_chain(&upstream.answers, &relay_question.name, relay_question.qtype)
+                    .into_iter()
+                    .filter(|record| record.qtype != rtype::RRSIG)
+                    .filter(|record| match domain {
+                        Some(domain) => is_subdomain(&record.name, domain),
+                        None => true,
+                    })
+                    .collect();
+                cache.lock().unwrap().insert(&cacheable, security == Security::Secure);
+            }
+            let cloaked = cloaked_cname(&upstream.answers, question.qtype, &zones.read().unwrap());
+            if let Some((end, lookup)) = cloaked {
+                chain.answers.extend(upstream.answers.into_iter().take(end + 1));
+                chain.authorities.clear();
+                chain.additionals.clear();
+                chain = chain.finish_local(lookup, false);
+                rcode = chain.end.rcode();
+            } else {
+                rcode = upstream.header.rcode;
+                chain.answers.extend(upstream.answers);
+                chain.authorities = upstream.authorities;
+                chain.additionals = upstream.additionals
+                    .into_iter()
+                    .filter(|record| record.qtype != rtype::OPT)
+                    .collect();
+            }
+        } else if chain.answers.is_empty() && matches!(chain.end, ChainEnd::Unresolved(_)) {
+            // No forwarding rule covers the name and there is no default
+            // resolver to ask.
+            rcode = Rcode::Refused;
+        }
 
-        questions.extend(vec![question.clone()]);
-        answers.extend(if response.answers.len() > 0 {
-            response.answers
-        } else {
-            vec![Answer::builder()
-                .name_bytes(question.name.clone())
-                .unwrap()
-                .qtype(question.qtype)
-                .unwrap()
-                .qclass(question.qclass)
-                .unwrap()
-                .ttl(60)
-                .unwrap()
-                .length(4)
-                .unwrap()
-                .data(vec![8, 8, 8, 8])
-                .unwrap()
-                .build()]
-        });
+        // Address and nameserver triggers can only be checked against the
+        // answer.
+        let zones = zones.read().unwrap();
+        let hit = match query_hit {
+            Some(hit) => Some(hit),
+            None => zones.policy().check(&question.name, &chain.answers, &chain.authorities),
+        };
+        if let Some(hit) = hit {
+            println!(
+                "Policy zone {} matched {} ({:?} trigger)",
+                name_to_string(&hit.zone),
+                name_to_string(&hit.name),
+                hit.trigger
+            );
+            match hit.action {
+                PolicyAction::Drop => return None,
+                PolicyAction::Passthru => {}
+                action => {
+                    let local_data = matches!(action, PolicyAction::LocalData(_));
+                    chain = chain.rewrite(&question.name, &hit.name, action, question.qtype);
+                    if local_data {
+                        chain = chain.follow_policy_cname(question.qtype, &zones, &mut cache.lock().unwrap(), config);
+                    }
+                    rcode = chain.end.rcode();
+                }
+            }
+        }
+        response.set_rcode(rcode);
+        chain.fill_additionals(&zones, &mut cache.lock().unwrap());
+        drop(zones);
+        secure &= chain.secure;
+
+        if !dnssec_ok {
+            for section in [&mut chain.answers, &mut chain.authorities, &mut chain.additionals] {
+                section.retain(|record| !is_dnssec_record(record, question.qtype));
+            }
+        }
+        response.add_chain(question, chain);
     }
+    response.set_authenticated(secure);
 
-    Message {
-        header: Header::builder()
-            .qdcount(questions.len() as u16)
-            .unwrap()
-            .ancount(answers.len() as u16)
-            .unwrap()
-            .id(request.header.id)
-            .unwrap()
-            .opcode(request.header.opcode)
-            .unwrap()
-            .rd(request.header.rd)
-            .unwrap()
-            .rcode(if request.header.opcode == 0 { 0 } else { 4 })
-            .unwrap()
-            .build(),
-        questions: questions,
-        answers: answers,
+    Some(response.build())
+}
+
+/// Answers a request from `source` that passed TSIG verification and rate
+/// limiting, over either transport: UPDATEs are applied, and queries are
+/// answered from local data or, for clients allowed to recurse, forwarded.
+/// `None` when a policy says to drop the request.
+pub fn answer_request(
+    request: Message,
+    source: SocketAddr,
+    key: Option<&[u8]>,
+    view: &View,
+    validator: Option<&Validator>,
+    signer: Option<&Mutex<Signer>>,
+) -> Option<Message> {
+    let client = source.ip();
+    let recursion = recursion_available(&request, client, key, &view.config);
+    if request.header.opcode == Opcode::Update {
+        println!("Handling UPDATE.");
+        Some(handle_update(request, source, key, &view.zones, &view.config))
+    } else if !request_allowed(&request, Operation::Query, client, key, &view.config) {
+        println!("Refusing query from {}", source);
+        Some(error_response(&request, Rcode::Refused, recursion))
+    } else if recursion {
+        println!("Forwarding request to resolver.");
+        build_response_forward(request, client, &view.zones, &view.cache, validator, signer, &view.config)
+    } else {
+        // The cache only holds what upstream resolvers returned, so clients
+        // without recursion get local data alone.
+        println!("Directly building response.");
+        Some(build_response(
+            request,
+            &view.zones.read().unwrap(),
+            &mut Cache::new(),
+            signer.map(|signer| signer.lock().unwrap()).as_deref_mut(),
+            &view.config,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zone::{parse_records, Zone};
+
+    const ZONE: &str = "$ORIGIN example.com.
+@ SOA ns admin 1 3600 600 86400 300
+www CNAME web
+web CNAME host
+host A 10.0.0.2
+loop1 CNAME loop2
+loop2 CNAME loop1
+ext CNAME www.example.org.
+";
+
+    fn zones(text: &str) -> ZoneStore {
+        let mut zones = ZoneStore::new();
+        zones.insert(Zone::parse(text).unwrap());
+        zones
+    }
+
+    fn chain(zones: &ZoneStore, qname: &str, qtype: u16) -> Chain {
+        follow_chain(&encode_name(qname), qtype, zones, &mut Cache::new(), &Config::default())
+    }
+
+    #[test]
+    fn follows_cnames_to_the_answer() {
+        let chain = chain(&zones(ZONE), "www.example.com", rtype::A);
+        assert!(matches!(chain.end, ChainEnd::Answered));
+        assert!(chain.authoritative);
+        let types: Vec<u16> = chain.answers.iter().map(|record| record.qtype).collect();
+        assert_eq!(types, [rtype::CNAME, rtype::CNAME, rtype::A]);
+    }
+
+    #[test]
+    fn stops_where_local_data_ends() {
+        let chain = chain(&zones(ZONE), "ext.example.com", rtype::A);
+        assert_eq!(chain.answers.len(), 1);
+        assert!(matches!(&chain.end, ChainEnd::Unresolved(name) if names_equal(name, &encode_name("www.example.org"))));
+    }
+
+    #[test]
+    fn detects_loops() {
+        let chain = chain(&zones(ZONE), "loop1.example.com", rtype::A);
+        assert!(matches!(chain.end, ChainEnd::Loop));
+        assert_eq!(chain.answers.len(), 2);
+        assert_eq!(chain.end.rcode(), Rcode::ServFail);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn bounds_chain_length() {
+        let mut text = "$ORIGIN example.com.\n@ SOA ns admin 1 3600 600 86400 300\n".to_owned();
+        for link in 0..MAX_CHAIN_LENGTH + 4 {
+            text.push_str(&format!("c{} CNAME c{}\n", link, link + 1));
+        }
+        text.push_str(&format!("c{} A 10.0.0.1\n", MAX_CHAIN_LENGTH + 4));
+
+        let chain = chain(&zones(&text), "c0.example.com", rtype::A);
+        assert!(matches!(chain.end, ChainEnd::Loop));
+        assert_eq!(chain.answers.len(), MAX_CHAIN_LENGTH);
+    }
+
+    #[test]
+    fn additionals_carry_target_addresses() {
+        let zones = zones(
+            "$ORIGIN example.com.
+@ SOA ns admin 1 3600 600 86400 300
+@ NS ns1
+@ MX 10 mail
+@ MX 20 mail
+_sip._tcp SRV 0 5 5060 sip
+@ NS ns.example.org.
+ns1 A 10.0.0.1
+mail A 10.0.0.2
+mail AAAA ::2
+sip A 10.0.0.3
+",
+        );
+        let addresses = |name: &str, qtype: u16, existing: &[Answer], cache: &mut Cache| {
+            let name = encode_name(name);
+            let records = match zones.find_zone(&name).unwrap().lookup(&name, qtype) {
+                Lookup::Records(records) => records,
+                other => panic!("expected records, got {:?}", other),
+            };
+            additional_records(&records, existing, &zones, cache)
+                .iter()
+                .map(|record| (name_to_string(&record.name), record.qtype))
+                .collect::<Vec<_>>()
+        };
+
+        // Both MX records share a target, which is only added once.
+        let mail = addresses("example.com", rtype::MX, &[], &mut Cache::new());
+        assert_eq!(mail, [("mail.example.com".to_owned(), rtype::A), ("mail.example.com".to_owned(), rtype::AAAA)]);
+        let sip = addresses("_sip._tcp.example.com", rtype::SRV, &[], &mut Cache::new());
+        assert_eq!(sip, [("sip.example.com".to_owned(), rtype::A)]);
+
+        // Out-of-zone targets come from the cache, when it has them.
+        let glue = parse_records("$ORIGIN example.org.\nns A 192.0.2.1\n").unwrap();
+        let mut cache = Cache::new();
+        assert_eq!(addresses("example.com", rtype::NS, &[], &mut cache).len(), 1);
+        cache.insert(&glue, false);
+        assert_eq!(addresses("example.com", rtype::NS, &[], &mut cache).len(), 2);
+
+        // Nothing already in the response is repeated.
+        assert_eq!(addresses("example.com", rtype::NS, &glue, &mut cache).len(), 1);
+    }
+
+    #[test]
+    fn error_responses_report_recursion_for_the_client() {
+        let mut query = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        query.extend(encode_name("www.example.com"));
+        query.extend([0, 0, 1, 0, 1]);
+        let mut config = Config::from_args(&["dns".to_owned(), "--resolver".to_owned(), "192.0.2.53:53".to_owned()]).unwrap();
+        config.access.push(Operation::Recursion, None, "10.0.0.0/8".parse().unwrap());
+        let inside: IpAddr = "10.1.2.3".parse().unwrap();
+        let outside: IpAddr = "192.0.2.1".parse().unwrap();
+
+        let request = Message::decode(&query).unwrap();
+        assert!(recursion_available(&request, inside, None, &config));
+        assert!(!recursion_available(&request, outside, None, &config));
+        assert!(!recursion_available(&request, inside, None, &Config::default()));
+        assert!(error_response(&request, Rcode::Refused, true).header.ra);
+        assert!(!error_response(&request, Rcode::Refused, false).header.ra);
+
+        // A message that does not decode still gets FORMERR, with RA as
+        // the client would otherwise see it.
+        let truncated = &query[..query.len() - 2];
+        let response = format_error(truncated, inside, &config).unwrap();
+        assert_eq!((response.header.id, response.header.rcode, response.header.ra), (0x1234, Rcode::FormErr, true));
+        assert!(!format_error(truncated, outside, &config).unwrap().header.ra);
+        // Responses are never answered.
+        query[2] |= 0x80;
+        assert!(format_error(&query[..query.len() - 2], inside, &config).is_none());
+    }
+
+    #[test]
+    fn answer_chain_keeps_only_what_the_question_proves() {
+        let answers = parse_records(
+            "$ORIGIN example.
+a CNAME b
+victim A 6.6.6.6
+b A 10.0.0.1
+b AAAA ::1
+",
+        )
+        .unwrap();
+        let chain = answer_chain(&answers, &encode_name("a.example"), rtype::A);
+        let names: Vec<String> = chain.iter().map(|record| name_to_string(&record.name)).collect();
+        assert_eq!(names, ["a.example", "b.example"]);
+        assert_eq!(chain[1].qtype, rtype::A);
+
+        // The DNAME a synthesized CNAME came from belongs to the chain too.
+        let answers = parse_records(
+            "$ORIGIN example.
+old DNAME new.example.
+x.old CNAME x.new
+x.new A 10.0.0.2
+other.new A 6.6.6.6
+",
+        )
+        .unwrap();
+        let chain = answer_chain(&answers, &encode_name("x.old.example"), rtype::A);
+        let types: Vec<u16> = chain.iter().map(|record| record.qtype).collect();
+        assert_eq!(types, [rtype::DNAME, rtype::CNAME, rtype::A]);
+    }
+
+    #[test]
+    fn answer_chain_stops_on_loops() {
+        let answers = parse_records("$ORIGIN example.\na CNAME b\nb CNAME a\n").unwrap();
+        assert_eq!(answer_chain(&answers, &encode_name("a.example"), rtype::A).len(), 2);
+    }
+
+    fn policy_data(text: &str) -> PolicyAction {
+        PolicyAction::LocalData(parse_records(text).unwrap())
+    }
+
+    #[test]
+    fn policy_rewrites_keep_the_cnames_leading_to_the_trigger() {
+        let zones = zones(ZONE);
+        let qname = encode_name("ext.example.com");
+        let chain = chain(&zones, "ext.example.com", rtype::A);
+        let chain = chain.rewrite(&qname, &encode_name("WWW.example.org"), PolicyAction::NxDomain, rtype::A);
+        assert!(matches!(chain.end, ChainEnd::NxDomain));
+        assert_eq!(chain.answers.len(), 1);
+        assert!(!chain.authoritative);
+
+        let chain = self::chain(&zones, "ext.example.com", rtype::A);
+        let chain = chain.rewrite(&qname, &qname, PolicyAction::NoData, rtype::A);
+        assert!(matches!(chain.end, ChainEnd::NoData));
+        assert!(chain.answers.is_empty());
+    }
+
+    #[test]
+    fn policy_data_answers_for_the_matched_name() {
+        let zones = zones(ZONE);
+        let qname = encode_name("x.ads.test");
+        let action = policy_data("*.ads.test.rpz.local. A 10.9.9.9\n");
+        let chain = chain(&zones, "x.ads.test", rtype::A).rewrite(&qname, &qname, action.clone(), rtype::A);
+        assert!(matches!(chain.end, ChainEnd::Answered));
+        assert_eq!(chain.answers[0].name, qname);
+        assert_eq!(chain.answers[0].rdata().unwrap(), RData::A("10.9.9.9".parse().unwrap()));
+
+        let chain = self::chain(&zones, "x.ads.test", rtype::AAAA).rewrite(&qname, &qname, action, rtype::AAAA);
+        assert!(matches!(chain.end, ChainEnd::NoData));
+    }
+
+    #[test]
+    fn follows_policy_cnames_through_local_data() {
+        let zones = zones(ZONE);
+        let config = Config::default();
+        let qname = encode_name("blocked.test");
+        let rewrite = |target: &str| {
+            let action = policy_data(&format!("blocked.test.rpz.local. CNAME {}\n", target));
+            chain(&zones, "blocked.test", rtype::A)
+                .rewrite(&qname, &qname, action, rtype::A)
+                .follow_policy_cname(rtype::A, &zones, &mut Cache::new(), &config)
+        };
+
+        let chain = rewrite("www.example.com.");
+        assert!(matches!(chain.end, ChainEnd::Answered));
+        let types: Vec<u16> = chain.answers.iter().map(|record| record.qtype).collect();
+        assert_eq!(types, [rtype::CNAME, rtype::CNAME, rtype::CNAME, rtype::A]);
+
+        // A target we know nothing about is left for the client.
+        let chain = rewrite("www.example.org.");
+        assert!(matches!(chain.end, ChainEnd::Answered));
+        assert_eq!(chain.answers.len(), 1);
+    }
+}
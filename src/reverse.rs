@@ -0,0 +1,175 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use anyhow::{Result, anyhow};
+
+use crate::acl::Cidr;
+use crate::message::*;
+use crate::zone::Lookup;
+
+const SYNTHESIZED_TTL: u32 = 300;
+const HOST_PREFIX: &str = "ip-";
+
+/// A network whose addresses get synthesized names under `domain`, such as
+/// `ip-192-0-2-1.<domain>`, with PTR records pointing at them.
+#[derive(Debug, Clone)]
+pub struct ReverseNetwork {
+    pub network: Cidr,
+    pub domain: Vec<u8>,
+}
+
+impl FromStr for ReverseNetwork {
+    type Err = anyhow::Error;
+
+    /// Parses `<network>/<prefix>=<domain>`.
+    fn from_str(s: &str) -> Result<ReverseNetwork> {
+        let (network, domain) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Reverse network must be <network>/<prefix>=<domain>"))?;
+        Ok(ReverseNetwork { network: network.parse()?, domain: encode_name(domain) })
+    }
+}
+
+/// The synthesized host name of `addr`: its address with dashes for dots
+/// or colons, `ip-2001-db8-0-0-0-0-0-1` for IPv6.
+fn host_name(addr: IpAddr, domain: &[u8]) -> Vec<u8> {
+    let label = match addr {
+        IpAddr::V4(addr) => addr.octets().map(|octet| octet.to_string()).join("-"),
+        IpAddr::V6(addr) => addr.segments().map(|segment| format!("{:x}", segment)).join("-"),
+    };
+    let mut name = encode_name(&format!("{}{}", HOST_PREFIX, label));
+    name.extend(domain);
+    name
+}
+
+/// The address named by the first label of a synthesized host name.
+fn host_address(label: &[u8]) -> Option<IpAddr> {
+    let label = std::str::from_utf8(label).ok()?.to_ascii_lowercase();
+    let parts: Vec<&str> = label.strip_prefix(HOST_PREFIX)?.split('-').collect();
+    match parts.len() {
+        4 => {
+            let octets: Vec<u8> = parts.iter().map(|part| part.parse().ok()).collect::<Option<_>>()?;
+            Some(IpAddr::V4(Ipv4Addr::new(octets[0], octets[1], octets[2], octets[3])))
+        }
+        8 => {
+            let segments: Vec<u16> = parts
+                .iter()
+                .map(|part| u16::from_str_radix(part, 16).ok())
+                .collect::<Option<_>>()?;
+            let segments: [u16; 8] = segments.try_into().ok()?;
+            Some(IpAddr::V6(Ipv6Addr::from(segments)))
+        }
+        _ => None,
+    }
+}
+
+fn synthesized_record(name: &[u8], qtype: u16, rdata: &RData) -> Answer {
+    Answer::builder()
+        .name_bytes(name.to_vec())
+        .unwrap()
+        .qtype(qtype)
+        .unwrap()
+        .ttl(SYNTHESIZED_TTL)
+        .unwrap()
+        .rdata(rdata)
+        .unwrap()
+        .build()
+}
+
+/// Answers for names no zone holds data for: PTR records for the reverse
+/// names of addresses in `networks`, and the matching A or AAAA records for
+/// the host names those PTRs point to, so that every synthesized PTR is
+/// forward-confirmed. `None` when `name` is not a synthesized name.
+pub fn synthesize(name: &[u8], qtype: u16, networks: &[ReverseNetwork]) -> Option<Lookup> {
+    if let Some(addr) = reverse_name_to_ip(name) {
+        let network = networks.iter().find(|network| network.network.contains(addr))?;
+        let ptr = RData::Ptr(host_name(addr, &network.domain));
+        return Some(match qtype {
+            rtype::PTR | rtype::ANY => Lookup::Records(vec![synthesized_record(name, rtype::PTR, &ptr)]),
+            _ => Lookup::NoData,
+        });
+    }
+
+    let label = labels(name).first()?.to_vec();
+    let addr = host_address(&label)?;
+    let domain = parent_name(name)?;
+    // Only names in exactly the form a PTR would point to are answered,
+    // so `ip-192-0-2-01` does not alias `ip-192-0-2-1`.
+    if !names_equal(&host_name(addr, &domain), name) {
+        return None;
+    }
+    let configured = networks
+        .iter()
+        .any(|network| network.network.contains(addr) && names_equal(&network.domain, &domain));
+    if !configured {
+        return None;
+    }
+    let (address_type, rdata) = match addr {
+        IpAddr::V4(addr) => (rtype::A, RData::A(addr)),
+        IpAddr::V6(addr) => (rtype::AAAA, RData::Aaaa(addr)),
+    };
+    Some(if qtype == address_type || qtype == rtype::ANY {
+        Lookup::Records(vec![synthesized_record(name, address_type, &rdata)])
+    } else {
+        Lookup::NoData
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn networks() -> Vec<ReverseNetwork> {
+        vec![
+            "192.0.2.0/24=hosts.example".parse().unwrap(),
+            "2001:db8::/32=v6.example".parse().unwrap(),
+        ]
+    }
+
+    fn records(lookup: Option<Lookup>) -> Vec<RData> {
+        match lookup {
+            Some(Lookup::Records(records)) => records.iter().map(|record| record.rdata().unwrap()).collect(),
+            other => panic!("expected records, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn host_names_round_trip() {
+        for addr in ["192.0.2.1", "2001:db8::1"] {
+            let addr: IpAddr = addr.parse().unwrap();
+            let name = host_name(addr, &encode_name("example"));
+            assert_eq!(host_address(labels(&name)[0]), Some(addr));
+        }
+        assert_eq!(name_to_string(&host_name("192.0.2.1".parse().unwrap(), &[])), "ip-192-0-2-1");
+        assert_eq!(host_address(b"ip-192-0-2"), None);
+        assert_eq!(host_address(b"host-192-0-2-1"), None);
+    }
+
+    #[test]
+    fn synthesizes_forward_confirmed_ptrs() {
+        let networks = networks();
+        let reverse = reverse_name("192.0.2.7".parse().unwrap());
+        let ptr = records(synthesize(&reverse, rtype::PTR, &networks));
+        assert_eq!(ptr, [RData::Ptr(encode_name("ip-192-0-2-7.hosts.example"))]);
+
+        let forward = records(synthesize(&encode_name("ip-192-0-2-7.hosts.example"), rtype::A, &networks));
+        assert_eq!(forward, [RData::A("192.0.2.7".parse().unwrap())]);
+        let forward = records(synthesize(&encode_name("ip-2001-db8-0-0-0-0-0-1.v6.example"), rtype::AAAA, &networks));
+        assert_eq!(forward, [RData::Aaaa("2001:db8::1".parse().unwrap())]);
+
+        assert!(matches!(synthesize(&reverse, rtype::A, &networks), Some(Lookup::NoData)));
+    }
+
+    #[test]
+    fn only_configured_names_are_synthesized() {
+        let networks = networks();
+        let outside = reverse_name("198.51.100.1".parse().unwrap());
+        assert!(synthesize(&outside, rtype::PTR, &networks).is_none());
+        // The address must be in a network configured for that domain.
+        assert!(synthesize(&encode_name("ip-198-51-100-1.hosts.example"), rtype::A, &networks).is_none());
+        assert!(synthesize(&encode_name("ip-192-0-2-1.v6.example"), rtype::A, &networks).is_none());
+        // Only the exact spelling a PTR points to is answered.
+        assert!(synthesize(&encode_name("ip-192-0-2-01.hosts.example"), rtype::A, &networks).is_none());
+        assert!(synthesize(&encode_name("www.hosts.example"), rtype::A, &networks).is_none());
+    }
+}
@@ -0,0 +1,429 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use anyhow::Result;
+
+use crate::acl::Cidr;
+use crate::message::*;
+use crate::zone::Zone;
+
+const IP_TRIGGER: &[u8] = b"rpz-ip";
+const CLIENT_IP_TRIGGER: &[u8] = b"rpz-client-ip";
+const NSDNAME_TRIGGER: &[u8] = b"rpz-nsdname";
+// Triggers on nameserver addresses, which we do not support.
+const UNSUPPORTED_TRIGGERS: &[&[u8]] = &[b"rpz-nsip"];
+const PASSTHRU_TARGET: &str = "rpz-passthru";
+const DROP_TARGET: &str = "rpz-drop";
+
+/// What a policy does with a response it matches.
+#[derive(Debug, Clone)]
+pub enum PolicyAction {
+    NxDomain,
+    NoData,
+    // Answer unchanged, and skip any later policy.
+    Passthru,
+    // Send no response at all.
+    Drop,
+    // Answer with the policy records instead.
+    LocalData(Vec<Answer>),
+}
+
+impl PolicyAction {
+    /// Decodes the action a trigger's records stand for: CNAMEs to `.`,
+    /// `*.`, `rpz-passthru.` and `rpz-drop.` are special, anything else is
+    /// data to answer with.
+    fn from_records(records: Vec<Answer>) -> PolicyAction {
+        if let [record] = records.as_slice() {
+            let target = record
+                .rdata()
+                .ok()
+                .filter(|_| record.qtype == rtype::CNAME)
+                .and_then(|rdata| rdata.target().map(|target| target.to_vec()));
+            match target {
+                Some(target) if target.is_empty() => return PolicyAction::NxDomain,
+                Some(target) if target == wildcard_name(&[]) => return PolicyAction::NoData,
+                Some(target) if names_equal(&target, &encode_name(PASSTHRU_TARGET)) => return PolicyAction::Passthru,
+                Some(target) if names_equal(&target, &encode_name(DROP_TARGET)) => return PolicyAction::Drop,
+                _ => {}
+            }
+        }
+        PolicyAction::LocalData(records)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Trigger {
+    ClientIp,
+    QName,
+    Ip,
+    NsDname,
+}
+
+/// A policy that matched a response.
+#[derive(Debug, Clone)]
+pub struct PolicyHit {
+    // Origin of the policy zone the trigger is in.
+    pub zone: Vec<u8>,
+    pub trigger: Trigger,
+    // The name whose answer the action replaces: the CNAME target for QNAME
+    // triggers on one, otherwise the question name.
+    pub name: Vec<u8>,
+    pub action: PolicyAction,
+}
+
+/// The triggers of one response policy zone.
+#[derive(Debug)]
+struct PolicyZone {
+    origin: Vec<u8>,
+    // Keyed by the lowercased name relative to the policy zone origin;
+    // `*.<name>` keys match every name below `<name>`.
+    qnames: HashMap<Vec<u8>, PolicyAction>,
+    nsdnames: HashMap<Vec<u8>, PolicyAction>,
+    // Most specific network first, as are `client_ips`.
+    ips: Vec<(Cidr, PolicyAction)>,
+    client_ips: Vec<(Cidr, PolicyAction)>,
+}
+
+impl PolicyZone {
+    fn from_zone(zone: &Zone) -> PolicyZone {
+        let mut owners: HashMap<Vec<u8>, Vec<Answer>> = HashMap::new();
+        for record in zone.records() {
+            if names_equal(&record.name, &zone.origin) {
+                continue;
+            }
+            owners.entry(lowercase_name(&record.name)).or_default().push(record.clone());
+        }
+
+        let mut policy = PolicyZone {
+            origin: zone.origin.clone(),
+            qnames: HashMap::new(),
+            nsdnames: HashMap::new(),
+            ips: vec![],
+            client_ips: vec![],
+        };
+        for (owner, records) in owners {
+            let relative = owner[..owner.len() - zone.origin.len()].to_vec();
+            let relative_labels = labels(&relative);
+            let trigger = relative_labels.last().copied().unwrap_or_default();
+            let action = PolicyAction::from_records(records);
+            if trigger == IP_TRIGGER || trigger == CLIENT_IP_TRIGGER {
+                let networks = if trigger == IP_TRIGGER { &mut policy.ips } else { &mut policy.client_ips };
+                match ip_trigger(&relative_labels[..relative_labels.len() - 1]) {
+                    Some(network) => networks.push((network, action)),
+                    None => eprintln!("Invalid IP trigger {}", name_to_string(&owner)),
+                }
+            } else if trigger == NSDNAME_TRIGGER {
+                let name = relative[..relative.len() - NSDNAME_TRIGGER.len() - 1].to_vec();
+                policy.nsdnames.insert(name, action);
+            } else if UNSUPPORTED_TRIGGERS.contains(&trigger) {
+                eprintln!("Ignoring unsupported trigger {}", name_to_string(&owner));
+            } else {
+                policy.qnames.insert(relative, action);
+            }
+        }
+        policy.ips.sort_by_key(|(network, _)| std::cmp::Reverse(network.prefix()));
+        policy.client_ips.sort_by_key(|(network, _)| std::cmp::Reverse(network.prefix()));
+        policy
+    }
+
+    fn hit(&self, trigger: Trigger, name: &[u8], action: &PolicyAction) -> PolicyHit {
+        PolicyHit {
+            zone: self.origin.clone(),
+            trigger,
+            name: name.to_vec(),
+            action: action.clone(),
+        }
+    }
+}
+
+fn match_ip(networks: &[(Cidr, PolicyAction)], address: IpAddr) -> Option<&PolicyAction> {
+    networks
+        .iter()
+        .find(|(network, _)| network.contains(address))
+        .map(|(_, action)| action)
+}
+
+/// The question name and the CNAME targets in `answers`, which QNAME
+/// triggers apply to.
+fn chain_names(qname: &[u8], answers: &[Answer]) -> Vec<Vec<u8>> {
+    let mut names = vec![qname.to_vec()];
+    for record in answers {
+        if let Ok(RData::Cname(target)) = record.rdata() {
+            names.push(target);
+        }
+    }
+    names
+}
+
+/// Parses the labels of an `rpz-ip` trigger owner, the prefix length
+/// followed by the address in reverse: `24.0.2.0.192` for 192.0.2.0/24,
+/// or `48.zz.db8.2001` for 2001:db8::/48 with `zz` standing for `::`.
+fn ip_trigger(trigger_labels: &[&[u8]]) -> Option<Cidr> {
+    let trigger_labels: Vec<String> = trigger_labels
+        .iter()
+        .map(|label| String::from_utf8_lossy(label).to_ascii_lowercase())
+        .collect();
+    let (prefix, parts) = trigger_labels.split_first()?;
+    let parts: Vec<&str> = parts.iter().rev().map(|part| part.as_str()).collect();
+    let address = if parts.len() == 4 && parts.iter().all(|part| part.parse::<u8>().is_ok()) {
+        parts.join(".")
+    } else {
+        let address = parts
+            .iter()
+            .map(|part| if *part == "zz" { "" } else { part })
+            .collect::<Vec<_>>()
+            .join(":");
+        // `zz` at either end needs the second colon of `::` spelled out.
+        match (address.starts_with(':'), address.ends_with(':')) {
+            (true, _) => format!(":{}", address),
+            (_, true) => format!("{}:", address),
+            _ => address,
+        }
+    };
+    format!("{}/{}", address, prefix).parse().ok()
+}
+
+/// Looks `name` up among name triggers, preferring an exact match and then
+/// the closest wildcard.
+fn match_name<'a>(triggers: &'a HashMap<Vec<u8>, PolicyAction>, name: &[u8]) -> Option<&'a PolicyAction> {
+    let name = lowercase_name(name);
+    if let Some(action) = triggers.get(&name) {
+        return Some(action);
+    }
+    let mut ancestor = parent_name(&name);
+    while let Some(parent) = ancestor {
+        if let Some(action) = triggers.get(&wildcard_name(&parent)) {
+            return Some(action);
+        }
+        ancestor = parent_name(&parent);
+    }
+    None
+}
+
+/// Response policy zones (RPZ), consulted in the order they were
+/// configured.
+#[derive(Debug, Default)]
+pub struct ResponsePolicy {
+    zones: Vec<PolicyZone>,
+}
+
+impl ResponsePolicy {
+    pub fn load(paths: &[String]) -> Result<ResponsePolicy> {
+        let mut zones = vec![];
+        for path in paths {
+            let zone = Zone::load(path)?;
+            zones.push(PolicyZone::from_zone(&zone));
+        }
+        Ok(ResponsePolicy { zones })
+    }
+
+    /// Finds the policy for a query from `client` before anything is
+    /// forwarded, so that a blocked name is never looked up. Each zone is
+    /// checked in turn for client IP triggers, then QNAME triggers on
+    /// `qname` and the CNAME targets in the local `answers` leading from
+    /// it; the first match wins.
+    pub fn check_query(&self, client: IpAddr, qname: &[u8], answers: &[Answer]) -> Option<PolicyHit> {
+        let names = chain_names(qname, answers);
+        for zone in &self.zones {
+            if let Some(action) = match_ip(&zone.client_ips, client) {
+                return Some(zone.hit(Trigger::ClientIp, qname, action));
+            }
+            for name in &names {
+                if let Some(action) = match_name(&zone.qnames, name) {
+                    return Some(zone.hit(Trigger::QName, name, action));
+                }
+            }
+        }
+        None
+    }
+
+    /// Finds the policy for a response to `qname`. Each zone is checked in
+    /// turn for QNAME triggers on the question name and CNAME targets in
+    /// `answers`, then IP triggers on the addresses in `answers`, then
+    /// NSDNAME triggers on the nameservers in `answers` and `authorities`;
+    /// the first match wins.
+    pub fn check(&self, qname: &[u8], answers: &[Answer], authorities: &[Answer]) -> Option<PolicyHit> {
+        let names = chain_names(qname, answers);
+        let mut addresses = vec![];
+        let mut nameservers = vec![];
+        for record in answers {
+            match record.rdata() {
+                Ok(RData::A(address)) => addresses.push(IpAddr::V4(address)),
+                Ok(RData::Aaaa(address)) => addresses.push(IpAddr::V6(address)),
+                _ => {}
+            }
+        }
+        for record in answers.iter().chain(authorities) {
+            if let Ok(RData::Ns(nameserver)) = record.rdata() {
+                nameservers.push(nameserver);
+            }
+        }
+
+        for zone in &self.zones {
+            for name in &names {
+                if let Some(action) = match_name(&zone.qnames, name) {
+                    return Some(zone.hit(Trigger::QName, name, action));
+                }
+            }
+            for &address in &addresses {
+                if let Some(action) = match_ip(&zone.ips, address) {
+                    return Some(zone.hit(Trigger::Ip, qname, action));
+                }
+            }
+            for nameserver in &nameservers {
+                if let Some(action) = match_name(&zone.nsdnames, nameserver) {
+                    return Some(zone.hit(Trigger::NsDname, qname, action));
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zone::parse_records;
+
+    const POLICY: &str = "$ORIGIN rpz.local.
+@ SOA ns admin 1 3600 600 86400 300
+@ NS ns
+nx.shop.test CNAME .
+*.ads.test CNAME *.
+ok.ads.test CNAME rpz-passthru.
+drop.test CNAME rpz-drop.
+blocked.test CNAME www.example.com.
+data.test A 10.9.9.9
+24.0.2.0.192.rpz-ip CNAME .
+32.1.2.0.192.rpz-ip CNAME rpz-passthru.
+48.zz.db8.2001.rpz-ip CNAME *.
+8.0.0.0.10.rpz-client-ip CNAME rpz-drop.
+ns.evil.test.rpz-nsdname CNAME .
+32.1.0.0.127.rpz-nsip CNAME .
+";
+
+    fn policy(text: &str) -> ResponsePolicy {
+        ResponsePolicy { zones: vec![PolicyZone::from_zone(&Zone::parse(text).unwrap())] }
+    }
+
+    fn client() -> IpAddr {
+        "192.168.1.1".parse().unwrap()
+    }
+
+    fn check_query(policy: &ResponsePolicy, qname: &str) -> Option<PolicyHit> {
+        policy.check_query(client(), &encode_name(qname), &[])
+    }
+
+    fn check(policy: &ResponsePolicy, qname: &str, answers: &str) -> Option<PolicyHit> {
+        policy.check(&encode_name(qname), &parse_records(answers).unwrap(), &[])
+    }
+
+    #[test]
+    fn decodes_actions() {
+        let policy = policy(POLICY);
+        let action = |qname| check_query(&policy, qname).unwrap().action;
+        assert!(matches!(action("nx.shop.test"), PolicyAction::NxDomain));
+        assert!(matches!(action("tracker.ads.test"), PolicyAction::NoData));
+        assert!(matches!(action("ok.ads.test"), PolicyAction::Passthru));
+        assert!(matches!(action("drop.test"), PolicyAction::Drop));
+        match action("blocked.test") {
+            PolicyAction::LocalData(records) => {
+                assert_eq!(records[0].rdata().unwrap(), RData::Cname(encode_name("www.example.com")));
+            }
+            other => panic!("unexpected action {:?}", other),
+        }
+        assert!(matches!(action("data.test"), PolicyAction::LocalData(records) if records.len() == 1));
+    }
+
+    #[test]
+    fn matches_query_names() {
+        let policy = policy(POLICY);
+        let hit = check_query(&policy, "NX.Shop.test").unwrap();
+        assert_eq!((hit.trigger, hit.zone), (Trigger::QName, encode_name("rpz.local")));
+        // Wildcards match names below the trigger, not the name itself.
+        assert!(check_query(&policy, "a.b.ads.test").is_some());
+        assert!(check_query(&policy, "ads.test").is_none());
+        assert!(check_query(&policy, "www.nx.shop.test").is_none());
+        assert!(check_query(&policy, "shop.test").is_none());
+    }
+
+    #[test]
+    fn matches_cname_targets() {
+        let policy = policy(POLICY);
+        let answers = parse_records("$ORIGIN example.com.\nwww CNAME nx.shop.test.\n").unwrap();
+        let hit = policy.check_query(client(), &encode_name("www.example.com"), &answers).unwrap();
+        assert_eq!(hit.name, encode_name("nx.shop.test"));
+
+        let hit = check(&policy, "www.example.com", "$ORIGIN example.com.\nwww CNAME x.ads.test.\n").unwrap();
+        assert_eq!((hit.trigger, hit.name), (Trigger::QName, encode_name("x.ads.test")));
+    }
+
+    #[test]
+    fn client_ips_are_checked_before_names() {
+        let policy = policy(POLICY);
+        let hit = policy.check_query("10.1.2.3".parse().unwrap(), &encode_name("nx.shop.test"), &[]).unwrap();
+        assert_eq!((hit.trigger, hit.name), (Trigger::ClientIp, encode_name("nx.shop.test")));
+        assert!(matches!(hit.action, PolicyAction::Drop));
+        assert!(policy.check_query("11.1.2.3".parse().unwrap(), &encode_name("www.test"), &[]).is_none());
+    }
+
+    #[test]
+    fn matches_answer_addresses() {
+        let policy = policy(POLICY);
+        let hit = check(&policy, "www.test", "www.test. A 192.0.2.7\n").unwrap();
+        assert_eq!((hit.trigger, hit.name), (Trigger::Ip, encode_name("www.test")));
+        assert!(matches!(hit.action, PolicyAction::NxDomain));
+        // The most specific network wins.
+        let hit = check(&policy, "www.test", "www.test. A 192.0.2.1\n").unwrap();
+        assert!(matches!(hit.action, PolicyAction::Passthru));
+        let hit = check(&policy, "www.test", "www.test. AAAA 2001:db8:0:1::5\n").unwrap();
+        assert!(matches!(hit.action, PolicyAction::NoData));
+        assert!(check(&policy, "www.test", "www.test. AAAA 2001:db9::5\n").is_none());
+        assert!(check(&policy, "www.test", "www.test. A 192.0.3.1\n").is_none());
+        // Client IP and nameserver address triggers do not apply to answers.
+        assert!(check(&policy, "www.test", "www.test. A 10.0.0.1\n").is_none());
+        assert!(check(&policy, "www.test", "www.test. A 127.0.0.1\n").is_none());
+    }
+
+    #[test]
+    fn matches_nameserver_names() {
+        let policy = policy(POLICY);
+        let authorities = parse_records("sub.test. NS NS.evil.test.\n").unwrap();
+        let hit = policy.check(&encode_name("www.sub.test"), &[], &authorities).unwrap();
+        assert_eq!((hit.trigger, hit.name), (Trigger::NsDname, encode_name("www.sub.test")));
+        assert!(check(&policy, "sub.test", "sub.test. NS ns.good.test.\n").is_none());
+    }
+
+    #[test]
+    fn earlier_zones_win() {
+        let passthru = POLICY
+            .replace("rpz.local.", "first.local.")
+            .replace("nx.shop.test CNAME .", "nx.shop.test CNAME rpz-passthru.");
+        let policy = ResponsePolicy {
+            zones: vec![
+                PolicyZone::from_zone(&Zone::parse(&passthru).unwrap()),
+                PolicyZone::from_zone(&Zone::parse(POLICY).unwrap()),
+            ],
+        };
+        let hit = check_query(&policy, "nx.shop.test").unwrap();
+        assert_eq!(hit.zone, encode_name("first.local"));
+        assert!(matches!(hit.action, PolicyAction::Passthru));
+    }
+
+    #[test]
+    fn parses_ip_triggers() {
+        let trigger = |text: &str| {
+            let labels: Vec<&[u8]> = text.split('.').map(str::as_bytes).collect();
+            ip_trigger(&labels)
+        };
+        assert_eq!(trigger("24.0.2.0.192"), "192.0.2.0/24".parse().ok());
+        assert_eq!(trigger("32.1.0.0.127"), "127.0.0.1/32".parse().ok());
+        assert_eq!(trigger("48.zz.db8.2001"), "2001:db8::/48".parse().ok());
+        assert_eq!(trigger("128.1.zz"), "::1/128".parse().ok());
+        assert_eq!(trigger("64.zz.1.db8.2001"), "2001:db8:1::/64".parse().ok());
+        assert_eq!(trigger("33.0.2.0.192"), None);
+        assert_eq!(trigger("24.2.0.192"), None);
+        assert_eq!(trigger("x.0.2.0.192"), None);
+        assert_eq!(trigger(""), None);
+    }
+}
@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::{Duration, Instant};
+
+use crate::message::*;
+
+/// Response rate limiting settings. A `rate` of zero turns it off.
+#[derive(Debug, Clone)]
+pub struct RrlSettings {
+    // Identical responses per second allowed to one client network.
+    pub rate: u32,
+    // Seconds a client's excess is remembered for, so that a flood keeps
+    // being limited for up to this long after it slows down.
+    pub window: u32,
+    // Every `slip`th limited response is sent truncated rather than
+    // dropped, so that real clients behind a forged address can retry over
+    // TCP; zero drops them all.
+    pub slip: u32,
+    pub ipv4_prefix: u8,
+    pub ipv6_prefix: u8,
+}
+
+impl Default for RrlSettings {
+    fn default() -> RrlSettings {
+        RrlSettings {
+            rate: 0,
+            window: 15,
+            slip: 2,
+            ipv4_prefix: 24,
+            ipv6_prefix: 56,
+        }
+    }
+}
+
+/// What to do with a response after rate limiting.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RrlAction {
+    Send,
+    Slip,
+    Drop,
+}
+
+/// Which responses count as the same. Negative answers are grouped by
+/// zone so that queries for random names cannot evade the limit.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum ResponseIdentity {
+    Answer { name: Vec<u8>, qtype: u16 },
+    NoData { zone: Vec<u8> },
+    NxDomain { zone: Vec<u8> },
+    Error,
+}
+
+impl ResponseIdentity {
+    fn of(response: &Message) -> ResponseIdentity {
+        let question = response.questions.first();
+        let name = question.map(|question| lowercase_name(&question.name)).unwrap_or_default();
+        let zone = response
+            .authorities
+            .iter()
+            .find(|record| record.qtype == rtype::SOA)
+            .map(|soa| lowercase_name(&soa.name))
+            .unwrap_or_else(|| name.clone());
+        match response.header.rcode {
+            Rcode::NoError if response.answers.is_empty() => ResponseIdentity::NoData { zone },
+            Rcode::NoError => ResponseIdentity::Answer {
+                name,
+                qtype: question.map(|question| question.qtype).unwrap_or_default(),
+            },
+            Rcode::NxDomain => ResponseIdentity::NxDomain { zone },
+            _ => ResponseIdentity::Error,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Account {
+    // Responses still allowed; negative while the client is over the limit.
+    balance: f64,
+    updated: Instant,
+    // Responses limited since the last one that slipped.
+    limited: u32,
+}
+
+/// Limits identical UDP responses to each client network (RRL), so that the
+/// server is a poor amplifier for reflection attacks with forged sources.
+#[derive(Debug)]
+pub struct ResponseRateLimiter {
+    settings: RrlSettings,
+    accounts: HashMap<(IpAddr, ResponseIdentity), Account>,
+    last_cleanup: Instant,
+}
+
+impl ResponseRateLimiter {
+    pub fn new(settings: RrlSettings) -> ResponseRateLimiter {
+        ResponseRateLimiter {
+            settings,
+            accounts: HashMap::new(),
+            last_cleanup: Instant::now(),
+        }
+    }
+
+    /// Charges `response` to the network of `client`.
+    pub fn check(&mut self, client: IpAddr, response: &Message) -> RrlAction {
+        let now = Instant::now();
+        let window = Duration::from_secs(self.settings.window.into());
+        if now.duration_since(self.last_cleanup) >= window {
+            self.accounts.retain(|_, account| now.duration_since(account.updated) < window);
+            self.last_cleanup = now;
+        }
+
+        let rate = f64::from(self.settings.rate);
+        let network = client_network(client, self.settings.ipv4_prefix, self.settings.ipv6_prefix);
+        let account = self
+            .accounts
+            .entry((network, ResponseIdentity::of(response)))
+            .or_insert(Account { balance: rate, updated: now, limited: 0 });
+        let elapsed = now.duration_since(account.updated).as_secs_f64();
+        account.balance = (account.balance + elapsed * rate).min(rate) - 1.0;
+        account.balance = account.balance.max(-rate * f64::from(self.settings.window));
+        account.updated = now;
+
+        if account.balance >= 0.0 {
+            return RrlAction::Send;
+        }
+        account.limited += 1;
+        if self.settings.slip > 0 && account.limited >= self.settings.slip {
+            account.limited = 0;
+            RrlAction::Slip
+        } else {
+            RrlAction::Drop
+        }
+    }
+}
+
+/// The network of `client`, its address with the host bits cleared.
+pub fn client_network(client: IpAddr, ipv4_prefix: u8, ipv6_prefix: u8) -> IpAddr {
+    match client {
+        IpAddr::V4(addr) => {
+            let mask = u32::MAX.checked_shl(32 - u32::from(ipv4_prefix.min(32))).unwrap_or(0);
+            IpAddr::V4(Ipv4Addr::from(u32::from(addr) & mask))
+        }
+        IpAddr::V6(addr) => {
+            let mask = u128::MAX.checked_shl(128 - u32::from(ipv6_prefix.min(128))).unwrap_or(0);
+            IpAddr::V6(Ipv6Addr::from(u128::from(addr) & mask))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zone::parse_records;
+
+    const SOA: &str = "example.com. SOA ns.example.com. admin.example.com. 1 3600 600 86400 300\n";
+
+    fn response(qname: &str, rcode: Rcode, answers: &str, authorities: &str) -> Message {
+        Message {
+            header: Header::builder().qr(true).unwrap().rcode(rcode).unwrap().build(),
+            questions: vec![Question::builder().name(qname.to_owned()).unwrap().qtype(rtype::A).build()],
+            answers: parse_records(answers).unwrap(),
+            authorities: parse_records(authorities).unwrap(),
+            additionals: vec![],
+        }
+    }
+
+    fn answer(qname: &str) -> Message {
+        response(qname, Rcode::NoError, &format!("{}. A 10.0.0.1\n", qname), "")
+    }
+
+    fn limiter(rate: u32, slip: u32) -> ResponseRateLimiter {
+        ResponseRateLimiter::new(RrlSettings { rate, slip, ..RrlSettings::default() })
+    }
+
+    fn actions(limiter: &mut ResponseRateLimiter, client: &str, response: &Message, count: usize) -> Vec<RrlAction> {
+        (0..count).map(|_| limiter.check(client.parse().unwrap(), response)).collect()
+    }
+
+    #[test]
+    fn slips_every_nth_limited_response() {
+        use RrlAction::*;
+        let mut rrl = limiter(2, 2);
+        let www = answer("www.example.com");
+        assert_eq!(actions(&mut rrl, "192.0.2.1", &www, 7), [Send, Send, Drop, Slip, Drop, Slip, Drop]);
+
+        let mut rrl = limiter(1, 0);
+        assert_eq!(actions(&mut rrl, "192.0.2.1", &www, 4), [Send, Drop, Drop, Drop]);
+        let mut rrl = limiter(1, 1);
+        assert_eq!(actions(&mut rrl, "192.0.2.1", &www, 3), [Send, Slip, Slip]);
+    }
+
+    #[test]
+    fn counts_each_response_and_network_apart() {
+        use RrlAction::*;
+        let mut rrl = limiter(1, 0);
+        let www = answer("www.example.com");
+        assert_eq!(actions(&mut rrl, "192.0.2.1", &www, 2), [Send, Drop]);
+        // Same /24, so the same account.
+        assert_eq!(actions(&mut rrl, "192.0.2.200", &www, 1), [Drop]);
+        assert_eq!(actions(&mut rrl, "192.0.3.1", &www, 1), [Send]);
+        assert_eq!(actions(&mut rrl, "2001:db8:0:ff::1", &www, 2), [Send, Drop]);
+        assert_eq!(actions(&mut rrl, "2001:db8:0:100::1", &www, 1), [Send]);
+        assert_eq!(actions(&mut rrl, "192.0.2.1", &answer("WWW.example.com"), 1), [Drop]);
+        assert_eq!(actions(&mut rrl, "192.0.2.1", &answer("mail.example.com"), 1), [Send]);
+    }
+
+    #[test]
+    fn groups_negative_answers_by_zone() {
+        use RrlAction::*;
+        let mut rrl = limiter(1, 0);
+        let nxdomain = |qname| response(qname, Rcode::NxDomain, "", SOA);
+        assert_eq!(actions(&mut rrl, "192.0.2.1", &nxdomain("a.example.com"), 1), [Send]);
+        assert_eq!(actions(&mut rrl, "192.0.2.1", &nxdomain("b.example.com"), 1), [Drop]);
+        // NODATA is counted apart from NXDOMAIN in the same zone.
+        let nodata = response("c.example.com", Rcode::NoError, "", SOA);
+        assert_eq!(actions(&mut rrl, "192.0.2.1", &nodata, 2), [Send, Drop]);
+        let servfail = response("d.example.org", Rcode::ServFail, "", "");
+        assert_eq!(actions(&mut rrl, "192.0.2.1", &servfail, 2), [Send, Drop]);
+    }
+
+    #[test]
+    fn masks_client_networks() {
+        let network = |client: &str, v4, v6| client_network(client.parse().unwrap(), v4, v6).to_string();
+        assert_eq!(network("192.0.2.77", 24, 56), "192.0.2.0");
+        assert_eq!(network("192.0.2.77", 32, 56), "192.0.2.77");
+        assert_eq!(network("192.0.2.77", 0, 56), "0.0.0.0");
+        assert_eq!(network("192.0.2.77", 20, 56), "192.0.0.0");
+        assert_eq!(network("2001:db8:1:2ff::1", 24, 56), "2001:db8:1:200::");
+        assert_eq!(network("2001:db8::1", 24, 128), "2001:db8::1");
+        assert_eq!(network("2001:db8::1", 24, 0), "::");
+    }
+}
@@ -0,0 +1,223 @@
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Result, anyhow};
+
+use crate::config::SecondaryZone;
+use crate::message::*;
+use crate::tcp::{read_message, write_message};
+use crate::tsig::{TsigContext, TsigKey};
+use crate::zone::{serial_lt, soa_serial, Zone, ZoneStore};
+
+// Used until the primary's SOA tells us the zone's own timers.
+const INITIAL_RETRY: Duration = Duration::from_secs(10);
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The SOA REFRESH, RETRY and EXPIRE intervals (RFC 1035 section 3.3.13).
+#[derive(Debug, Clone, Copy)]
+struct Timers {
+    refresh: Duration,
+    retry: Duration,
+    expire: Duration,
+}
+
+impl Timers {
+    fn from_soa(soa: &Answer) -> Option<Timers> {
+        match soa.rdata() {
+            Ok(RData::Soa { refresh, retry, expire, .. }) => Some(Timers {
+                refresh: Duration::from_secs(refresh as u64),
+                retry: Duration::from_secs(retry as u64),
+                expire: Duration::from_secs(expire as u64),
+            }),
+            _ => None,
+        }
+    }
+}
+
+fn query(origin: &[u8], qtype: u16) -> Message {
+    Message {
+        header: Header::builder()
+            .id(rand::random())
+            .unwrap()
+            .qr(false)
+            .unwrap()
+            .qdcount(1)
+            .unwrap()
+            .build(),
+        questions: vec![
+            Question::builder()
+                .name_bytes(origin.to_vec())
+                .unwrap()
+                .qtype(qtype)
+                .build(),
+        ],
+        answers: vec![],
+        authorities: vec![],
+        additionals: vec![],
+    }
+}
+
+pub fn query_soa(primary: &str, origin: &[u8]) -> Result<Answer> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.set_read_timeout(Some(QUERY_TIMEOUT))?;
+    // Connected, so that only the primary's replies are received.
+    socket.connect(primary)?;
+    let request = query(origin, rtype::SOA);
+    let id = request.header.id;
+    socket.send(&request.encode())?;
+
+    let mut buf = [0; 512];
+    let size = socket.recv(&mut buf)?;
+    let response = Message::decode(&buf[..size])?;
+    if response.header.id != id {
+        return Err(anyhow!("Mismatched response ID from {}", primary));
+    }
+    if response.header.rcode != Rcode::NoError {
+        return Err(anyhow!("Primary {} answered SOA query with rcode {}", primary, response.header.rcode));
+    }
+    response.answers
+        .into_iter()
+        .find(|record| record.qtype == rtype::SOA && names_equal(&record.name, origin))
+        .ok_or_else(|| anyhow!("Primary {} returned no SOA for {}", primary, name_to_string(origin)))
+}
+
+/// Pulls a full copy of the zone from `primary` with AXFR, signing the
+/// exchange when a TSIG key is given. Records outside the zone are dropped.
+pub fn fetch_axfr(primary: &str, origin: &[u8], key: Option<TsigKey>) -> Result<Zone> {
+    let addr = primary
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| anyhow!("Cannot resolve primary {}", primary))?;
+    let mut stream = TcpStream::connect_timeout(&addr, QUERY_TIMEOUT)?;
+    stream.set_read_timeout(Some(QUERY_TIMEOUT))?;
+    let mut tsig = key.map(TsigContext::for_request);
+    let request = query(origin, rtype::AXFR).encode();
+    let request = match tsig.as_mut() {
+        Some(tsig) => tsig.sign(request),
+        None => request,
+    };
+    write_message(&mut stream, &request)?;
+
+    let mut records: Vec<Answer> = vec![];
+    let mut outside = 0;
+    let mut soa_seen = 0;
+    while soa_seen < 2 {
+        let bytes = read_message(&mut stream)?
+            .ok_or_else(|| anyhow!("Primary {} closed the transfer early", primary))?;
+        let response = Message::decode(&bytes)?;
+        if let Some(tsig) = tsig.as_mut() {
+            tsig.verify_response(&bytes)?;
+        }
+        if response.header.rcode != Rcode::NoError {
+            return Err(anyhow!("Primary {} refused transfer with rcode {}", primary, response.header.rcode));
+        }
+        for record in response.answers {
+            if records.is_empty() && record.qtype != rtype::SOA {
+                return Err(anyhow!("Zone transfer does not start with an SOA"));
+            }
+            // Otherwise a primary could replace any zone we serve.
+            if records.is_empty() && !names_equal(&record.name, origin) {
+                return Err(anyhow!(
+                    "Primary {} sent zone {} instead of {}",
+                    primary,
+                    name_to_string(&record.name),
+                    name_to_string(origin)
+                ));
+            }
+            if !is_subdomain(&record.name, origin) {
+                outside += 1;
+                continue;
+            }
+            if record.qtype == rtype::SOA && names_equal(&record.name, origin) {
+                soa_seen += 1;
+                if soa_seen == 2 {
+                    break;
+                }
+            }
+            records.push(record);
+        }
+    }
+
+    if outside > 0 {
+        eprintln!("Dropped {} records outside {} from {}", outside, name_to_string(origin), primary);
+    }
+    Zone::from_records(records)
+}
+
+/// Checks the primary's serial and transfers the zone if it is newer than
+/// ours, into every store in `stores`. Returns the primary's SOA.
+fn refresh(secondary: &SecondaryZone, stores: &[Arc<RwLock<ZoneStore>>]) -> Result<Answer> {
+    let primary_soa = query_soa(&secondary.primary, &secondary.origin)?;
+    let theirs = soa_serial(&primary_soa)
+        .ok_or_else(|| anyhow!("Malformed SOA from {}", secondary.primary))?;
+    // Every store is given the same transfers, so any one of them tells
+    // which serial we have.
+    let ours = stores[0]
+        .read()
+        .unwrap()
+        .find_zone(&secondary.origin)
+        .filter(|zone| names_equal(&zone.origin, &secondary.origin))
+        .and_then(|zone| zone.serial());
+
+    let outdated = match ours {
+        Some(ours) => serial_lt(ours, theirs),
+        None => true,
+    };
+    if outdated {
+        let zone = fetch_axfr(&secondary.primary, &secondary.origin, secondary.key.clone())?;
+        println!(
+            "Transferred zone {} serial {} from {}",
+            name_to_string(&secondary.origin),
+            theirs,
+            secondary.primary
+        );
+        for zones in stores {
+            zones.write().unwrap().update(zone.clone());
+        }
+    }
+
+    Ok(primary_soa)
+}
+
+/// Keeps a secondary zone in sync with its primary, following the SOA
+/// refresh, retry and expire timers, in the zone store of every view.
+/// Sending on the returned channel triggers an immediate refresh, as a
+/// NOTIFY from the primary does.
+pub fn spawn(secondary: SecondaryZone, stores: Vec<Arc<RwLock<ZoneStore>>>) -> Sender<()> {
+    let (trigger, triggered) = mpsc::channel();
+    thread::spawn(move || {
+        let mut timers: Option<Timers> = None;
+        let mut last_success: Option<Instant> = None;
+
+        loop {
+            let wait = match refresh(&secondary, &stores) {
+                Ok(soa) => {
+                    timers = Timers::from_soa(&soa);
+                    last_success = Some(Instant::now());
+                    timers.map_or(INITIAL_RETRY, |timers| timers.refresh)
+                }
+                Err(e) => {
+                    eprintln!("Refresh of {} failed: {}", name_to_string(&secondary.origin), e);
+                    if let (Some(last), Some(timers)) = (last_success, timers) {
+                        if last.elapsed() >= timers.expire {
+                            println!("Zone {} expired", name_to_string(&secondary.origin));
+                            for zones in &stores {
+                                zones.write().unwrap().remove(&secondary.origin);
+                            }
+                            last_success = None;
+                        }
+                    }
+                    timers.map_or(INITIAL_RETRY, |timers| timers.retry)
+                }
+            };
+            // A closed channel just means nobody can trigger us any more.
+            if let Err(mpsc::RecvTimeoutError::Disconnected) = triggered.recv_timeout(wait) {
+                thread::sleep(wait);
+            }
+        }
+    });
+    trigger
+}
@@ -0,0 +1,658 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs;
+use std::time::{Duration, Instant};
+
+use anyhow::{Result, anyhow};
+use base64::Engine;
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, Ed25519KeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+
+use crate::config::Config;
+use crate::message::*;
+use crate::response::{Chain, ChainEnd};
+use crate::validator::{now, nsec3_hash};
+use crate::zone::{Zone, ZoneStore};
+
+const ALGORITHM_ECDSAP256SHA256: u8 = 13;
+const ALGORITHM_ED25519: u8 = 15;
+
+const DNSKEY_ZONE_KEY: u16 = 0x0100;
+// Secure entry point, set on key signing keys.
+const DNSKEY_SEP: u16 = 0x0001;
+const DNSKEY_PROTOCOL: u8 = 3;
+const DNSKEY_TTL: u32 = 3600;
+const NSEC3_SHA1: u8 = 1;
+
+// Signatures are dated an hour back to allow for clock skew and expire a
+// week later. Cached signatures are replaced halfway through.
+const INCEPTION_SKEW: u32 = 3600;
+const SIGNATURE_LIFETIME: u32 = 7 * 24 * 3600;
+const SIGNATURE_REFRESH: Duration = Duration::from_secs(SIGNATURE_LIFETIME as u64 / 2);
+
+#[derive(Debug)]
+enum PrivateKey {
+    Ecdsa(EcdsaKeyPair),
+    Ed25519(Ed25519KeyPair),
+}
+
+/// A zone signing or key signing key, loaded from a PKCS#8 file.
+#[derive(Debug)]
+pub struct SigningKey {
+    private_key: PrivateKey,
+    // The DNSKEY RDATA published for the key.
+    dnskey: RData,
+    key_tag: u16,
+    ksk: bool,
+}
+
+impl SigningKey {
+    /// Loads an ECDSA P-256 or Ed25519 private key in PKCS#8 form, PEM or DER.
+    pub fn load(path: &str, ksk: bool) -> Result<SigningKey> {
+        let contents = fs::read(path)?;
+        let der = match std::str::from_utf8(&contents) {
+            Ok(text) if text.contains("-----BEGIN") => {
+                let body: String = text
+                    .lines()
+                    .filter(|line| !line.starts_with("-----"))
+                    .collect();
+                base64::engine::general_purpose::STANDARD.decode(body.trim())?
+            }
+            _ => contents,
+        };
+        SigningKey::from_pkcs8(&der, ksk).map_err(|e| anyhow!("{}: {}", path, e))
+    }
+
+    /// Builds a key from its PKCS#8 DER encoding.
+    pub fn from_pkcs8(der: &[u8], ksk: bool) -> Result<SigningKey> {
+        let (private_key, algorithm, public_key) = if let Ok(key) = Ed25519KeyPair::from_pkcs8_maybe_unchecked(der) {
+            let public_key = key.public_key().as_ref().to_vec();
+            (PrivateKey::Ed25519(key), ALGORITHM_ED25519, public_key)
+        } else {
+            let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, der, &SystemRandom::new())
+                .map_err(|_| anyhow!("not a PKCS#8 ECDSA P-256 or Ed25519 key"))?;
+            // DNSKEY carries the point without its uncompressed form prefix
+            // (RFC 6605 section 4).
+            let public_key = key.public_key().as_ref()[1..].to_vec();
+            (PrivateKey::Ecdsa(key), ALGORITHM_ECDSAP256SHA256, public_key)
+        };
+
+        let flags = if ksk { DNSKEY_ZONE_KEY | DNSKEY_SEP } else { DNSKEY_ZONE_KEY };
+        let dnskey = RData::Dnskey { flags, protocol: DNSKEY_PROTOCOL, algorithm, public_key };
+        let key_tag = dnskey.key_tag().unwrap();
+        Ok(SigningKey { private_key, dnskey, key_tag, ksk })
+    }
+
+    fn algorithm(&self) -> u8 {
+        match self.private_key {
+            PrivateKey::Ecdsa(_) => ALGORITHM_ECDSAP256SHA256,
+            PrivateKey::Ed25519(_) => ALGORITHM_ED25519,
+        }
+    }
+
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match &self.private_key {
+            PrivateKey::Ecdsa(key) => key
+                .sign(&SystemRandom::new(), data)
+                .map(|signature| signature.as_ref().to_vec())
+                .map_err(|_| anyhow!("ECDSA signing failed")),
+            PrivateKey::Ed25519(key) => Ok(key.sign(data).as_ref().to_vec()),
+        }
+    }
+}
+
+/// What a set of NSEC or NSEC3 records has to prove.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Denial {
+    NoData,
+    NxDomain,
+    // A wildcard answered for the name, so no closer match exists.
+    Wildcard,
+}
+
+/// A name in the zone's NSEC or NSEC3 chain.
+#[derive(Debug, Clone)]
+struct Link {
+    // The lowercased owner name for NSEC, the raw hash for NSEC3.
+    key: Vec<u8>,
+    owner: Vec<u8>,
+    types: Vec<u16>,
+}
+
+/// The zone's denial chain in order, built for one zone serial.
+#[derive(Debug)]
+struct DenialChain {
+    serial: Option<u32>,
+    nsec3: bool,
+    links: Vec<Link>,
+}
+
+impl DenialChain {
+    fn build(zone: &Zone, nsec3: bool) -> DenialChain {
+        let mut names: Vec<Vec<u8>> = zone.records().map(|record| lowercase_name(&record.name)).collect();
+        if nsec3 {
+            // Empty non-terminals get NSEC3 records too (RFC 5155 section 7.1).
+            for name in names.clone() {
+                let mut cur = name;
+                while let Some(parent) = parent_name(&cur).filter(|parent| is_subdomain(parent, &zone.origin)) {
+                    names.push(parent.clone());
+                    cur = parent;
+                }
+            }
+        }
+        names.sort_by(|a, b| canonical_cmp(a, b));
+        names.dedup();
+
+        let mut links = vec![];
+        for name in names {
+            // Names below a zone cut are glue, not authoritative data.
+            let cut = zone.zone_cut(&name);
+            if cut.as_ref().is_some_and(|cut| !names_equal(cut, &name)) {
+                continue;
+            }
+            let mut types: Vec<u16> = zone.records_at(&name)
+                .iter()
+                .map(|record| record.qtype)
+                .filter(|qtype| cut.is_none() || matches!(*qtype, rtype::NS | rtype::DS))
+                .collect();
+            if names_equal(&name, &zone.origin) {
+                types.push(rtype::DNSKEY);
+                if nsec3 {
+                    types.push(rtype::NSEC3PARAM);
+                }
+            }
+            // A delegation without DS has nothing signed at its owner.
+            if !types.is_empty() && (cut.is_none() || types.contains(&rtype::DS)) {
+                types.push(rtype::RRSIG);
+            }
+            if nsec3 {
+                let hash = nsec3_hash(&name, &[], 0);
+                let mut owner = base32hex_encode(&hash).to_ascii_lowercase().into_bytes();
+                owner.insert(0, owner.len() as u8);
+                owner.extend(&zone.origin);
+                links.push(Link { key: hash, owner, types });
+            } else {
+                types.push(rtype::NSEC);
+                links.push(Link { key: name.clone(), owner: name, types });
+            }
+        }
+        if nsec3 {
+            links.sort_by(|a, b| a.key.cmp(&b.key));
+        }
+
+        DenialChain { serial: zone.serial(), nsec3, links }
+    }
+
+    fn key(&self, name: &[u8]) -> Vec<u8> {
+        if self.nsec3 {
+            nsec3_hash(name, &[], 0)
+        } else {
+            lowercase_name(name)
+        }
+    }
+
+    fn matching(&self, name: &[u8]) -> Option<usize> {
+        let key = self.key(name);
+        self.links.iter().position(|link| link.key == key)
+    }
+
+    /// The link that covers a name absent from the chain: the last one
+    /// before it, wrapping around to the end of the chain.
+    fn covering(&self, name: &[u8]) -> usize {
+        let key = self.key(name);
+        let precedes = |link: &Link| match self.nsec3 {
+            true => link.key < key,
+            false => canonical_cmp(&link.key, &key) == Ordering::Less,
+        };
+        self.links
+            .iter()
+            .rposition(precedes)
+            .unwrap_or(self.links.len() - 1)
+    }
+
+    fn record(&self, index: usize, qclass: u16, ttl: u32) -> Answer {
+        let link = &self.links[index];
+        let next = &self.links[(index + 1) % self.links.len()];
+        let (qtype, rdata) = if self.nsec3 {
+            let rdata = RData::Nsec3 {
+                hash_algorithm: NSEC3_SHA1,
+                flags: 0,
+                iterations: 0,
+                salt: vec![],
+                next_hashed: next.key.clone(),
+                types: link.types.clone(),
+            };
+            (rtype::NSEC3, rdata)
+        } else {
+            (rtype::NSEC, RData::Nsec { next: next.owner.clone(), types: link.types.clone() })
+        };
+        Answer::builder()
+            .name_bytes(link.owner.clone())
+            .unwrap()
+            .qtype(qtype)
+            .unwrap()
+            .qclass(qclass)
+            .unwrap()
+            .ttl(ttl)
+            .unwrap()
+            .rdata(&rdata)
+            .unwrap()
+            .build()
+    }
+}
+
+#[derive(Debug)]
+struct CachedSignatures {
+    // The canonical form of the RRset the signatures cover.
+    rrset: Vec<u8>,
+    rrsigs: Vec<RData>,
+    refresh: Instant,
+}
+
+/// Online signing state for one zone.
+#[derive(Debug)]
+pub struct ZoneSigner {
+    origin: Vec<u8>,
+    keys: Vec<SigningKey>,
+    nsec3: bool,
+    // Keyed by the lowercased owner the RRset is signed under, and its type.
+    signatures: HashMap<(Vec<u8>, u16), CachedSignatures>,
+    chain: Option<DenialChain>,
+}
+
+impl ZoneSigner {
+    fn dnskeys(&self, zone: &Zone) -> Vec<Answer> {
+        self.keys
+            .iter()
+            .map(|key| {
+                Answer::builder()
+                    .name_bytes(zone.origin.clone())
+                    .unwrap()
+                    .qtype(rtype::DNSKEY)
+                    .unwrap()
+                    .qclass(zone_class(zone))
+                    .unwrap()
+                    .ttl(DNSKEY_TTL)
+                    .unwrap()
+                    .rdata(&key.dnskey)
+                    .unwrap()
+                    .build()
+            })
+            .collect()
+    }
+
+    fn nsec3param(&self, zone: &Zone) -> Answer {
+        Answer::builder()
+            .name_bytes(zone.origin.clone())
+            .unwrap()
+            .qtype(rtype::NSEC3PARAM)
+            .unwrap()
+            .qclass(zone_class(zone))
+            .unwrap()
+            // RFC 5155 section 4 asks for an NSEC3PARAM TTL of zero.
+            .ttl(0)
+            .unwrap()
+            .rdata(&RData::Nsec3Param { hash_algorithm: NSEC3_SHA1, flags: 0, iterations: 0, salt: vec![] })
+            .unwrap()
+            .build()
+    }
+
+    /// The NSEC or NSEC3 records proving `denial` for `name`.
+    fn deny(&mut self, zone: &Zone, name: &[u8], denial: Denial) -> Vec<Answer> {
+        let stale = match &self.chain {
+            Some(chain) => chain.serial != zone.serial(),
+            None => true,
+        };
+        if stale {
+            self.chain = Some(DenialChain::build(zone, self.nsec3));
+        }
+        let chain = self.chain.as_ref().unwrap();
+        if chain.links.is_empty() {
+            return vec![];
+        }
+
+        let closest_encloser = zone.closest_encloser(name);
+        let next_closer = name_suffix(name, label_count(&closest_encloser) + 1);
+        let wildcard = wildcard_name(&closest_encloser);
+        let mut indexes = match (denial, self.nsec3) {
+            // An empty non-terminal has no NSEC of its own; the one before
+            // it leads to its descendants.
+            (Denial::NoData, _) if zone.name_exists(name) => {
+                vec![chain.matching(name).unwrap_or_else(|| chain.covering(name))]
+            }
+            (Denial::NoData, false) => chain.matching(&wildcard).into_iter().chain([chain.covering(name)]).collect(),
+            (Denial::NoData, true) => chain.matching(&closest_encloser)
+                .into_iter()
+                .chain(chain.matching(&wildcard))
+                .chain([chain.covering(&next_closer)])
+                .collect(),
+            (Denial::NxDomain, false) => vec![chain.covering(name), chain.covering(&wildcard)],
+            (Denial::NxDomain, true) => chain.matching(&closest_encloser)
+                .into_iter()
+                .chain([chain.covering(&next_closer), chain.covering(&wildcard)])
+                .collect(),
+            (Denial::Wildcard, false) => vec![chain.covering(name)],
+            (Denial::Wildcard, true) => vec![chain.covering(&next_closer)],
+        };
+        indexes.sort_unstable();
+        indexes.dedup();
+
+        let ttl = negative_ttl(zone);
+        indexes
+            .into_iter()
+            .map(|index| chain.record(index, zone_class(zone), ttl))
+            .collect()
+    }
+
+    /// RRSIGs over `rrset`, signed under `owner`, reusing cached signatures
+    /// while the RRset is unchanged.
+    fn sign_rrset(&mut self, rrset: &[Answer], owner: &[u8]) -> Vec<Answer> {
+        let qtype = rrset[0].qtype;
+        let ttl = rrset[0].ttl;
+        let mut canonical = vec![];
+        for record in canonical_rrset(rrset) {
+            let mut record = record.clone();
+            record.name = owner.to_vec();
+            canonical.extend(record.canonical_form(ttl));
+        }
+
+        let cache_key = (lowercase_name(owner), qtype);
+        let cached = self.signatures
+            .get(&cache_key)
+            .filter(|cached| cached.rrset == canonical && cached.refresh > Instant::now());
+        let rrsigs = match cached {
+            Some(cached) => cached.rrsigs.clone(),
+            None => {
+                let rrsigs = self.create_signatures(qtype, ttl, owner, &canonical);
+                let refresh = Instant::now() + SIGNATURE_REFRESH;
+                self.signatures.insert(cache_key, CachedSignatures { rrset: canonical, rrsigs: rrsigs.clone(), refresh });
+                rrsigs
+            }
+        };
+
+        rrsigs
+            .iter()
+            .map(|rrsig| {
+                Answer::builder()
+                    .name_bytes(rrset[0].name.clone())
+                    .unwrap()
+                    .qtype(rtype::RRSIG)
+                    .unwrap()
+                    .qclass(rrset[0].qclass)
+                    .unwrap()
+                    .ttl(ttl)
+                    .unwrap()
+                    .rdata(rrsig)
+                    .unwrap()
+                    .build()
+            })
+            .collect()
+    }
+
+    fn create_signatures(&self, qtype: u16, ttl: u32, owner: &[u8], canonical: &[u8]) -> Vec<RData> {
+        // The KSKs sign the DNSKEY RRset and the ZSKs everything else. A
+        // zone with only one kind of key signs everything with it.
+        let wants_ksk = qtype == rtype::DNSKEY;
+        let has_kind = self.keys.iter().any(|key| key.ksk == wants_ksk);
+        let mut labels = label_count(owner);
+        if is_wildcard(owner) {
+            labels -= 1;
+        }
+        let now = now();
+
+        let mut rrsigs = vec![];
+        for key in self.keys.iter().filter(|key| !has_kind || key.ksk == wants_ksk) {
+            let mut rrsig = RData::Rrsig {
+                type_covered: qtype,
+                algorithm: key.algorithm(),
+                labels: labels as u8,
+                original_ttl: ttl,
+                expiration: now.wrapping_add(SIGNATURE_LIFETIME),
+                inception: now.wrapping_sub(INCEPTION_SKEW),
+                key_tag: key.key_tag,
+                signer: lowercase_name(&self.origin),
+                signature: vec![],
+            };
+            let mut data = rrsig.encode();
+            data.extend(canonical);
+            match key.sign(&data) {
+                Ok(signed) => {
+                    if let RData::Rrsig { signature, .. } = &mut rrsig {
+                        *signature = signed;
+                    }
+                    rrsigs.push(rrsig);
+                }
+                Err(e) => eprintln!("Failed to sign {} type {}: {}", name_to_string(owner), qtype, e),
+            }
+        }
+        rrsigs
+    }
+}
+
+fn zone_class(zone: &Zone) -> u16 {
+    zone.soa().map_or(1, |soa| soa.qclass)
+}
+
+/// The TTL of negative answers and their denial records (RFC 9077).
+fn negative_ttl(zone: &Zone) -> u32 {
+    match zone.soa().map(|soa| (soa.ttl, soa.rdata())) {
+        Some((ttl, Ok(RData::Soa { minimum, .. }))) => ttl.min(minimum),
+        _ => 0,
+    }
+}
+
+/// The owner an RRset from `zone` is signed under: its own name, or the
+/// wildcard it was synthesized from. `None` for data that is not signed:
+/// delegation NS RRsets, glue and CNAMEs synthesized from a DNAME.
+fn signed_owner(zone: &Zone, rrset: &[Answer]) -> Option<Vec<u8>> {
+    let owner = &rrset[0].name;
+    let qtype = rrset[0].qtype;
+    match qtype {
+        rtype::RRSIG | rtype::OPT => return None,
+        rtype::NSEC3 => return Some(owner.clone()),
+        _ => {}
+    }
+    if let Some(cut) = zone.zone_cut(owner) {
+        if !names_equal(&cut, owner) || !matches!(qtype, rtype::DS | rtype::NSEC) {
+            return None;
+        }
+    }
+    if zone.name_exists(owner) {
+        return Some(owner.clone());
+    }
+    let wildcard = wildcard_name(&zone.closest_encloser(owner));
+    zone.records_at(&wildcard)
+        .iter()
+        .any(|record| record.qtype == qtype)
+        .then_some(wildcard)
+}
+
+/// Signs answers from local zones on the fly for clients that set DO.
+#[derive(Debug, Default)]
+pub struct Signer {
+    zones: Vec<ZoneSigner>,
+}
+
+impl Signer {
+    pub fn load(config: &Config) -> Result<Signer> {
+        let mut signer = Signer::default();
+        for file in &config.signing_keys {
+            let key = SigningKey::load(&file.path, file.ksk)
+                .map_err(|e| anyhow!("Failed to load signing key {}: {}", file.path, e))?;
+            println!(
+                "Signing {} with {} {} (algorithm {})",
+                name_to_string(&file.origin),
+                if key.ksk { "KSK" } else { "ZSK" },
+                key.key_tag,
+                key.algorithm(),
+            );
+            match signer.zone_signer(&file.origin) {
+                Some(zone) => zone.keys.push(key),
+                None => signer.zones.push(ZoneSigner {
+                    origin: file.origin.clone(),
+                    keys: vec![key],
+                    nsec3: config.nsec3_zones.iter().any(|zone| names_equal(zone, &file.origin)),
+                    signatures: HashMap::new(),
+                    chain: None,
+                }),
+            }
+        }
+        Ok(signer)
+    }
+
+    fn zone_signer(&mut self, origin: &[u8]) -> Option<&mut ZoneSigner> {
+        self.zones.iter_mut().find(|zone| names_equal(&zone.origin, origin))
+    }
+
+    /// Adds DNSKEY and NSEC3PARAM answers at signed apexes, denial of
+    /// existence for negative answers, referrals and wildcard answers, and
+    /// RRSIGs over every authoritative RRset in the answer and authority
+    /// sections of `chain`.
+    pub fn sign_chain(&mut self, chain: &mut Chain, qname: &[u8], qtype: u16, zones: &ZoneStore) {
+        let final_name = match chain.answers.last() {
+            Some(last) if last.qtype == rtype::CNAME => last.rdata()
+                .ok()
+                .and_then(|rdata| rdata.target().map(|target| target.to_vec()))
+                .unwrap_or_else(|| qname.to_vec()),
+            _ => qname.to_vec(),
+        };
+
+        if let Some(zone) = zones.find_zone(&final_name) {
+            if let Some(signer) = self.zone_signer(&zone.origin) {
+                let at_apex = names_equal(&final_name, &zone.origin);
+                match &chain.end {
+                    ChainEnd::NoData if at_apex && qtype == rtype::DNSKEY => {
+                        chain.answers.extend(signer.dnskeys(zone));
+                        chain.authorities.clear();
+                        chain.end = ChainEnd::Answered;
+                    }
+                    ChainEnd::NoData if at_apex && qtype == rtype::NSEC3PARAM && signer.nsec3 => {
+                        chain.answers.push(signer.nsec3param(zone));
+                        chain.authorities.clear();
+                        chain.end = ChainEnd::Answered;
+                    }
+                    ChainEnd::NoData => chain.authorities.extend(signer.deny(zone, &final_name, Denial::NoData)),
+                    ChainEnd::NxDomain => chain.authorities.extend(signer.deny(zone, &final_name, Denial::NxDomain)),
+                    ChainEnd::Referral(name) => {
+                        // A signed referral carries the DS RRset, or proof
+                        // that there is none (RFC 4035 section 3.1.4).
+                        let cut = zone.zone_cut(name).unwrap_or_else(|| name.clone());
+                        let ds: Vec<Answer> = zone.records_at(&cut)
+                            .iter()
+                            .filter(|record| record.qtype == rtype::DS)
+                            .cloned()
+                            .collect();
+                        if ds.is_empty() {
+                            chain.authorities.extend(signer.deny(zone, &cut, Denial::NoData));
+                        } else {
+                            chain.authorities.extend(ds);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        // Answers synthesized from a wildcard need proof that the name
+        // itself does not exist (RFC 4035 section 3.1.3.3).
+        for rrset in rrsets(&chain.answers) {
+            let Some(zone) = zones.find_zone(&rrset[0].name) else {
+                continue;
+            };
+            let Some(signer) = self.zone_signer(&zone.origin) else {
+                continue;
+            };
+            if signed_owner(zone, &rrset).is_some_and(|owner| is_wildcard(&owner)) {
+                let proof = signer.deny(zone, &rrset[0].name, Denial::Wildcard);
+                chain.authorities.extend(proof);
+            }
+        }
+
+        chain.answers = self.sign_section(&chain.answers, zones);
+        chain.authorities = self.sign_section(&chain.authorities, zones);
+    }
+
+    fn sign_section(&mut self, records: &[Answer], zones: &ZoneStore) -> Vec<Answer> {
+        let mut signed = vec![];
+        for rrset in rrsets(records) {
+            let signatures = zones.find_zone(&rrset[0].name).and_then(|zone| {
+                let owner = signed_owner(zone, &rrset)?;
+                let signer = self.zone_signer(&zone.origin)?;
+                Some(signer.sign_rrset(&rrset, &owner))
+            });
+            signed.extend(rrset);
+            signed.extend(signatures.unwrap_or_default());
+        }
+        signed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validator::verify_rrset;
+    use crate::zone::parse_records;
+
+    fn zone_signer(key: SigningKey) -> ZoneSigner {
+        ZoneSigner {
+            origin: encode_name("example.com"),
+            keys: vec![key],
+            nsec3: false,
+            signatures: HashMap::new(),
+            chain: None,
+        }
+    }
+
+    fn dnskey(signer: &ZoneSigner) -> Vec<Answer> {
+        signer.dnskeys(&Zone::new(signer.origin.clone()))
+    }
+
+    // Out of canonical order and in mixed case, so verification only
+    // passes if both sides sort and lowercase the same way.
+    fn rrset() -> Vec<Answer> {
+        parse_records(
+            "WWW.Example.com. 300 IN A 192.0.2.20\n\
+             www.example.COM. 300 IN A 192.0.2.3\n\
+             www.example.com. 300 IN A 192.0.2.100\n",
+        )
+        .unwrap()
+    }
+
+    fn round_trip(key: SigningKey) {
+        let mut signer = zone_signer(key);
+        let keys = dnskey(&signer);
+        let mut rrset = rrset();
+        let sigs = signer.sign_rrset(&rrset, &rrset[0].name.clone());
+        assert_eq!(sigs.len(), 1);
+
+        rrset.reverse();
+        assert_eq!(verify_rrset(&rrset, &sigs, &keys), Ok(3));
+        rrset[0].data[3] ^= 1;
+        assert!(verify_rrset(&rrset, &sigs, &keys).is_err());
+    }
+
+    #[test]
+    fn ecdsa_signatures_validate() {
+        let der = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &SystemRandom::new()).unwrap();
+        round_trip(SigningKey::from_pkcs8(der.as_ref(), false).unwrap());
+    }
+
+    #[test]
+    fn ed25519_signatures_validate() {
+        let der = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        round_trip(SigningKey::from_pkcs8(der.as_ref(), false).unwrap());
+    }
+
+    #[test]
+    fn wildcard_signatures_validate_expanded() {
+        let der = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let mut signer = zone_signer(SigningKey::from_pkcs8(der.as_ref(), false).unwrap());
+        let keys = dnskey(&signer);
+        let mut rrset = rrset();
+        let sigs = signer.sign_rrset(&rrset, &encode_name("*.example.com"));
+        for record in &mut rrset {
+            record.name = encode_name("www.example.com");
+        }
+        assert_eq!(verify_rrset(&rrset, &sigs, &keys), Ok(2));
+    }
+}
@@ -0,0 +1,255 @@
+use std::io::{ErrorKind, Read, Write};
+use std::net::{IpAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::acl::Operation;
+use crate::config::Config;
+use crate::message::*;
+use crate::ratelimit::{LimitAction, QueryRateLimiter};
+use crate::response::{answer_request, error_response, format_error, recursion_available};
+use crate::transfer::{build_axfr, build_ixfr, ixfr_serial};
+use crate::tsig::{verify_request, TsigContext};
+use crate::signer::Signer;
+use crate::validator::Validator;
+use crate::view::{self, View};
+use crate::zone::ZoneStore;
+
+// How long a client may take to send its next message or to accept ours
+// before the connection is closed.
+const TCP_TIMEOUT: Duration = Duration::from_secs(10);
+// Connections beyond this are closed as soon as they are accepted, since
+// each one holds a thread.
+const MAX_TCP_CONNECTIONS: usize = 128;
+
+/// Reads one length-prefixed message (RFC 1035 section 4.2.2). Returns
+/// `None` when the peer closes the connection between messages.
+pub fn read_message(stream: &mut TcpStream) -> Result<Option<Vec<u8>>> {
+    let mut length = [0; 2];
+    match stream.read_exact(&mut length) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let mut bytes = vec![0; u16::from_be_bytes(length) as usize];
+    stream.read_exact(&mut bytes)?;
+    Ok(Some(bytes))
+}
+
+pub fn write_message(stream: &mut TcpStream, bytes: &[u8]) -> Result<()> {
+    stream.write_all(&(bytes.len() as u16).to_be_bytes())?;
+    stream.write_all(bytes)?;
+    Ok(())
+}
+
+/// Encodes `message`, signing it when the request was signed.
+fn encode_signed(message: Message, tsig: &mut Option<TsigContext>) -> Vec<u8> {
+    match tsig {
+        Some(tsig) => tsig.sign(message.encode()),
+        None => message.encode(),
+    }
+}
+
+/// The messages of an AXFR or IXFR stream for `request`, or the RCODE to
+/// refuse it with.
+fn transfer_messages(request: &Message, peer: IpAddr, zones: &ZoneStore) -> Result<Vec<Message>, Rcode> {
+    let question = &request.questions[0];
+    let zone = match zones.find_zone(&question.name) {
+        Some(zone) if names_equal(&zone.origin, &question.name) => zone,
+        _ => return Err(Rcode::NotAuth),
+    };
+
+    if question.qtype == rtype::IXFR {
+        let serial = ixfr_serial(request).ok_or(Rcode::FormErr)?;
+        println!("Incremental transfer of {} from serial {} to {}", name_to_string(&zone.origin), serial, peer);
+        Ok(build_ixfr(request, zone, serial))
+    } else {
+        println!("Transferring zone {} to {}", name_to_string(&zone.origin), peer);
+        Ok(build_axfr(request, zone))
+    }
+}
+
+fn handle_transfer(
+    stream: &mut TcpStream,
+    request: &Message,
+    peer: IpAddr,
+    tsig: &mut Option<TsigContext>,
+    zones: &RwLock<ZoneStore>,
+    config: &Config,
+) -> Result<()> {
+    let question = &request.questions[0];
+    let key = tsig.as_ref().map(|tsig| tsig.key_name().to_vec());
+    let recursion = recursion_available(request, peer, key.as_deref(), config);
+    if !config.access.allows(Operation::Transfer, &question.name, peer, key.as_deref()) {
+        println!("Refusing zone transfer of {} to {}", name_to_string(&question.name), peer);
+        return write_message(stream, &encode_signed(error_response(request, Rcode::Refused, recursion), tsig));
+    }
+
+    // The stream is built before anything is written, so that a slow client
+    // does not hold the zones lock against updates, reloads and refreshes.
+    let messages = transfer_messages(request, peer, &zones.read().unwrap());
+    let messages = match messages {
+        Ok(messages) => messages,
+        Err(rcode) => return write_message(stream, &encode_signed(error_response(request, rcode, recursion), tsig)),
+    };
+    // Every message of the stream is signed, each MAC chaining to the last.
+    for message in messages {
+        write_message(stream, &encode_signed(message, tsig))?;
+    }
+    Ok(())
+}
+
+fn is_timeout(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<std::io::Error>()
+        .is_some_and(|e| matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut))
+}
+
+fn handle_connection(
+    mut stream: TcpStream,
+    view: &View,
+    validator: Option<&Validator>,
+    signer: Option<&Mutex<Signer>>,
+    limiter: Option<&Mutex<QueryRateLimiter>>,
+    config: &Config,
+) -> Result<()> {
+    let peer_addr = stream.peer_addr()?;
+    let peer = peer_addr.ip();
+
+    loop {
+        let bytes = match read_message(&mut stream) {
+            Ok(Some(bytes)) => bytes,
+            Ok(None) => break,
+            Err(e) if is_timeout(&e) => {
+                println!("Closing idle TCP connection from {}", peer);
+                break;
+            }
+            Err(e) => return Err(e),
+        };
+        println!("Received {} bytes over TCP from {}", bytes.len(), peer);
+        let request = match Message::decode(&bytes) {
+            Ok(request) => request,
+            Err(e) => {
+                eprintln!("Malformed request from {}: {}", peer, e);
+                if let Some(response) = format_error(&bytes, peer, &view.config) {
+                    write_message(&mut stream, &response.encode())?;
+                }
+                continue;
+            }
+        };
+
+        let mut tsig = match verify_request(&bytes, &config.tsig_keys) {
+            Ok(tsig) => tsig,
+            Err(e) => {
+                eprintln!("Malformed TSIG from {}: {}", peer, e);
+                let recursion = recursion_available(&request, peer, None, &view.config);
+                write_message(&mut stream, &error_response(&request, Rcode::FormErr, recursion).encode())?;
+                continue;
+            }
+        };
+        if let Some(context) = tsig.as_mut().filter(|context| context.error != 0) {
+            println!("TSIG verification failed for {} with error {}", peer, context.error);
+            let recursion = recursion_available(&request, peer, None, &view.config);
+            write_message(&mut stream, &context.sign(error_response(&request, Rcode::NotAuth, recursion).encode()))?;
+            continue;
+        }
+        let key = tsig.as_ref().map(|tsig| tsig.key_name().to_vec());
+        let recursion = recursion_available(&request, peer, key.as_deref(), &view.config);
+        if limiter.is_some_and(|limiter| !limiter.lock().unwrap().allow(peer, key.as_deref())) {
+            if config.query_limit.action == LimitAction::Refuse {
+                println!("Refusing rate limited query from {}", peer);
+                write_message(&mut stream, &encode_signed(error_response(&request, Rcode::Refused, recursion), &mut tsig))?;
+            } else {
+                println!("Dropping rate limited query from {}", peer);
+            }
+            continue;
+        }
+
+        let is_transfer = request.questions
+            .first()
+            .is_some_and(|question| question.qtype == rtype::AXFR || question.qtype == rtype::IXFR);
+        if is_transfer {
+            handle_transfer(&mut stream, &request, peer, &mut tsig, &view.zones, &view.config)?;
+            continue;
+        }
+
+        let response = match answer_request(request, peer_addr, key.as_deref(), view, validator, signer) {
+            Some(response) => response,
+            None => {
+                println!("Dropping request from {}", peer);
+                continue;
+            }
+        };
+        write_message(&mut stream, &encode_signed(response, &mut tsig))?;
+    }
+
+    Ok(())
+}
+
+/// Frees a connection's place under the limit when its thread ends.
+struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+pub fn serve(
+    listener: TcpListener,
+    views: Vec<Arc<View>>,
+    validator: Option<Arc<Validator>>,
+    signer: Option<Arc<Mutex<Signer>>>,
+    limiter: Option<Arc<Mutex<QueryRateLimiter>>>,
+    config: Arc<Config>,
+) {
+    let connections = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Error accepting TCP connection: {}", e);
+                continue;
+            }
+        };
+        let peer = match stream.peer_addr() {
+            Ok(peer) => peer,
+            Err(e) => {
+                eprintln!("Error accepting TCP connection: {}", e);
+                continue;
+            }
+        };
+        if connections.load(Ordering::SeqCst) >= MAX_TCP_CONNECTIONS {
+            eprintln!("Too many TCP connections, closing the one from {}", peer);
+            continue;
+        }
+        let timeouts = stream
+            .set_read_timeout(Some(TCP_TIMEOUT))
+            .and_then(|()| stream.set_write_timeout(Some(TCP_TIMEOUT)));
+        if let Err(e) = timeouts {
+            eprintln!("Error accepting TCP connection: {}", e);
+            continue;
+        }
+        // Only this thread adds connections, so the count cannot pass the
+        // limit between the check and here.
+        connections.fetch_add(1, Ordering::SeqCst);
+        let slot = ConnectionSlot(connections.clone());
+        let view = view::select(&views, peer.ip()).clone();
+        let validator = validator.clone();
+        let signer = signer.clone();
+        let limiter = limiter.clone();
+        let config = config.clone();
+        thread::spawn(move || {
+            let _slot = slot;
+            let result =
+                handle_connection(stream, &view, validator.as_deref(), signer.as_deref(), limiter.as_deref(), &config);
+            if let Err(e) = result {
+                eprintln!("Error on TCP connection: {}", e);
+            }
+        });
+    }
+}
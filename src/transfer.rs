@@ -0,0 +1,191 @@
+use crate::message::*;
+use crate::zone::{serial_lt, soa_serial, Zone};
+
+// Records are packed into messages until their uncompressed size would
+// pass this, keeping each message well under the 64KiB TCP limit.
+const MAX_TRANSFER_MESSAGE: usize = 16384;
+
+fn transfer_message(request: &Message, first: bool, answers: Vec<Answer>) -> Message {
+    let questions = if first {
+        request.questions.clone()
+    } else {
+        vec![]
+    };
+    Message {
+        header: Header::builder()
+            .id(request.header.id)
+            .unwrap()
+            .aa(true)
+            .unwrap()
+            .qdcount(questions.len() as u16)
+            .unwrap()
+            .ancount(answers.len() as u16)
+            .unwrap()
+            .build(),
+        questions,
+        answers,
+        authorities: vec![],
+        additionals: vec![],
+    }
+}
+
+/// Splits `records` into response messages for a zone transfer.
+pub fn pack_records(request: &Message, records: Vec<Answer>) -> Vec<Message> {
+    let mut messages = vec![];
+    let mut batch = vec![];
+    let mut size = 0;
+
+    for record in records {
+        let record_size = record.name.len() + 11 + record.data.len();
+        if !batch.is_empty() && size + record_size > MAX_TRANSFER_MESSAGE {
+            messages.push(transfer_message(request, messages.is_empty(), std::mem::take(&mut batch)));
+            size = 0;
+        }
+        size += record_size;
+        batch.push(record);
+    }
+    if !batch.is_empty() || messages.is_empty() {
+        messages.push(transfer_message(request, messages.is_empty(), batch));
+    }
+
+    messages
+}
+
+/// Builds the AXFR response stream for `zone` (RFC 5936 section 2.2): every
+/// record in the zone, opened and closed by the SOA.
+pub fn build_axfr(request: &Message, zone: &Zone) -> Vec<Message> {
+    let soa = match zone.soa() {
+        Some(soa) => soa.clone(),
+        None => return vec![],
+    };
+
+    let mut records = vec![soa.clone()];
+    records.extend(zone.records().filter(|record| record.qtype != rtype::SOA).cloned());
+    records.push(soa);
+
+    pack_records(request, records)
+}
+
+/// The serial of the zone version an IXFR client holds, carried in the SOA
+/// of the request's authority section.
+pub fn ixfr_serial(request: &Message) -> Option<u32> {
+    request.authorities
+        .iter()
+        .find(|record| record.qtype == rtype::SOA)
+        .and_then(soa_serial)
+}
+
+/// Builds the IXFR response stream (RFC 1995 section 4) for a client holding
+/// version `serial` of `zone`. Falls back to a full transfer when the
+/// journal does not reach back to that version.
+pub fn build_ixfr(request: &Message, zone: &Zone, serial: u32) -> Vec<Message> {
+    let soa = match zone.soa() {
+        Some(soa) => soa.clone(),
+        None => return vec![],
+    };
+
+    if !serial_lt(serial, soa_serial(&soa).unwrap_or(0)) {
+        return pack_records(request, vec![soa]);
+    }
+
+    let changes = match zone.changes_since(serial) {
+        Some(changes) => changes,
+        None => {
+            println!("Journal does not reach serial {}, sending full zone", serial);
+            return build_axfr(request, zone);
+        }
+    };
+
+    let mut records = vec![soa.clone()];
+    for change in changes {
+        records.push(change.old_soa.clone());
+        records.extend(change.removed.iter().cloned());
+        records.push(change.new_soa.clone());
+        records.extend(change.added.iter().cloned());
+    }
+    records.push(soa);
+
+    pack_records(request, records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(serial: u32, data: &str) -> Zone {
+        Zone::parse(&format!("$ORIGIN example.com.\n@ SOA ns admin {} 3600 600 86400 300\n{}", serial, data)).unwrap()
+    }
+
+    fn request() -> Message {
+        Message {
+            header: Header::builder().id(7).unwrap().qdcount(1).unwrap().build(),
+            questions: vec![Question {
+                name: encode_name("example.com"),
+                qtype: rtype::IXFR,
+                qclass: rtype::CLASS_IN,
+            }],
+            answers: vec![],
+            authorities: vec![],
+            additionals: vec![],
+        }
+    }
+
+    // Each record as its type, with SOAs standing for their serial.
+    fn stream(messages: &[Message]) -> Vec<String> {
+        messages
+            .iter()
+            .flat_map(|message| &message.answers)
+            .map(|record| match soa_serial(record) {
+                Some(serial) => format!("SOA {}", serial),
+                None => format!("{} {}", name_to_string(&record.name), record.qtype),
+            })
+            .collect()
+    }
+
+    fn journaled_zone() -> Zone {
+        let mut zone = version(1, "www A 10.0.0.1\n");
+        zone.apply(version(2, "www A 10.0.0.2\n"));
+        zone.apply(version(3, "www A 10.0.0.2\nmail A 10.0.0.3\n"));
+        zone
+    }
+
+    #[test]
+    fn ixfr_sends_the_journal_since_the_client_serial() {
+        let zone = journaled_zone();
+        assert_eq!(
+            stream(&build_ixfr(&request(), &zone, 1)),
+            [
+                "SOA 3", "SOA 1", "www.example.com 1", "SOA 2", "www.example.com 1", "SOA 2", "SOA 3",
+                "mail.example.com 1", "SOA 3",
+            ]
+        );
+        assert_eq!(stream(&build_ixfr(&request(), &zone, 2)).len(), 5);
+        // A client that is up to date only gets the current SOA.
+        assert_eq!(stream(&build_ixfr(&request(), &zone, 3)), ["SOA 3"]);
+    }
+
+    #[test]
+    fn ixfr_falls_back_to_axfr() {
+        let axfr = ["SOA 3", "www.example.com 1", "mail.example.com 1", "SOA 3"];
+
+        // The journal does not reach back to the client's version.
+        let zone = journaled_zone();
+        assert_eq!(stream(&build_ixfr(&request(), &zone, 0)), axfr);
+
+        // A change without a serial increase drops the journal.
+        let mut zone = journaled_zone();
+        zone.apply(version(3, "www A 10.0.0.4\nmail A 10.0.0.3\n"));
+        assert!(zone.changes_since(1).is_none());
+        assert_eq!(stream(&build_ixfr(&request(), &zone, 1)).len(), axfr.len());
+    }
+
+    #[test]
+    fn large_transfers_span_messages() {
+        let data: String = (0..1000).map(|index| format!("host{} A 10.0.{}.{}\n", index, index / 256, index % 256)).collect();
+        let messages = build_axfr(&request(), &version(1, &data));
+        assert!(messages.len() > 1);
+        assert_eq!(messages[0].questions.len(), 1);
+        assert!(messages[1..].iter().all(|message| message.questions.is_empty()));
+        assert_eq!(stream(&messages).len(), 1002);
+    }
+}
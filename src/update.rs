@@ -0,0 +1,391 @@
+use std::net::SocketAddr;
+use std::sync::RwLock;
+
+use crate::acl::Operation;
+use crate::config::Config;
+use crate::message::*;
+use crate::notify::notify_secondaries;
+use crate::response::{error_response, recursion_available};
+use crate::zone::{same_record, serial_lt, soa_serial, Zone, ZoneStore};
+
+/// Checks the prerequisite section (RFC 2136 section 3.2) against the
+/// current zone contents.
+fn check_prerequisites(zone: &Zone, prerequisites: &[Answer]) -> Result<(), Rcode> {
+    // Value-dependent prerequisites, grouped into the RRsets they describe.
+    let mut expected: Vec<(Vec<u8>, u16, Vec<&Answer>)> = vec![];
+
+    for prerequisite in prerequisites {
+        if prerequisite.ttl != 0 {
+            return Err(Rcode::FormErr);
+        }
+        if !is_subdomain(&prerequisite.name, &zone.origin) {
+            return Err(Rcode::NotZone);
+        }
+        let records = zone.records_at(&prerequisite.name);
+        let rrset_exists = records.iter().any(|record| record.qtype == prerequisite.qtype);
+
+        match prerequisite.qclass {
+            rtype::CLASS_ANY | rtype::CLASS_NONE if !prerequisite.data.is_empty() => return Err(Rcode::FormErr),
+            rtype::CLASS_ANY if prerequisite.qtype == rtype::ANY => {
+                if records.is_empty() {
+                    return Err(Rcode::NxDomain);
+                }
+            }
+            rtype::CLASS_ANY => {
+                if !rrset_exists {
+                    return Err(Rcode::NxRrset);
+                }
+            }
+            rtype::CLASS_NONE if prerequisite.qtype == rtype::ANY => {
+                if !records.is_empty() {
+                    return Err(Rcode::YxDomain);
+                }
+            }
+            rtype::CLASS_NONE => {
+                if rrset_exists {
+                    return Err(Rcode::YxRrset);
+                }
+            }
+            rtype::CLASS_IN => {
+                let key = lowercase_name(&prerequisite.name);
+                match expected.iter_mut().find(|(name, rtype, _)| *name == key && *rtype == prerequisite.qtype) {
+                    Some((_, _, rrset)) => rrset.push(prerequisite),
+                    None => expected.push((key, prerequisite.qtype, vec![prerequisite])),
+                }
+            }
+            _ => return Err(Rcode::FormErr),
+        }
+    }
+
+    for (name, rtype, rrset) in expected {
+        let actual: Vec<&Answer> = zone.records_at(&name)
+            .iter()
+            .filter(|record| record.qtype == rtype)
+            .collect();
+        let same = actual.len() == rrset.len()
+            && actual.iter().all(|record| rrset.iter().any(|wanted| wanted.data == record.data));
+        if !same {
+            return Err(Rcode::NxRrset);
+        }
+    }
+
+    Ok(())
+}
+
+/// Validates the update section before anything is applied (RFC 2136
+/// section 3.4.1).
+fn prescan(zone: &Zone, updates: &[Answer]) -> Result<(), Rcode> {
+    for update in updates {
+        if !is_subdomain(&update.name, &zone.origin) {
+            return Err(Rcode::NotZone);
+        }
+        let valid = match update.qclass {
+            rtype::CLASS_IN => !rtype::is_meta(update.qtype),
+            rtype::CLASS_ANY => {
+                update.ttl == 0
+                    && update.data.is_empty()
+                    && (update.qtype == rtype::ANY || !rtype::is_meta(update.qtype))
+            }
+            rtype::CLASS_NONE => update.ttl == 0 && !rtype::is_meta(update.qtype),
+            _ => false,
+        };
+        if !valid {
+            return Err(Rcode::FormErr);
+        }
+    }
+    Ok(())
+}
+
+/// Applies the update section to `zone` (RFC 2136 section 3.4.2). Whether
+/// any record was added, removed or had its TTL changed.
+fn apply_updates(zone: &mut Zone, updates: &[Answer]) -> bool {
+    let mut changed = false;
+    for update in updates {
+        let at_apex = names_equal(&update.name, &zone.origin);
+        match update.qclass {
+            rtype::CLASS_IN => {
+                let records = zone.records_at(&update.name);
+                let has_cname = records.iter().any(|record| record.qtype == rtype::CNAME);
+                let has_other = records.iter().any(|record| record.qtype != rtype::CNAME);
+                if update.qtype == rtype::CNAME && has_other {
+                    continue;
+                }
+                if update.qtype != rtype::CNAME && has_cname {
+                    continue;
+                }
+                if update.qtype == rtype::SOA {
+                    let newer = match (zone.serial(), soa_serial(update)) {
+                        (Some(current), Some(serial)) => serial_lt(current, serial),
+                        _ => false,
+                    };
+                    if !at_apex || !newer {
+                        continue;
+                    }
+                }
+                if records.iter().any(|record| same_record(record, update)) {
+                    continue;
+                }
+                if matches!(update.qtype, rtype::CNAME | rtype::SOA) {
+                    zone.remove_rrset(&update.name, update.qtype);
+                }
+                // Re-adding an existing record only refreshes its TTL.
+                zone.remove_record(update);
+                let mut record = update.clone();
+                record.name = zone.records_at(&update.name)
+                    .first()
+                    .map(|existing| existing.name.clone())
+                    .unwrap_or(record.name);
+                zone.insert(record);
+                changed = true;
+            }
+            rtype::CLASS_ANY => {
+                if at_apex && update.qtype == rtype::ANY {
+                    let (kept, removed): (Vec<Answer>, Vec<Answer>) = zone.records_at(&update.name)
+                        .iter()
+                        .cloned()
+                        .partition(|record| record.qtype == rtype::SOA || record.qtype == rtype::NS);
+                    zone.remove_rrset(&update.name, rtype::ANY);
+                    for record in kept {
+                        zone.insert(record);
+                    }
+                    changed |= !removed.is_empty();
+                } else if !(at_apex && matches!(update.qtype, rtype::SOA | rtype::NS)) {
+                    changed |= zone.remove_rrset(&update.name, update.qtype);
+                }
+            }
+            rtype::CLASS_NONE => {
+                if at_apex && update.qtype == rtype::SOA {
+                    continue;
+                }
+                if at_apex && update.qtype == rtype::NS {
+                    let ns_count = zone.records_at(&update.name)
+                        .iter()
+                        .filter(|record| record.qtype == rtype::NS)
+                        .count();
+                    if ns_count <= 1 {
+                        continue;
+                    }
+                }
+                let mut record = update.clone();
+                record.qclass = rtype::CLASS_IN;
+                changed |= zone.remove_record(&record);
+            }
+            _ => {}
+        }
+    }
+    changed
+}
+
+/// Moves the SOA serial forward after a change, unless the update itself
+/// installed a newer SOA.
+fn bump_serial(zone: &mut Zone, old_serial: u32) {
+    let soa = match zone.soa() {
+        Some(soa) => soa.clone(),
+        None => return,
+    };
+    if let Ok(RData::Soa { mname, rname, serial, refresh, retry, expire, minimum }) = soa.rdata() {
+        if serial != old_serial {
+            return;
+        }
+        let bumped = Answer::builder()
+            .name_bytes(soa.name.clone())
+            .unwrap()
+            .qtype(rtype::SOA)
+            .unwrap()
+            .qclass(soa.qclass)
+            .unwrap()
+            .ttl(soa.ttl)
+            .unwrap()
+            .rdata(&RData::Soa {
+                mname,
+                rname,
+                serial: serial.wrapping_add(1),
+                refresh,
+                retry,
+                expire,
+                minimum,
+            })
+            .unwrap()
+            .build();
+        zone.remove_rrset(&soa.name, rtype::SOA);
+        zone.insert(bumped);
+    }
+}
+
+/// Processes an RFC 2136 UPDATE. The zone, prerequisite and update sections
+/// arrive in the question, answer and authority sections of `request`.
+/// `key` names the TSIG key the request was validly signed with.
+pub fn handle_update(
+    request: Message,
+    source: SocketAddr,
+    key: Option<&[u8]>,
+    zones: &RwLock<ZoneStore>,
+    config: &Config,
+) -> Message {
+    let zone = request.questions.first().map(|question| question.name.as_slice()).unwrap_or_default();
+    let recursion = recursion_available(&request, source.ip(), key, config);
+    if !config.access.allows(Operation::Update, zone, source.ip(), key) {
+        println!("Refusing UPDATE from {}", source);
+        return error_response(&request, Rcode::Refused, recursion);
+    }
+    if request.questions.len() != 1 || request.questions[0].qtype != rtype::SOA {
+        return error_response(&request, Rcode::FormErr, recursion);
+    }
+    let origin = request.questions[0].name.clone();
+    if config.secondaries.iter().any(|secondary| names_equal(&secondary.origin, &origin)) {
+        println!("UPDATE for secondary zone {} is not forwarded to its primary", name_to_string(&origin));
+        return error_response(&request, Rcode::NotImp, recursion);
+    }
+
+    let mut zones = zones.write().unwrap();
+    let current = match zones.find_zone(&origin) {
+        Some(zone) if names_equal(&zone.origin, &origin) => zone,
+        _ => return error_response(&request, Rcode::NotAuth, recursion),
+    };
+
+    if let Err(rcode) = check_prerequisites(current, &request.answers) {
+        return error_response(&request, rcode, recursion);
+    }
+    if let Err(rcode) = prescan(current, &request.authorities) {
+        return error_response(&request, rcode, recursion);
+    }
+
+    // Changes are made to a copy so the update applies atomically.
+    let old_serial = current.serial().unwrap_or(0);
+    let mut updated = current.clone();
+    if apply_updates(&mut updated, &request.authorities) {
+        bump_serial(&mut updated, old_serial);
+        updated.set_dynamic();
+        println!(
+            "Applied UPDATE to {}, serial now {}",
+            name_to_string(&origin),
+            updated.serial().unwrap_or(0)
+        );
+        let soa = updated.soa().cloned();
+        zones.update(updated);
+        if let Some(soa) = soa {
+            notify_secondaries(&soa, config);
+        }
+    }
+
+    error_response(&request, Rcode::NoError, recursion)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zone::parse_records;
+
+    fn zone() -> Zone {
+        Zone::parse(
+            "$ORIGIN example.com.
+@ SOA ns admin 10 3600 600 86400 300
+@ NS ns
+ns A 10.0.0.1
+www A 10.0.0.2
+www A 10.0.0.3
+alias CNAME www
+",
+        )
+        .unwrap()
+    }
+
+    fn record(line: &str) -> Answer {
+        parse_records(&format!("$ORIGIN example.com.\n{}\n", line)).unwrap().remove(0)
+    }
+
+    // A record of class ANY or NONE with no RDATA, as prerequisites and
+    // deletions use them.
+    fn empty(name: &str, qtype: u16, qclass: u16) -> Answer {
+        let mut record = record(&format!("{} 0 A 0.0.0.0", name));
+        record.qtype = qtype;
+        record.qclass = qclass;
+        record.data = vec![];
+        record.length = 0;
+        record
+    }
+
+    fn with_class(mut record: Answer, qclass: u16) -> Answer {
+        record.qclass = qclass;
+        record
+    }
+
+    fn addresses(zone: &Zone, name: &str) -> Vec<RData> {
+        zone.records_at(&encode_name(name))
+            .iter()
+            .filter(|record| record.qtype == rtype::A)
+            .map(|record| record.rdata().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn prerequisites() {
+        let zone = zone();
+        let check = |prerequisites: &[Answer]| check_prerequisites(&zone, prerequisites);
+
+        assert_eq!(check(&[empty("www", rtype::ANY, rtype::CLASS_ANY)]), Ok(()));
+        assert_eq!(check(&[empty("nope", rtype::ANY, rtype::CLASS_ANY)]), Err(Rcode::NxDomain));
+        assert_eq!(check(&[empty("www", rtype::A, rtype::CLASS_ANY)]), Ok(()));
+        assert_eq!(check(&[empty("www", rtype::MX, rtype::CLASS_ANY)]), Err(Rcode::NxRrset));
+        assert_eq!(check(&[empty("nope", rtype::ANY, rtype::CLASS_NONE)]), Ok(()));
+        assert_eq!(check(&[empty("www", rtype::ANY, rtype::CLASS_NONE)]), Err(Rcode::YxDomain));
+        assert_eq!(check(&[empty("www", rtype::A, rtype::CLASS_NONE)]), Err(Rcode::YxRrset));
+
+        // Value-dependent prerequisites must describe the whole RRset.
+        let both = [record("www 0 A 10.0.0.3"), record("www 0 A 10.0.0.2")];
+        assert_eq!(check(&both), Ok(()));
+        assert_eq!(check(&both[..1]), Err(Rcode::NxRrset));
+        assert_eq!(check(&[record("www 0 A 10.0.0.2"), record("www 0 A 10.0.0.9")]), Err(Rcode::NxRrset));
+
+        assert_eq!(check(&[record("www 60 A 10.0.0.2")]), Err(Rcode::FormErr));
+        assert_eq!(check(&[with_class(record("www 0 A 10.0.0.2"), rtype::CLASS_ANY)]), Err(Rcode::FormErr));
+        let mut outside = empty("www", rtype::ANY, rtype::CLASS_ANY);
+        outside.name = encode_name("www.example.org");
+        assert_eq!(check(&[outside]), Err(Rcode::NotZone));
+    }
+
+    #[test]
+    fn adds_and_deletes() {
+        let mut zone = zone();
+        assert!(apply_updates(&mut zone, &[record("www 300 A 10.0.0.4"), record("new 300 A 10.0.0.5")]));
+        assert_eq!(addresses(&zone, "www.example.com").len(), 3);
+        assert_eq!(addresses(&zone, "new.example.com").len(), 1);
+
+        // Adding what is already there changes nothing.
+        assert!(!apply_updates(&mut zone, &[record("new 300 A 10.0.0.5")]));
+
+        let delete_one = with_class(record("www 0 A 10.0.0.2"), rtype::CLASS_NONE);
+        assert!(apply_updates(&mut zone, &[delete_one]));
+        assert_eq!(addresses(&zone, "www.example.com").len(), 2);
+        assert!(apply_updates(&mut zone, &[empty("www", rtype::A, rtype::CLASS_ANY)]));
+        assert!(addresses(&zone, "www.example.com").is_empty());
+        assert!(apply_updates(&mut zone, &[empty("new", rtype::ANY, rtype::CLASS_ANY)]));
+        assert!(zone.records_at(&encode_name("new.example.com")).is_empty());
+    }
+
+    #[test]
+    fn keeps_the_zone_consistent() {
+        let mut zone = zone();
+        // A CNAME cannot join other data, nor other data a CNAME.
+        assert!(!apply_updates(&mut zone, &[record("www CNAME ns")]));
+        assert!(!apply_updates(&mut zone, &[record("alias A 10.0.0.9")]));
+        // The apex keeps its SOA and last NS.
+        assert!(!apply_updates(&mut zone, &[empty("@", rtype::ANY, rtype::CLASS_ANY)]));
+        assert!(!apply_updates(&mut zone, &[with_class(record("@ 0 NS ns"), rtype::CLASS_NONE)]));
+        assert!(zone.soa().is_some());
+        // Only a newer SOA replaces the current one.
+        assert!(!apply_updates(&mut zone, &[record("@ SOA ns admin 9 3600 600 86400 300")]));
+        assert!(apply_updates(&mut zone, &[record("@ SOA ns admin 20 3600 600 86400 300")]));
+        assert_eq!(zone.serial(), Some(20));
+    }
+
+    #[test]
+    fn serial_moves_forward_once() {
+        let mut zone = zone();
+        bump_serial(&mut zone, 10);
+        assert_eq!(zone.serial(), Some(11));
+        // An update that set its own serial is left alone.
+        bump_serial(&mut zone, 10);
+        assert_eq!(zone.serial(), Some(11));
+    }
+}
@@ -0,0 +1,841 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Result, anyhow};
+use ring::{digest, signature};
+
+use crate::message::*;
+use crate::response::query_upstream;
+use crate::zone::{parse_records, serial_lt};
+
+// How long a validated DNSKEY RRset, or proof that a zone is unsigned, is
+// trusted before it is fetched again.
+const KEY_LIFETIME: Duration = Duration::from_secs(3600);
+// NSEC3 chains with more iterations are treated as insecure (RFC 9276).
+const MAX_NSEC3_ITERATIONS: u16 = 150;
+
+const DNSKEY_ZONE_KEY: u16 = 0x0100;
+const DNSKEY_PROTOCOL: u8 = 3;
+const NSEC3_OPT_OUT: u8 = 1;
+
+/// The outcome of validating an answer (RFC 4033 section 5).
+#[derive(Debug, Clone, PartialEq)]
+pub enum Security {
+    Secure,
+    // The answer lies below a delegation proven to be unsigned.
+    Insecure,
+    Bogus(String),
+}
+
+#[derive(Debug, Clone)]
+enum ZoneKeys {
+    // The validated DNSKEY RRset of a signed zone.
+    Secure(Vec<Answer>),
+    Insecure,
+}
+
+/// Validates forwarded answers against a chain of trust that starts at the
+/// configured trust anchors, fetching DS and DNSKEY RRsets from upstream.
+#[derive(Debug)]
+pub struct Validator {
+    anchors: Vec<Answer>,
+    // Keyed by the lowercased zone name. Locked only around lookups and
+    // inserts, never while keys are fetched from upstream.
+    keys: Mutex<HashMap<Vec<u8>, (ZoneKeys, Instant)>>,
+}
+
+pub fn now() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as u32)
+        .unwrap_or(0)
+}
+
+fn common_ancestor(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let shared = labels(a)
+        .iter()
+        .rev()
+        .zip(labels(b).iter().rev())
+        .take_while(|(a, b)| a.eq_ignore_ascii_case(b))
+        .count();
+    name_suffix(a, shared)
+}
+
+/// The RRsets in a section, leaving out the RRSIGs that cover them.
+fn unsigned_rrsets(records: &[Answer]) -> Vec<Vec<Answer>> {
+    rrsets(records)
+        .into_iter()
+        .filter(|set| set[0].qtype != rtype::RRSIG)
+        .collect()
+}
+
+fn signatures(records: &[Answer], rrset: &[Answer]) -> Vec<Answer> {
+    records
+        .iter()
+        .filter(|record| record.qtype == rtype::RRSIG && names_equal(&record.name, &rrset[0].name))
+        .filter(|record| match record.rdata() {
+            Ok(RData::Rrsig { type_covered, .. }) => type_covered == rrset[0].qtype,
+            _ => false,
+        })
+        .cloned()
+        .collect()
+}
+
+fn signer_of(records: &[Answer]) -> Option<Vec<u8>> {
+    records.iter().find_map(|record| match record.rdata() {
+        Ok(RData::Rrsig { signer, .. }) => Some(signer),
+        _ => None,
+    })
+}
+
+fn verify_signature(algorithm: u8, key: &[u8], message: &[u8], sig: &[u8]) -> bool {
+    let rsa = |params: &'static signature::RsaParameters| {
+        // The exponent length is one byte, or zero and then two bytes
+        // (RFC 3110 section 2).
+        let (exponent_len, start) = match key.first() {
+            Some(0) if key.len() > 3 => (u16::from_be_bytes([key[1], key[2]]) as usize, 3),
+            Some(len) => (*len as usize, 1),
+            None => return false,
+        };
+        let Some(exponent) = key.get(start..start + exponent_len) else {
+            return false;
+        };
+        let modulus = &key[start + exponent_len..];
+        let leading_zeros = modulus.iter().take_while(|byte| **byte == 0).count();
+        signature::RsaPublicKeyComponents { n: &modulus[leading_zeros..], e: exponent }
+            .verify(params, message, sig)
+            .is_ok()
+    };
+    let ecdsa = |algorithm: &'static signature::EcdsaVerificationAlgorithm| {
+        let mut point = vec![0x04];
+        point.extend(key);
+        signature::UnparsedPublicKey::new(algorithm, point).verify(message, sig).is_ok()
+    };
+    match algorithm {
+        5 | 7 => rsa(&signature::RSA_PKCS1_1024_8192_SHA1_FOR_LEGACY_USE_ONLY),
+        8 => rsa(&signature::RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY),
+        10 => rsa(&signature::RSA_PKCS1_1024_8192_SHA512_FOR_LEGACY_USE_ONLY),
+        13 => ecdsa(&signature::ECDSA_P256_SHA256_FIXED),
+        14 => ecdsa(&signature::ECDSA_P384_SHA384_FIXED),
+        15 => signature::UnparsedPublicKey::new(&signature::ED25519, key).verify(message, sig).is_ok(),
+        _ => false,
+    }
+}
+
+fn algorithm_supported(algorithm: u8) -> bool {
+    matches!(algorithm, 5 | 7 | 8 | 10 | 13 | 14 | 15)
+}
+
+fn ds_digest(digest_type: u8) -> Option<&'static digest::Algorithm> {
+    match digest_type {
+        1 => Some(&digest::SHA1_FOR_LEGACY_USE_ONLY),
+        2 => Some(&digest::SHA256),
+        4 => Some(&digest::SHA384),
+        _ => None,
+    }
+}
+
+/// Whether a DS record refers to the DNSKEY `key` (RFC 4034 section 5.1.4).
+fn ds_matches(ds: &RData, key: &Answer) -> bool {
+    let RData::Ds { key_tag, algorithm, digest_type, digest: expected } = ds else {
+        return false;
+    };
+    let Ok(dnskey) = key.rdata() else {
+        return false;
+    };
+    let Some(digest_algorithm) = ds_digest(*digest_type) else {
+        return false;
+    };
+    let matches_key = match &dnskey {
+        RData::Dnskey { algorithm: key_algorithm, .. } => key_algorithm == algorithm,
+        _ => false,
+    };
+    let mut data = lowercase_name(&key.name);
+    data.push(0);
+    data.extend(dnskey.encode());
+    matches_key
+        && dnskey.key_tag() == Some(*key_tag)
+        && digest::digest(digest_algorithm, &data).as_ref() == expected.as_slice()
+}
+
+/// Whether a trust anchor, given as DS or DNSKEY, designates `key`.
+fn anchor_matches(anchor: &Answer, key: &Answer) -> bool {
+    match anchor.rdata() {
+        Ok(RData::Dnskey { .. }) => anchor.data == key.data,
+        Ok(ds @ RData::Ds { .. }) => ds_matches(&ds, key),
+        _ => false,
+    }
+}
+
+/// Checks the RRSIGs over `rrset` against `keys` (RFC 4035 section 5.3) and
+/// returns the label count of the signature that verified.
+pub fn verify_rrset(rrset: &[Answer], sigs: &[Answer], keys: &[Answer]) -> Result<u8, String> {
+    verify_rrset_at(rrset, sigs, keys, now())
+}
+
+fn verify_rrset_at(rrset: &[Answer], sigs: &[Answer], keys: &[Answer], now: u32) -> Result<u8, String> {
+    let owner = &rrset[0].name;
+    let mut problem = format!("no signature for {} type {}", name_to_string(owner), rrset[0].qtype);
+
+    for sig in sigs {
+        let Ok(rrsig) = sig.rdata() else {
+            continue;
+        };
+        let RData::Rrsig { algorithm, labels, original_ttl, expiration, inception, key_tag, signer, signature, .. } =
+            &rrsig
+        else {
+            continue;
+        };
+        if !is_subdomain(owner, signer) || *labels as usize > label_count(owner) {
+            continue;
+        }
+        if serial_lt(now, *inception) || serial_lt(*expiration, now) {
+            problem = format!("signature for {} is outside its validity period", name_to_string(owner));
+            continue;
+        }
+
+        // The signed data is the RRSIG RDATA without the signature followed
+        // by the RRset in canonical form (RFC 4034 section 3.1.8.1).
+        let mut unsigned = rrsig.canonical();
+        if let RData::Rrsig { signature, .. } = &mut unsigned {
+            signature.clear();
+        }
+        let mut data = unsigned.encode();
+        let signed_owner = if (*labels as usize) < label_count(owner) {
+            wildcard_name(&name_suffix(owner, *labels as usize))
+        } else {
+            owner.clone()
+        };
+        for record in canonical_rrset(rrset) {
+            let mut record = record.clone();
+            record.name = signed_owner.clone();
+            data.extend(record.canonical_form(*original_ttl));
+        }
+
+        for key in keys.iter().filter(|key| names_equal(&key.name, signer)) {
+            let Ok(dnskey) = key.rdata() else {
+                continue;
+            };
+            let RData::Dnskey { flags, protocol, algorithm: key_algorithm, public_key } = &dnskey else {
+                continue;
+            };
+            if flags & DNSKEY_ZONE_KEY == 0
+                || *protocol != DNSKEY_PROTOCOL
+                || key_algorithm != algorithm
+                || dnskey.key_tag() != Some(*key_tag)
+            {
+                continue;
+            }
+            if verify_signature(*algorithm, public_key, &data, signature) {
+                return Ok(*labels);
+            }
+            problem = format!("bad signature for {} type {}", name_to_string(owner), rrset[0].qtype);
+        }
+    }
+
+    Err(problem)
+}
+
+/// Whether an NSEC from `owner` to `next` covers `name`, that is, proves no
+/// name exists strictly between them. The last NSEC in a zone wraps around
+/// to the apex.
+fn nsec_covers(owner: &[u8], next: &[u8], name: &[u8]) -> bool {
+    let after_owner = canonical_cmp(owner, name) == Ordering::Less;
+    let before_next = canonical_cmp(name, next) == Ordering::Less;
+    if canonical_cmp(owner, next) == Ordering::Less {
+        after_owner && before_next
+    } else {
+        after_owner || before_next
+    }
+}
+
+/// The NSEC3 hash of a name (RFC 5155 section 5).
+pub fn nsec3_hash(name: &[u8], salt: &[u8], iterations: u16) -> Vec<u8> {
+    let mut input = lowercase_name(name);
+    input.push(0);
+    let mut hash = input;
+    for _ in 0..=iterations {
+        let mut context = digest::Context::new(&digest::SHA1_FOR_LEGACY_USE_ONLY);
+        context.update(&hash);
+        context.update(salt);
+        hash = context.finish().as_ref().to_vec();
+    }
+    hash
+}
+
+struct Nsec3 {
+    // The hash from the first label of the owner name.
+    hash: Vec<u8>,
+    zone: Vec<u8>,
+    flags: u8,
+    iterations: u16,
+    salt: Vec<u8>,
+    next_hashed: Vec<u8>,
+    types: Vec<u16>,
+}
+
+impl Nsec3 {
+    fn from_record(record: &Answer) -> Option<Nsec3> {
+        let RData::Nsec3 { hash_algorithm: 1, flags, iterations, salt, next_hashed, types } = record.rdata().ok()? else {
+            return None;
+        };
+        let label = labels(&record.name).first()?.to_vec();
+        Some(Nsec3 {
+            hash: base32hex_decode(std::str::from_utf8(&label).ok()?).ok()?,
+            zone: parent_name(&record.name)?,
+            flags,
+            iterations,
+            salt,
+            next_hashed,
+            types,
+        })
+    }
+
+    fn hash_of(&self, name: &[u8]) -> Vec<u8> {
+        nsec3_hash(name, &self.salt, self.iterations)
+    }
+
+    fn matches(&self, name: &[u8]) -> bool {
+        is_subdomain(name, &self.zone) && self.hash_of(name) == self.hash
+    }
+
+    fn covers(&self, name: &[u8]) -> bool {
+        if !is_subdomain(name, &self.zone) {
+            return false;
+        }
+        let hash = self.hash_of(name);
+        if self.hash < self.next_hashed {
+            self.hash < hash && hash < self.next_hashed
+        } else {
+            self.hash < hash || hash < self.next_hashed
+        }
+    }
+
+    fn denies(&self, qtype: u16) -> bool {
+        !self.types.contains(&qtype) && !self.types.contains(&rtype::CNAME)
+    }
+}
+
+/// Denial of existence records from a response's authority section, already
+/// checked to be signed by a secure zone.
+struct Denial {
+    nsec: Vec<(Vec<u8>, Vec<u8>, Vec<u16>)>,
+    nsec3: Vec<Nsec3>,
+}
+
+impl Denial {
+    fn new(records: &[Answer]) -> Denial {
+        let nsec = records
+            .iter()
+            .filter_map(|record| match record.rdata() {
+                Ok(RData::Nsec { next, types }) => Some((record.name.clone(), next, types)),
+                _ => None,
+            })
+            .collect();
+        let nsec3 = records.iter().filter_map(Nsec3::from_record).collect();
+        Denial { nsec, nsec3 }
+    }
+
+    fn nsec_covering(&self, name: &[u8]) -> Option<&(Vec<u8>, Vec<u8>, Vec<u16>)> {
+        self.nsec.iter().find(|(owner, next, _)| nsec_covers(owner, next, name))
+    }
+
+    fn nsec_matching(&self, name: &[u8]) -> Option<&Vec<u16>> {
+        self.nsec
+            .iter()
+            .find(|(owner, _, _)| names_equal(owner, name))
+            .map(|(_, _, types)| types)
+    }
+
+    /// The closest encloser of a name proven not to exist with NSEC: the
+    /// longest ancestor shared with the covering NSEC's owner or next name.
+    fn nsec_closest_encloser(&self, name: &[u8]) -> Option<Vec<u8>> {
+        let (owner, next, _) = self.nsec_covering(name)?;
+        let by_owner = common_ancestor(name, owner);
+        let by_next = common_ancestor(name, next);
+        Some(if label_count(&by_owner) >= label_count(&by_next) { by_owner } else { by_next })
+    }
+
+    fn weak_nsec3(&self) -> bool {
+        self.nsec3.iter().any(|nsec3| nsec3.iterations > MAX_NSEC3_ITERATIONS)
+    }
+
+    fn nsec3_matching(&self, name: &[u8]) -> Option<&Nsec3> {
+        self.nsec3.iter().find(|nsec3| nsec3.matches(name))
+    }
+
+    fn nsec3_covering(&self, name: &[u8]) -> Option<&Nsec3> {
+        self.nsec3.iter().find(|nsec3| nsec3.covers(name))
+    }
+
+    /// The NSEC3 closest encloser proof (RFC 5155 section 8.3): the closest
+    /// encloser and whether the NSEC3 covering the next closer name has the
+    /// opt-out flag set.
+    fn nsec3_closest_encloser(&self, name: &[u8]) -> Option<(Vec<u8>, bool)> {
+        let mut next_closer = name.to_vec();
+        while let Some(candidate) = parent_name(&next_closer) {
+            if self.nsec3_matching(&candidate).is_some() {
+                let covering = self.nsec3_covering(&next_closer)?;
+                return Some((candidate, covering.flags & NSEC3_OPT_OUT != 0));
+            }
+            next_closer = candidate;
+        }
+        None
+    }
+
+    /// Proves that `name` does not exist. Returns whether the proof is
+    /// secure; NSEC3 chains too expensive to check count as insecure.
+    fn prove_nxdomain(&self, name: &[u8]) -> Result<bool, String> {
+        if let Some(closest_encloser) = self.nsec_closest_encloser(name) {
+            if self.nsec_covering(&wildcard_name(&closest_encloser)).is_some() {
+                return Ok(true);
+            }
+        }
+        if self.weak_nsec3() {
+            return Ok(false);
+        }
+        if let Some((closest_encloser, opt_out)) = self.nsec3_closest_encloser(name) {
+            if self.nsec3_covering(&wildcard_name(&closest_encloser)).is_some() {
+                return Ok(!opt_out);
+            }
+        }
+        Err(format!("no proof that {} does not exist", name_to_string(name)))
+    }
+
+    /// Proves that `name` has no records of type `qtype`.
+    fn prove_nodata(&self, name: &[u8], qtype: u16) -> Result<bool, String> {
+        // Above a delegation only the DS query may be answered from the
+        // parent's NSEC (RFC 4035 section 5.4).
+        let from_parent = |types: &Vec<u16>| {
+            qtype != rtype::DS && types.contains(&rtype::NS) && !types.contains(&rtype::SOA)
+        };
+        if let Some(types) = self.nsec_matching(name) {
+            if !types.contains(&qtype) && !types.contains(&rtype::CNAME) && !from_parent(types) {
+                return Ok(true);
+            }
+        }
+        // An empty non-terminal has no NSEC of its own; the NSEC before it
+        // leads to one of its descendants.
+        let empty_non_terminal = self.nsec_covering(name)
+            .is_some_and(|(_, next, _)| !names_equal(next, name) && is_subdomain(next, name));
+        if empty_non_terminal {
+            return Ok(true);
+        }
+        if let Some(closest_encloser) = self.nsec_closest_encloser(name) {
+            if let Some(types) = self.nsec_matching(&wildcard_name(&closest_encloser)) {
+                if !types.contains(&qtype) && !types.contains(&rtype::CNAME) {
+                    return Ok(true);
+                }
+            }
+        }
+        if self.weak_nsec3() {
+            return Ok(false);
+        }
+        if let Some(nsec3) = self.nsec3_matching(name) {
+            if nsec3.denies(qtype) && !from_parent(&nsec3.types) {
+                return Ok(true);
+            }
+        }
+        if let Some((closest_encloser, opt_out)) = self.nsec3_closest_encloser(name) {
+            // An opt-out span may hide an unsigned delegation (RFC 5155
+            // section 8.6).
+            if qtype == rtype::DS && opt_out {
+                return Ok(false);
+            }
+            if let Some(nsec3) = self.nsec3_matching(&wildcard_name(&closest_encloser)) {
+                if nsec3.denies(qtype) {
+                    return Ok(true);
+                }
+            }
+        }
+        Err(format!("no proof that {} has no type {}", name_to_string(name), qtype))
+    }
+
+    /// Proves that a wildcard was expanded to answer `name` because no
+    /// closer match exists (RFC 4035 section 5.3.4). `labels` is the label
+    /// count of the RRSIG, which is that of the wildcard's parent.
+    fn prove_wildcard(&self, name: &[u8], labels: u8) -> Result<bool, String> {
+        if self.nsec_covering(name).is_some() {
+            return Ok(true);
+        }
+        if self.weak_nsec3() {
+            return Ok(false);
+        }
+        let next_closer = name_suffix(name, labels as usize + 1);
+        match self.nsec3_covering(&next_closer) {
+            Some(nsec3) => Ok(nsec3.flags & NSEC3_OPT_OUT == 0),
+            None => Err(format!("no proof for wildcard expansion of {}", name_to_string(name))),
+        }
+    }
+}
+
+impl Validator {
+    pub fn new(anchors: Vec<Answer>) -> Validator {
+        Validator {
+            anchors,
+            keys: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Loads DS or DNSKEY trust anchors from files in master file format.
+    pub fn load(paths: &[String]) -> Result<Validator> {
+        let mut anchors = vec![];
+        for path in paths {
+            let text = fs::read_to_string(path)
+                .map_err(|e| anyhow!("Failed to read trust anchor file {}: {}", path, e))?;
+            anchors.extend(
+                parse_records(&text)?
+                    .into_iter()
+                    .filter(|record| record.qtype == rtype::DS || record.qtype == rtype::DNSKEY),
+            );
+        }
+        if anchors.is_empty() {
+            return Err(anyhow!("No DS or DNSKEY trust anchors found"));
+        }
+        Ok(Validator::new(anchors))
+    }
+
+    /// Validates an upstream response to `question`.
+    pub fn validate(&self, question: &Question, response: &Message, resolver: &str) -> Security {
+        match self.check_response(question, response, resolver) {
+            Ok(true) => Security::Secure,
+            Ok(false) => Security::Insecure,
+            Err(reason) => Security::Bogus(reason),
+        }
+    }
+
+    fn fetch(&self, name: &[u8], qtype: u16, resolver: &str) -> Result<Message, String> {
+        let question = Question {
+            name: name.to_vec(),
+            qtype,
+            qclass: rtype::CLASS_IN,
+        };
+        let header = Header::builder().qr(false).unwrap().rd(true).unwrap().build();
+        let response = query_upstream(&header, question, resolver, true)
+            .map_err(|e| format!("{} query for {} failed: {}", qtype, name_to_string(name), e))?;
+        if !matches!(response.header.rcode, Rcode::NoError | Rcode::NxDomain) {
+            return Err(format!(
+                "{} query for {} failed with rcode {}",
+                qtype,
+                name_to_string(name),
+                response.header.rcode
+            ));
+        }
+        Ok(response)
+    }
+
+    /// The apex of the zone `name` belongs to, from the SOA upstream
+    /// returns for it.
+    fn enclosing_zone(&self, name: &[u8], resolver: &str) -> Result<Vec<u8>, String> {
+        let response = self.fetch(name, rtype::SOA, resolver)?;
+        response.answers
+            .iter()
+            .chain(response.authorities.iter())
+            .find(|record| record.qtype == rtype::SOA && is_subdomain(name, &record.name))
+            .map(|soa| soa.name.clone())
+            .ok_or_else(|| format!("cannot find the zone of {}", name_to_string(name)))
+    }
+
+    fn zone_keys(&self, zone: &[u8], resolver: &str) -> Result<ZoneKeys, String> {
+        let key = lowercase_name(zone);
+        if let Some((keys, expires)) = self.keys.lock().unwrap().get(&key) {
+            if *expires > Instant::now() {
+                return Ok(keys.clone());
+            }
+        }
+        let keys = self.fetch_zone_keys(zone, resolver)?;
+        self.keys.lock().unwrap().insert(key, (keys.clone(), Instant::now() + KEY_LIFETIME));
+        Ok(keys)
+    }
+
+    /// Establishes the keys of `zone` by walking the chain of trust down from
+    /// its parent (RFC 4035 section 5.2).
+    fn fetch_zone_keys(&self, zone: &[u8], resolver: &str) -> Result<ZoneKeys, String> {
+        let anchors: Vec<Answer> = self.anchors
+            .iter()
+            .filter(|anchor| names_equal(&anchor.name, zone))
+            .cloned()
+            .collect();
+        if !anchors.is_empty() {
+            let keys = self.trusted_keys(zone, resolver, |key| {
+                anchors.iter().any(|anchor| anchor_matches(anchor, key))
+            })?;
+            return Ok(ZoneKeys::Secure(keys));
+        }
+        if !self.anchors.iter().any(|anchor| is_subdomain(zone, &anchor.name)) {
+            return Ok(ZoneKeys::Insecure);
+        }
+
+        let response = self.fetch(zone, rtype::DS, resolver)?;
+        let signer = signer_of(&response.answers).or_else(|| signer_of(&response.authorities));
+        let parent = match signer {
+            Some(signer) if is_subdomain(zone, &signer) && !names_equal(zone, &signer) => signer,
+            Some(signer) => {
+                return Err(format!(
+                    "DS for {} signed by {}",
+                    name_to_string(zone),
+                    name_to_string(&signer)
+                ))
+            }
+            None => {
+                // Only an unsigned parent may answer DS without signatures.
+                let parent = self.enclosing_zone(&parent_name(zone).unwrap_or_default(), resolver)?;
+                return match self.zone_keys(&parent, resolver)? {
+                    ZoneKeys::Insecure => Ok(ZoneKeys::Insecure),
+                    ZoneKeys::Secure(_) => Err(format!("unsigned DS response for {}", name_to_string(zone))),
+                };
+            }
+        };
+        let parent_keys = match self.zone_keys(&parent, resolver)? {
+            ZoneKeys::Secure(keys) => keys,
+            ZoneKeys::Insecure => return Ok(ZoneKeys::Insecure),
+        };
+
+        let ds_set: Vec<Answer> = response.answers
+            .iter()
+            .filter(|record| record.qtype == rtype::DS && names_equal(&record.name, zone))
+            .cloned()
+            .collect();
+        if ds_set.is_empty() {
+            let denial = self.secure_denial(&response.authorities, &parent_keys)?;
+            // Either way the delegation has no DS, so the child is unsigned.
+            denial.prove_nodata(zone, rtype::DS)?;
+            return Ok(ZoneKeys::Insecure);
+        }
+
+        verify_rrset(&ds_set, &signatures(&response.answers, &ds_set), &parent_keys)?;
+        let ds: Vec<RData> = ds_set
+            .iter()
+            .filter_map(|record| record.rdata().ok())
+            .filter(|ds| match ds {
+                RData::Ds { algorithm, digest_type, .. } => {
+                    algorithm_supported(*algorithm) && ds_digest(*digest_type).is_some()
+                }
+                _ => false,
+            })
+            .collect();
+        // A zone signed only with algorithms we cannot check is treated as
+        // unsigned (RFC 4035 section 5.2).
+        if ds.is_empty() {
+            return Ok(ZoneKeys::Insecure);
+        }
+        let keys = self.trusted_keys(zone, resolver, |key| ds.iter().any(|ds| ds_matches(ds, key)))?;
+        Ok(ZoneKeys::Secure(keys))
+    }
+
+    /// Fetches the DNSKEY RRset of `zone` and checks it is signed by a key
+    /// that `is_trusted` accepts.
+    fn trusted_keys(
+        &self,
+        zone: &[u8],
+        resolver: &str,
+        is_trusted: impl Fn(&Answer) -> bool,
+    ) -> Result<Vec<Answer>, String> {
+        let response = self.fetch(zone, rtype::DNSKEY, resolver)?;
+        let keys: Vec<Answer> = response.answers
+            .iter()
+            .filter(|record| record.qtype == rtype::DNSKEY && names_equal(&record.name, zone))
+            .cloned()
+            .collect();
+        if keys.is_empty() {
+            return Err(format!("no DNSKEY for {}", name_to_string(zone)));
+        }
+        let trusted: Vec<Answer> = keys.iter().filter(|key| is_trusted(key)).cloned().collect();
+        if trusted.is_empty() {
+            return Err(format!("no trusted DNSKEY for {}", name_to_string(zone)));
+        }
+        verify_rrset(&keys, &signatures(&response.answers, &keys), &trusted)?;
+        Ok(keys)
+    }
+
+    /// Collects the NSEC and NSEC3 records in `records`, requiring each
+    /// RRset to verify with `keys`.
+    fn secure_denial(&self, records: &[Answer], keys: &[Answer]) -> Result<Denial, String> {
+        let mut verified = vec![];
+        for rrset in unsigned_rrsets(records) {
+            if rrset[0].qtype == rtype::NSEC || rrset[0].qtype == rtype::NSEC3 {
+                verify_rrset(&rrset, &signatures(records, &rrset), keys)?;
+                verified.extend(rrset);
+            }
+        }
+        Ok(Denial::new(&verified))
+    }
+
+    /// Validates one RRset. Returns the label count of its verified RRSIG,
+    /// or `None` if it belongs to an unsigned zone.
+    fn check_rrset(&self, rrset: &[Answer], section: &[Answer], resolver: &str) -> Result<Option<u8>, String> {
+        let owner = &rrset[0].name;
+        let sigs = signatures(section, rrset);
+        let zone = match signer_of(&sigs) {
+            Some(signer) if is_subdomain(owner, &signer) => signer,
+            Some(signer) => {
+                return Err(format!(
+                    "{} signed by unrelated zone {}",
+                    name_to_string(owner),
+                    name_to_string(&signer)
+                ))
+            }
+            None => self.enclosing_zone(owner, resolver)?,
+        };
+        match self.zone_keys(&zone, resolver)? {
+            ZoneKeys::Insecure => Ok(None),
+            ZoneKeys::Secure(keys) => verify_rrset(rrset, &sigs, &keys).map(Some),
+        }
+    }
+
+    /// Returns whether the response is secure, or why it is bogus.
+    fn check_response(&self, question: &Question, response: &Message, resolver: &str) -> Result<bool, String> {
+        let rcode = response.header.rcode;
+        if !matches!(rcode, Rcode::NoError | Rcode::NxDomain) {
+            return Ok(false);
+        }
+        let mut secure = true;
+
+        let dnames: Vec<Vec<u8>> = response.answers
+            .iter()
+            .filter(|record| record.qtype == rtype::DNAME)
+            .map(|record| record.name.clone())
+            .collect();
+        let mut wildcards = vec![];
+        for rrset in unsigned_rrsets(&response.answers) {
+            let owner = rrset[0].name.clone();
+            // CNAMEs synthesized from a DNAME are not signed; the DNAME is.
+            let synthesized = rrset[0].qtype == rtype::CNAME
+                && dnames.iter().any(|dname| is_subdomain(&owner, dname) && !names_equal(&owner, dname));
+            if synthesized {
+                continue;
+            }
+            match self.check_rrset(&rrset, &response.answers, resolver)? {
+                Some(labels) if (labels as usize) < label_count(&owner) => wildcards.push((owner, labels)),
+                Some(_) => {}
+                None => secure = false,
+            }
+        }
+
+        let mut denial_keys = None;
+        for rrset in unsigned_rrsets(&response.authorities) {
+            if !matches!(rrset[0].qtype, rtype::SOA | rtype::NSEC | rtype::NSEC3) {
+                continue;
+            }
+            match self.check_rrset(&rrset, &response.authorities, resolver)? {
+                Some(_) => denial_keys = Some(true),
+                None => {
+                    secure = false;
+                    denial_keys.get_or_insert(false);
+                }
+            }
+        }
+        let denial = Denial::new(&response.authorities);
+
+        // Walk the CNAME chain to the name the final answer is about.
+        let mut name = question.name.clone();
+        let mut steps = 0;
+        while let Some(target) = response.answers
+            .iter()
+            .filter(|record| record.qtype == rtype::CNAME && names_equal(&record.name, &name))
+            .find_map(|record| record.rdata().ok().and_then(|rdata| rdata.target().map(|target| target.to_vec())))
+        {
+            name = target;
+            steps += 1;
+            if steps > response.answers.len() {
+                break;
+            }
+        }
+        let answered = response.answers.iter().any(|record| {
+            names_equal(&record.name, &name) && (record.qtype == question.qtype || question.qtype == rtype::ANY)
+        });
+
+        if !answered {
+            let zone_secure = match denial_keys {
+                Some(secure) => secure,
+                None => {
+                    let zone = self.enclosing_zone(&name, resolver)?;
+                    matches!(self.zone_keys(&zone, resolver)?, ZoneKeys::Secure(_))
+                }
+            };
+            if zone_secure {
+                let proven = if rcode == Rcode::NxDomain {
+                    denial.prove_nxdomain(&name)?
+                } else {
+                    denial.prove_nodata(&name, question.qtype)?
+                };
+                secure &= proven;
+            }
+        }
+        for (owner, labels) in wildcards {
+            secure &= denial.prove_wildcard(&owner, labels)?;
+        }
+
+        Ok(secure)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The Ed25519 example of RFC 8080 section 6.1.
+    const RFC8080_EXAMPLE: &str = "\
+example.com. 3600 IN DNSKEY 257 3 15 l02Woi0iS8Aa25FQkUd9RMzZHJpBoRQwAQEX1SxZJA4=
+example.com. 3600 IN DS 3613 15 2 3aa5ab37efce57f737fc1627013fee07bdf241bd10f3b1964ab55c78e79a304b
+example.com. 3600 IN MX 10 mail.example.com.
+example.com. 3600 IN RRSIG MX 15 2 3600 1440021600 1438207200 3613 example.com. oL9krJun7xfBOIWcGHi7mag5/hdZrKWw15jPGrHpjQeRAvTdszaPD+QLs3fx8A4M3e23mRZ9VrbpMngwcrqNAg==
+";
+    // Between the inception and expiration of the example signature.
+    const RFC8080_TIME: u32 = 1439000000;
+
+    fn rfc8080_records(qtype: u16) -> Vec<Answer> {
+        parse_records(RFC8080_EXAMPLE)
+            .unwrap()
+            .into_iter()
+            .filter(|record| record.qtype == qtype)
+            .collect()
+    }
+
+    #[test]
+    fn rfc8080_rrsig_verifies() {
+        let keys = rfc8080_records(rtype::DNSKEY);
+        let mx = rfc8080_records(rtype::MX);
+        let sigs = rfc8080_records(rtype::RRSIG);
+        assert_eq!(verify_rrset_at(&mx, &sigs, &keys, RFC8080_TIME), Ok(2));
+    }
+
+    #[test]
+    fn rfc8080_rrsig_rejects_changed_data_and_expiry() {
+        let keys = rfc8080_records(rtype::DNSKEY);
+        let sigs = rfc8080_records(rtype::RRSIG);
+        let mut mx = rfc8080_records(rtype::MX);
+        assert!(verify_rrset_at(&mx, &sigs, &keys, 1440021601).is_err());
+        // Names in RDATA are compared in lowercase, so case changes
+        // still verify.
+        let last = mx[0].data.len() - 2;
+        mx[0].data[last] ^= 0x20;
+        assert_eq!(verify_rrset_at(&mx, &sigs, &keys, RFC8080_TIME), Ok(2));
+        mx[0].data[1] = 20;
+        assert!(verify_rrset_at(&mx, &sigs, &keys, RFC8080_TIME).is_err());
+    }
+
+    #[test]
+    fn rfc8080_ds_matches_key() {
+        let key = &rfc8080_records(rtype::DNSKEY)[0];
+        let ds = rfc8080_records(rtype::DS)[0].rdata().unwrap();
+        assert!(ds_matches(&ds, key));
+    }
+
+    // Hashes from RFC 5155 appendix A.
+    #[test]
+    fn rfc5155_nsec3_hashes() {
+        let salt = [0xaa, 0xbb, 0xcc, 0xdd];
+        assert_eq!(
+            nsec3_hash(&encode_name("example"), &salt, 12),
+            base32hex_decode("0p9mhaveqvm6t7vbl5lop2u3t2rp3tom").unwrap()
+        );
+        assert_eq!(
+            nsec3_hash(&encode_name("a.example"), &salt, 12),
+            base32hex_decode("35mthgpgcu1qg68fab165klnsnk3dpvl").unwrap()
+        );
+    }
+}
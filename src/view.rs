@@ -0,0 +1,121 @@
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, RwLock};
+
+use anyhow::Result;
+
+use crate::acl::Cidr;
+use crate::blocklist::Blocklist;
+use crate::cache::Cache;
+use crate::config::Config;
+use crate::hosts::HostsTable;
+use crate::message::*;
+use crate::rpz::ResponsePolicy;
+use crate::zone::{Zone, ZoneStore};
+
+/// The zone data, cache and settings a set of clients is answered from.
+/// Each view keeps its own cache so that answers forwarded for one view
+/// never leak into another.
+pub struct View {
+    pub name: String,
+    // Empty for the default view, which matches every client.
+    pub clients: Vec<Cidr>,
+    pub config: Arc<Config>,
+    pub zones: Arc<RwLock<ZoneStore>>,
+    pub cache: Arc<Mutex<Cache>>,
+}
+
+impl View {
+    /// Loads the zones and local data `config` names.
+    pub fn load(name: &str, clients: Vec<Cidr>, config: Arc<Config>) -> Result<View> {
+        let mut zones = ZoneStore::new();
+        for path in &config.zone_files {
+            let zone = Zone::load(path)?;
+            println!("Loaded zone {} from {} for view {}", name_to_string(&zone.origin), path, name);
+            zones.insert(zone);
+        }
+        zones.set_hosts(HostsTable::load(&config.hosts_files, &config.overrides_files)?);
+        let blocklist = Blocklist::load(&config.blocklist_files, &config.allowlist_files, config.block_action.clone())?;
+        if !blocklist.is_empty() {
+            println!("Loaded {} blocked domains for view {}", blocklist.len(), name);
+        }
+        zones.set_blocklist(blocklist);
+        zones.set_policy(ResponsePolicy::load(&config.rpz_files)?);
+
+        Ok(View {
+            name: name.to_owned(),
+            clients,
+            config,
+            zones: Arc::new(RwLock::new(zones)),
+            cache: Arc::new(Mutex::new(Cache::new())),
+        })
+    }
+
+    /// Builds the top-level view followed by one for each configured view.
+    pub fn load_all(config: &Arc<Config>) -> Result<Vec<Arc<View>>> {
+        let mut views = vec![];
+        for view in &config.views {
+            let view_config = Arc::new(config.for_view(view));
+            views.push(Arc::new(View::load(&view.name, view.clients.clone(), view_config)?));
+        }
+        views.insert(0, Arc::new(View::load("default", vec![], config.clone())?));
+        Ok(views)
+    }
+
+    pub fn matches(&self, client: IpAddr) -> bool {
+        self.clients.is_empty() || self.clients.iter().any(|network| network.contains(client))
+    }
+}
+
+/// The view to answer `client` from: the first configured view whose
+/// networks contain it, or the default view.
+pub fn select(views: &[Arc<View>], client: IpAddr) -> &Arc<View> {
+    views[1..]
+        .iter()
+        .find(|view| view.matches(client))
+        .unwrap_or(&views[0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn views(args: &[&str]) -> Vec<Arc<View>> {
+        let args: Vec<String> = std::iter::once("dns").chain(args.iter().copied()).map(str::to_owned).collect();
+        View::load_all(&Arc::new(Config::from_args(&args).unwrap())).unwrap()
+    }
+
+    fn select_name(views: &[Arc<View>], client: &str) -> String {
+        select(views, client.parse().unwrap()).name.clone()
+    }
+
+    #[test]
+    fn selects_the_first_matching_view() {
+        let views = views(&[
+            "--resolver", "8.8.8.8:53",
+            "--view", "internal=10.0.0.0/8,192.168.1.0/24",
+            "--resolver", "10.0.0.53:53",
+            "--view", "lab=10.1.0.0/16",
+            "--view", "v6=fd00::/8",
+        ]);
+        assert_eq!(views.len(), 4);
+        assert_eq!(select_name(&views, "10.1.2.3"), "internal");
+        assert_eq!(select_name(&views, "192.168.1.20"), "internal");
+        assert_eq!(select_name(&views, "fd00::1"), "v6");
+        assert_eq!(select_name(&views, "192.168.2.20"), "default");
+        assert_eq!(select_name(&views, "::ffff:10.1.2.3"), "default");
+
+        // Settings given after --view belong to that view alone.
+        assert_eq!(select(&views, "10.1.2.3".parse().unwrap()).config.resolver.as_deref(), Some("10.0.0.53:53"));
+        assert_eq!(views[0].config.resolver.as_deref(), Some("8.8.8.8:53"));
+        assert_eq!(views[0].config.views.len(), 3);
+        assert!(views[1].config.views.is_empty());
+    }
+
+    #[test]
+    fn defaults_without_views() {
+        let views = views(&[]);
+        assert_eq!(views.len(), 1);
+        assert_eq!(select_name(&views, "203.0.113.1"), "default");
+        assert_eq!(select_name(&views, "2001:db8::1"), "default");
+    }
+}
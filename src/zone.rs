@@ -0,0 +1,727 @@
+use std::collections::BTreeMap;
+use std::fs;
+
+use anyhow::{Result, anyhow};
+
+use crate::blocklist::Blocklist;
+use crate::hosts::HostsTable;
+use crate::rpz::ResponsePolicy;
+use crate::message::*;
+
+const DEFAULT_TTL: u32 = 3600;
+// Number of zone versions kept for incremental transfers.
+const MAX_JOURNAL_ENTRIES: usize = 100;
+
+#[derive(Debug)]
+pub enum Lookup {
+    Records(Vec<Answer>),
+    Cname(Answer),
+    // The name is below a DNAME; carries the DNAME and the CNAME
+    // synthesized from it (RFC 6672 section 3.1).
+    Dname(Answer, Answer),
+    // Substituting the DNAME target would exceed the maximum name length.
+    NameTooLong(Answer),
+    // The name is at or below a zone cut; carries the delegation NS RRset
+    // and any glue addresses for it.
+    Referral {
+        ns: Vec<Answer>,
+        glue: Vec<Answer>,
+    },
+    NoData,
+    NxDomain,
+}
+
+/// The difference between two consecutive versions of a zone, in the
+/// shape IXFR sends it (RFC 1995 section 4).
+#[derive(Debug, Clone)]
+pub struct JournalEntry {
+    pub old_soa: Answer,
+    pub new_soa: Answer,
+    pub removed: Vec<Answer>,
+    pub added: Vec<Answer>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Zone {
+    pub origin: Vec<u8>,
+    // Keyed by the lowercased owner name.
+    records: BTreeMap<Vec<u8>, Vec<Answer>>,
+    journal: Vec<JournalEntry>,
+    // Set once a dynamic update has changed the zone, after which its file
+    // no longer describes it.
+    dynamic: bool,
+}
+
+/// Sequence space comparison of SOA serials (RFC 1982 section 3.2).
+pub fn serial_lt(a: u32, b: u32) -> bool {
+    a != b && b.wrapping_sub(a) < (1 << 31)
+}
+
+pub fn soa_serial(soa: &Answer) -> Option<u32> {
+    match soa.rdata() {
+        Ok(RData::Soa { serial, .. }) => Some(serial),
+        _ => None,
+    }
+}
+
+pub fn same_record(a: &Answer, b: &Answer) -> bool {
+    a.qtype == b.qtype
+        && a.qclass == b.qclass
+        && a.ttl == b.ttl
+        && a.data == b.data
+        && names_equal(&a.name, &b.name)
+}
+
+/// Parses the records of an RFC 1035 master file.
+pub fn parse_records(text: &str) -> Result<Vec<Answer>> {
+    let mut origin: Vec<u8> = vec![];
+    let mut default_ttl = DEFAULT_TTL;
+    let mut last_owner: Option<Vec<u8>> = None;
+    let mut records = vec![];
+
+    for (line_no, indented, tokens) in tokenize(text)? {
+        let mut tokens = tokens.into_iter().peekable();
+        let first = match tokens.peek() {
+            Some(first) => first.clone(),
+            None => continue,
+        };
+
+        if first.eq_ignore_ascii_case("$ORIGIN") {
+            tokens.next();
+            let name = tokens.next().ok_or_else(|| anyhow!("$ORIGIN without a name on line {}", line_no))?;
+            origin = absolute_name(&name, &origin);
+            continue;
+        }
+        if first.eq_ignore_ascii_case("$TTL") {
+            tokens.next();
+            default_ttl = tokens
+                .next()
+                .and_then(|ttl| ttl.parse().ok())
+                .ok_or_else(|| anyhow!("$TTL without a valid value on line {}", line_no))?;
+            continue;
+        }
+
+        let owner = if indented {
+            last_owner
+                .clone()
+                .ok_or_else(|| anyhow!("Record on line {} has no owner", line_no))?
+        } else {
+            absolute_name(&tokens.next().unwrap(), &origin)
+        };
+        last_owner = Some(owner.clone());
+
+        let mut ttl = default_ttl;
+        let mut class = rtype::CLASS_IN;
+        let mut record_type = None;
+        for token in tokens.by_ref() {
+            if let Ok(value) = token.parse::<u32>() {
+                ttl = value;
+            } else if token.eq_ignore_ascii_case("IN") {
+                class = rtype::CLASS_IN;
+            } else {
+                record_type = Some(
+                    rtype::from_str(&token)
+                        .ok_or_else(|| anyhow!("Unknown record type {} on line {}", token, line_no))?,
+                );
+                break;
+            }
+        }
+        let record_type = record_type
+            .ok_or_else(|| anyhow!("Record on line {} has no type", line_no))?;
+        let fields: Vec<String> = tokens.collect();
+        let rdata = RData::parse(record_type, &fields, &origin).map_err(|e| anyhow!("{} on line {}", e, line_no))?;
+
+        records.push(
+            Answer::builder()
+                .name_bytes(owner)?
+                .qtype(record_type)?
+                .qclass(class)?
+                .ttl(ttl)?
+                .rdata(&rdata)?
+                .build(),
+        );
+    }
+
+    Ok(records)
+}
+
+impl Zone {
+    pub fn new(origin: Vec<u8>) -> Zone {
+        Zone {
+            origin,
+            records: BTreeMap::new(),
+            journal: vec![],
+            dynamic: false,
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Zone> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read zone file {}: {}", path, e))?;
+        Zone::parse(&text)
+    }
+
+    /// Parses an RFC 1035 master file. The zone origin is taken from the
+    /// owner of the SOA record.
+    pub fn parse(text: &str) -> Result<Zone> {
+        Zone::from_records(parse_records(text)?)
+    }
+
+    /// Builds a zone from a set of records, such as a parsed master file or
+    /// the contents of a zone transfer. The origin is the owner of the SOA.
+    pub fn from_records(records: Vec<Answer>) -> Result<Zone> {
+        let soa = records
+            .iter()
+            .find(|record| record.qtype == rtype::SOA)
+            .ok_or_else(|| anyhow!("Zone has no SOA record"))?;
+        let mut zone = Zone::new(soa.name.clone());
+        for record in records {
+            if !is_subdomain(&record.name, &zone.origin) {
+                return Err(anyhow!(
+                    "Record {} is outside of zone {}",
+                    name_to_string(&record.name),
+                    name_to_string(&zone.origin)
+                ));
+            }
+            zone.insert(record);
+        }
+        Ok(zone)
+    }
+
+    pub fn insert(&mut self, record: Answer) {
+        let set = self.records.entry(lowercase_name(&record.name)).or_default();
+        if !set.iter().any(|existing| existing.qtype == record.qtype && existing.data == record.data) {
+            set.push(record);
+        }
+    }
+
+    /// Removes the RRset of `rtype` at `name`, or every RRset there when
+    /// `rtype` is ANY. Whether any record was removed.
+    pub fn remove_rrset(&mut self, name: &[u8], rtype: u16) -> bool {
+        self.remove_where(name, |record| rtype == rtype::ANY || record.qtype == rtype)
+    }
+
+    /// Whether the record was there to remove.
+    pub fn remove_record(&mut self, record: &Answer) -> bool {
+        self.remove_where(&record.name, |existing| existing.qtype == record.qtype && existing.data == record.data)
+    }
+
+    fn remove_where(&mut self, name: &[u8], remove: impl Fn(&Answer) -> bool) -> bool {
+        let key = lowercase_name(name);
+        let Some(set) = self.records.get_mut(&key) else {
+            return false;
+        };
+        let count = set.len();
+        set.retain(|record| !remove(record));
+        let removed = set.len() != count;
+        if set.is_empty() {
+            self.records.remove(&key);
+        }
+        removed
+    }
+
+    pub fn is_dynamic(&self) -> bool {
+        self.dynamic
+    }
+
+    pub fn set_dynamic(&mut self) {
+        self.dynamic = true;
+    }
+
+    pub fn serial(&self) -> Option<u32> {
+        self.soa().and_then(soa_serial)
+    }
+
+    /// Replaces the zone's data with that of a newer version, journaling the
+    /// difference when the serial moves forward.
+    pub fn apply(&mut self, new: Zone) {
+        // Only records with the same owner can match, so each is compared
+        // against its own name's set rather than the whole zone.
+        let removed: Vec<Answer> = self.records()
+            .filter(|record| record.qtype != rtype::SOA)
+            .filter(|record| !new.records_at(&record.name).iter().any(|other| same_record(record, other)))
+            .cloned()
+            .collect();
+        let added: Vec<Answer> = new.records()
+            .filter(|record| record.qtype != rtype::SOA)
+            .filter(|record| !self.records_at(&record.name).iter().any(|other| same_record(record, other)))
+            .cloned()
+            .collect();
+
+        match (self.soa().cloned(), new.soa().cloned()) {
+            (Some(old_soa), Some(new_soa))
+                if serial_lt(soa_serial(&old_soa).unwrap_or(0), soa_serial(&new_soa).unwrap_or(0)) =>
+            {
+                self.journal.push(JournalEntry { old_soa, new_soa, removed, added });
+                if self.journal.len() > MAX_JOURNAL_ENTRIES {
+                    self.journal.remove(0);
+                }
+            }
+            _ if removed.is_empty() && added.is_empty() => {}
+            _ => {
+                println!(
+                    "Zone {} changed without a serial increase; dropping its journal",
+                    name_to_string(&self.origin)
+                );
+                self.journal.clear();
+            }
+        }
+        self.records = new.records;
+        self.dynamic |= new.dynamic;
+    }
+
+    /// The journal entries leading from version `serial` to the current one,
+    /// or `None` if the journal does not reach back that far.
+    pub fn changes_since(&self, serial: u32) -> Option<&[JournalEntry]> {
+        let start = self.journal
+            .iter()
+            .position(|entry| soa_serial(&entry.old_soa) == Some(serial))?;
+        Some(&self.journal[start..])
+    }
+
+    pub fn records(&self) -> impl Iterator<Item = &Answer> {
+        self.records.values().flatten()
+    }
+
+    pub fn records_at(&self, name: &[u8]) -> &[Answer] {
+        self.records
+            .get(&lowercase_name(name))
+            .map(|set| set.as_slice())
+            .unwrap_or(&[])
+    }
+
+    pub fn soa(&self) -> Option<&Answer> {
+        self.records_at(&self.origin)
+            .iter()
+            .find(|record| record.qtype == rtype::SOA)
+    }
+
+    /// Whether any record is owned by `name` or by a name below it. A name
+    /// with no records of its own but with descendants is an empty
+    /// non-terminal and exists for the purpose of NXDOMAIN.
+    pub fn name_exists(&self, name: &[u8]) -> bool {
+        let name = lowercase_name(name);
+        self.records
+            .keys()
+            .any(|owner| is_subdomain(owner, &name))
+    }
+
+    /// The closest encloser of a non-existent `name` (RFC 4592 section 3.3.1):
+    /// its longest existing ancestor within the zone.
+    pub fn closest_encloser(&self, name: &[u8]) -> Vec<u8> {
+        let mut cur = name.to_vec();
+        while let Some(parent) = parent_name(&cur) {
+            if !is_subdomain(&parent, &self.origin) {
+                break;
+            }
+            if self.name_exists(&parent) {
+                return parent;
+            }
+            cur = parent;
+        }
+        self.origin.clone()
+    }
+
+    /// Synthesizes records for a non-existent `name` from the wildcard at
+    /// its closest encloser, with the owner rewritten to `name`.
+    fn wildcard_records(&self, name: &[u8]) -> Option<Vec<Answer>> {
+        let mut source = vec![1, b'*'];
+        source.extend(self.closest_encloser(name));
+        let records = self.records_at(&source);
+        if records.is_empty() {
+            return None;
+        }
+
+        Some(
+            records
+                .iter()
+                .cloned()
+                .map(|mut record| {
+                    record.name = name.to_vec();
+                    record
+                })
+                .collect()
+        )
+    }
+
+    /// Names from the apex down to and including `name`.
+    fn path_from_apex(&self, name: &[u8]) -> Vec<Vec<u8>> {
+        let mut path = vec![];
+        let mut cur = Some(name.to_vec());
+        while let Some(owner) = cur {
+            if !is_subdomain(&owner, &self.origin) {
+                break;
+            }
+            cur = parent_name(&owner);
+            path.push(owner);
+        }
+        path.reverse();
+        path
+    }
+
+    /// The first delegation point at or above `name`, if the name sits
+    /// below a zone cut rather than in authoritative data.
+    pub fn zone_cut(&self, name: &[u8]) -> Option<Vec<u8>> {
+        self.path_from_apex(name)
+            .into_iter()
+            .skip(1)
+            .find(|owner| self.records_at(owner).iter().any(|record| record.qtype == rtype::NS))
+    }
+
+    fn referral(&self, cut: &[u8]) -> Lookup {
+        let ns: Vec<Answer> = self.records_at(cut)
+            .iter()
+            .filter(|record| record.qtype == rtype::NS)
+            .cloned()
+            .collect();
+        let glue = ns
+            .iter()
+            .filter_map(|record| match record.rdata() {
+                Ok(RData::Ns(target)) if is_subdomain(&target, &self.origin) => Some(target),
+                _ => None,
+            })
+            .flat_map(|target| self.records_at(&target).to_vec())
+            .filter(|record| record.qtype == rtype::A || record.qtype == rtype::AAAA)
+            .collect();
+        Lookup::Referral { ns, glue }
+    }
+
+    fn synthesize_cname(qname: &[u8], dname: &Answer) -> Lookup {
+        let target = match dname.rdata() {
+            Ok(RData::Dname(target)) => target,
+            _ => return Lookup::NoData,
+        };
+        let mut rewritten = qname[..qname.len() - dname.name.len()].to_vec();
+        rewritten.extend(target);
+        if rewritten.len() + 1 > MAX_NAME_LEN {
+            return Lookup::NameTooLong(dname.clone());
+        }
+
+        let cname = Answer::builder()
+            .name_bytes(qname.to_vec())
+            .unwrap()
+            .qtype(rtype::CNAME)
+            .unwrap()
+            .qclass(dname.qclass)
+            .unwrap()
+            .ttl(dname.ttl)
+            .unwrap()
+            .rdata(&RData::Cname(rewritten))
+            .unwrap()
+            .build();
+        Lookup::Dname(dname.clone(), cname)
+    }
+
+    pub fn lookup(&self, qname: &[u8], qtype: u16) -> Lookup {
+        // Walking down from the apex, the first zone cut or DNAME (owned by
+        // a proper ancestor) takes the name out of this zone's data. The DS
+        // RRset at a cut belongs to the parent side (RFC 4035 section 2.4).
+        for owner in self.path_from_apex(qname) {
+            let records = self.records_at(&owner);
+            let is_apex = owner.len() == self.origin.len();
+            let parent_side = qtype == rtype::DS && owner.len() == qname.len();
+            if !is_apex && !parent_side && records.iter().any(|record| record.qtype == rtype::NS) {
+                return self.referral(&owner);
+            }
+            if owner.len() < qname.len() {
+                if let Some(dname) = records.iter().find(|record| record.qtype == rtype::DNAME) {
+                    return Zone::synthesize_cname(qname, dname);
+                }
+            }
+        }
+
+        let records = if !self.records_at(qname).is_empty() {
+            self.records_at(qname).to_vec()
+        } else if self.name_exists(qname) {
+            return Lookup::NoData;
+        } else {
+            match self.wildcard_records(qname) {
+                Some(records) => records,
+                None => return Lookup::NxDomain,
+            }
+        };
+
+        let matching: Vec<Answer> = records
+            .iter()
+            .filter(|record| qtype == rtype::ANY || record.qtype == qtype)
+            .cloned()
+            .collect();
+        if !matching.is_empty() {
+            return Lookup::Records(matching);
+        }
+
+        match records.iter().find(|record| record.qtype == rtype::CNAME) {
+            Some(cname) => Lookup::Cname(cname.clone()),
+            None => Lookup::NoData,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ZoneStore {
+    zones: Vec<Zone>,
+    // Local overrides answered ahead of any zone.
+    hosts: HostsTable,
+    // Names answered with the block action instead of being resolved.
+    blocklist: Blocklist,
+    // Policy zones applied to forwarded answers.
+    policy: ResponsePolicy,
+}
+
+impl ZoneStore {
+    pub fn new() -> ZoneStore {
+        ZoneStore::default()
+    }
+
+    /// Adds a zone, replacing any existing zone with the same origin.
+    pub fn insert(&mut self, zone: Zone) {
+        self.zones.retain(|existing| !names_equal(&existing.origin, &zone.origin));
+        self.zones.push(zone);
+    }
+
+    /// Installs a new version of a zone, keeping the journal of the zone it
+    /// replaces.
+    pub fn update(&mut self, zone: Zone) {
+        match self.zones.iter_mut().find(|existing| names_equal(&existing.origin, &zone.origin)) {
+            Some(existing) => existing.apply(zone),
+            None => self.zones.push(zone),
+        }
+    }
+
+    pub fn remove(&mut self, origin: &[u8]) {
+        self.zones.retain(|zone| !names_equal(&zone.origin, origin));
+    }
+
+    pub fn zones(&self) -> &[Zone] {
+        &self.zones
+    }
+
+    pub fn hosts(&self) -> &HostsTable {
+        &self.hosts
+    }
+
+    pub fn set_hosts(&mut self, hosts: HostsTable) {
+        self.hosts = hosts;
+    }
+
+    pub fn blocklist(&self) -> &Blocklist {
+        &self.blocklist
+    }
+
+    pub fn set_blocklist(&mut self, blocklist: Blocklist) {
+        self.blocklist = blocklist;
+    }
+
+    pub fn policy(&self) -> &ResponsePolicy {
+        &self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: ResponsePolicy) {
+        self.policy = policy;
+    }
+
+    /// Finds the most specific zone containing `name`.
+    pub fn find_zone(&self, name: &[u8]) -> Option<&Zone> {
+        self.zones
+            .iter()
+            .filter(|zone| is_subdomain(name, &zone.origin))
+            .max_by_key(|zone| zone.origin.len())
+    }
+
+    pub fn find_zone_mut(&mut self, name: &[u8]) -> Option<&mut Zone> {
+        self.zones
+            .iter_mut()
+            .filter(|zone| is_subdomain(name, &zone.origin))
+            .max_by_key(|zone| zone.origin.len())
+    }
+}
+
+/// Splits master file text into logical lines, joining parenthesised
+/// continuations and dropping comments. Each line records the 1-based
+/// source line it starts on and whether it began with whitespace, which
+/// means the owner is inherited from the last record.
+fn tokenize(text: &str) -> Result<Vec<(usize, bool, Vec<String>)>> {
+    let mut lines = vec![];
+    let mut tokens: Vec<String> = vec![];
+    let mut start = 0;
+    let mut indented = false;
+    let mut depth = 0;
+
+    for (line_no, line) in (1..).zip(text.lines()) {
+        if depth == 0 {
+            start = line_no;
+            indented = line.starts_with(' ') || line.starts_with('\t');
+        }
+        let mut chars = line.chars().peekable();
+        let mut token = String::new();
+        let mut quoted = false;
+        while let Some(c) = chars.next() {
+            if quoted {
+                match c {
+                    '"' => {
+                        quoted = false;
+                        tokens.push(std::mem::take(&mut token));
+                    }
+                    '\\' => {
+                        if let Some(escaped) = chars.next() {
+                            token.push(escaped);
+                        }
+                    }
+                    _ => token.push(c),
+                }
+                continue;
+            }
+            match c {
+                ';' => break,
+                '"' => quoted = true,
+                '(' => depth += 1,
+                ')' => {
+                    if depth == 0 {
+                        return Err(anyhow!("Unbalanced ')' on line {}", line_no));
+                    }
+                    depth -= 1;
+                }
+                c if c.is_whitespace() => {}
+                _ => {
+                    token.push(c);
+                    let ends_token = match chars.peek() {
+                        Some(next) => next.is_whitespace() || "();\"".contains(*next),
+                        None => true,
+                    };
+                    if ends_token {
+                        tokens.push(std::mem::take(&mut token));
+                    }
+                }
+            }
+        }
+        if quoted {
+            return Err(anyhow!("Unterminated string on line {}", line_no));
+        }
+        if depth == 0 && !tokens.is_empty() {
+            lines.push((start, indented, std::mem::take(&mut tokens)));
+        }
+    }
+    if depth != 0 {
+        return Err(anyhow!("Unbalanced '(' opened on line {}", start));
+    }
+
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ZONE: &str = "$ORIGIN example.com.
+@ SOA ns admin 1 3600 600 86400 300
+@ NS ns
+ns A 10.0.0.1
+old DNAME new.example.net.
+www.old A 10.0.0.9
+";
+
+    fn lookup(zones: &ZoneStore, name: &str, qtype: u16) -> Lookup {
+        let name = encode_name(name);
+        zones.find_zone(&name).unwrap().lookup(&name, qtype)
+    }
+
+    fn zones(text: &str) -> ZoneStore {
+        let mut zones = ZoneStore::new();
+        zones.insert(Zone::parse(text).unwrap());
+        zones
+    }
+
+    #[test]
+    fn dname_rewrites_names_below_it() {
+        let zones = zones(ZONE);
+        match lookup(&zones, "a.b.old.example.com", rtype::A) {
+            Lookup::Dname(dname, cname) => {
+                assert!(names_equal(&dname.name, &encode_name("old.example.com")));
+                assert!(names_equal(&cname.name, &encode_name("a.b.old.example.com")));
+                assert_eq!(cname.ttl, dname.ttl);
+                assert_eq!(cname.rdata().unwrap(), RData::Cname(encode_name("a.b.new.example.net")));
+            }
+            other => panic!("expected a DNAME, got {:?}", other),
+        }
+        // Data below a DNAME is occluded by it.
+        assert!(matches!(lookup(&zones, "www.old.example.com", rtype::A), Lookup::Dname(..)));
+        // The DNAME owner itself is answered normally.
+        assert!(matches!(lookup(&zones, "old.example.com", rtype::DNAME), Lookup::Records(_)));
+        assert!(matches!(lookup(&zones, "old.example.com", rtype::A), Lookup::NoData));
+    }
+
+    #[test]
+    fn dname_substitution_longer_than_a_name() {
+        let target = ["a".repeat(63), "b".repeat(63), "c".repeat(63), "d".repeat(50)].join(".");
+        let text = format!("{}long DNAME {}.\n", ZONE, target);
+        let zones = zones(&text);
+        let qname = format!("{}.long.example.com", "x".repeat(20));
+        assert!(matches!(lookup(&zones, &qname, rtype::A), Lookup::NameTooLong(_)));
+        assert!(matches!(lookup(&zones, "x.long.example.com", rtype::A), Lookup::Dname(..)));
+    }
+
+    #[test]
+    fn wildcards_answer_for_missing_names() {
+        let text = format!("{}*.wild A 10.1.1.1\n*.wild MX 10 mail\nsub.wild TXT \"here\"\n", ZONE);
+        let zones = zones(&text);
+        match lookup(&zones, "a.b.wild.example.com", rtype::A) {
+            Lookup::Records(records) => {
+                assert_eq!(records.len(), 1);
+                assert!(names_equal(&records[0].name, &encode_name("a.b.wild.example.com")));
+            }
+            other => panic!("expected records, got {:?}", other),
+        }
+        assert!(matches!(lookup(&zones, "x.wild.example.com", rtype::AAAA), Lookup::NoData));
+        // Names that exist, and names below them, are not covered by the
+        // wildcard (RFC 4592 section 3.3.1).
+        assert!(matches!(lookup(&zones, "sub.wild.example.com", rtype::A), Lookup::NoData));
+        assert!(matches!(lookup(&zones, "x.sub.wild.example.com", rtype::A), Lookup::NxDomain));
+        assert!(matches!(lookup(&zones, "wild.example.com", rtype::A), Lookup::NoData));
+        assert!(matches!(lookup(&zones, "nothing.example.com", rtype::A), Lookup::NxDomain));
+    }
+
+    #[test]
+    fn delegations_refer_with_glue() {
+        let text = format!(
+            "{}sub NS ns1.sub\nsub NS ns.example.org.\nsub DS 1 13 2 abcd\nns1.sub A 10.2.0.1\nwww.sub A 10.2.0.2\n",
+            ZONE
+        );
+        let zones = zones(&text);
+        for name in ["sub.example.com", "www.sub.example.com", "a.b.sub.example.com"] {
+            match lookup(&zones, name, rtype::A) {
+                Lookup::Referral { ns, glue } => {
+                    assert_eq!(ns.len(), 2);
+                    // Only the in-zone nameserver has glue.
+                    assert_eq!(glue.len(), 1);
+                    assert!(names_equal(&glue[0].name, &encode_name("ns1.sub.example.com")));
+                }
+                other => panic!("expected a referral for {}, got {:?}", name, other),
+            }
+        }
+        // The DS RRset is authoritative data of the parent.
+        assert!(matches!(lookup(&zones, "sub.example.com", rtype::DS), Lookup::Records(_)));
+        // NS records at the apex are not a delegation.
+        assert!(matches!(lookup(&zones, "example.com", rtype::NS), Lookup::Records(_)));
+        assert_eq!(
+            zones.find_zone(&encode_name("x.sub.example.com")).unwrap().zone_cut(&encode_name("x.sub.example.com")),
+            Some(encode_name("sub.example.com"))
+        );
+    }
+
+    #[test]
+    fn errors_name_their_source_line() {
+        let error = |text: &str| parse_records(text).unwrap_err().to_string();
+
+        let text = "$ORIGIN example.com.\n@ SOA ns admin 1 3600 600 86400 300\n\nwww A not-an-address\n";
+        assert!(error(text).ends_with("on line 4"), "{}", error(text));
+        assert!(error("$ORIGIN example.com.\nwww BOGUS 1\n").ends_with("on line 2"));
+        assert!(error("$TTL forever\n").ends_with("on line 1"));
+        assert!(error("$ORIGIN example.com.\n  A 10.0.0.1\n").contains("on line 2"));
+
+        // A record continued over several lines is reported where it starts.
+        let text = "$ORIGIN example.com.\n@ SOA ns admin (\n  1 3600\n  600 86400 300\n\nwww A 10.0.0.1 )\n)\n";
+        assert_eq!(error(text), "Unbalanced ')' on line 7");
+        assert_eq!(error("$ORIGIN example.com.\n\n@ SOA ns admin (\n  1 3600\n"), "Unbalanced '(' opened on line 3");
+        assert_eq!(error("$ORIGIN example.com.\nwww TXT \"open\n"), "Unterminated string on line 2");
+    }
+}